@@ -0,0 +1,108 @@
+//! Learned interception bypass CLI commands
+//!
+//! These talk to a running proxy's `/admin/bypass` endpoint, so they work for
+//! both the in-memory and the Redis certificate cache backends. The proxy
+//! only answers admins (`admin.users` or `admin.allow_loopback`).
+
+use crate::tls::BypassEntry;
+use anyhow::{anyhow, Result};
+use base64::{Engine as _, engine::general_purpose};
+use clap::{Args, Subcommand};
+use hyper::{Body, Client, Method, Request};
+use serde_json::Value;
+use tracing::info;
+
+#[derive(Debug, Subcommand)]
+pub enum BypassCommand {
+    /// List hosts the proxy has learned to tunnel instead of intercept
+    List(BypassArgs),
+
+    /// Forget learned bypass entries
+    Clear(ClearBypassArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct BypassArgs {
+    /// HTTP address of the running proxy
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub proxy_url: String,
+
+    /// Proxy credentials as user:password (when proxy auth is enabled)
+    #[arg(long)]
+    pub proxy_user: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ClearBypassArgs {
+    #[command(flatten)]
+    pub proxy: BypassArgs,
+
+    /// Only clear this host (default: all learned entries)
+    #[arg(long)]
+    pub host: Option<String>,
+}
+
+impl BypassCommand {
+    pub async fn execute(&self) -> Result<()> {
+        match self {
+            BypassCommand::List(args) => list_bypass(args).await,
+            BypassCommand::Clear(args) => clear_bypass(args).await,
+        }
+    }
+}
+
+/// List learned bypass entries
+async fn list_bypass(args: &BypassArgs) -> Result<()> {
+    let body = admin_request(args, Method::GET, "/admin/bypass").await?;
+    let entries: Vec<BypassEntry> = serde_json::from_value(body["entries"].clone())?;
+
+    if entries.is_empty() {
+        info!("📭 No learned bypass entries");
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    info!("🔀 {} learned bypass entr{}:", entries.len(), if entries.len() == 1 { "y" } else { "ies" });
+    for entry in entries {
+        let remaining = entry.expires_at.saturating_sub(now);
+        info!("   {} (expires in {}h {}m) - {}", entry.host, remaining / 3600, (remaining % 3600) / 60, entry.reason);
+    }
+
+    Ok(())
+}
+
+/// Clear one or all learned bypass entries
+async fn clear_bypass(args: &ClearBypassArgs) -> Result<()> {
+    let path = match &args.host {
+        Some(host) => format!("/admin/bypass?host={}", form_urlencoded::byte_serialize(host.as_bytes()).collect::<String>()),
+        None => "/admin/bypass".to_string(),
+    };
+
+    let body = admin_request(&args.proxy, Method::DELETE, &path).await?;
+    info!("🧹 Removed {} learned bypass entr{}", body["removed"],
+          if body["removed"] == 1 { "y" } else { "ies" });
+
+    Ok(())
+}
+
+async fn admin_request(args: &BypassArgs, method: Method, path: &str) -> Result<Value> {
+    let url = format!("{}{}", args.proxy_url.trim_end_matches('/'), path);
+    let mut request = Request::builder().method(method).uri(&url);
+    if let Some(credentials) = &args.proxy_user {
+        let encoded = general_purpose::STANDARD.encode(credentials);
+        request = request.header("proxy-authorization", format!("Basic {}", encoded));
+    }
+
+    let response = Client::new()
+        .request(request.body(Body::empty())?)
+        .await
+        .map_err(|e| anyhow!("Failed to reach proxy at {}: {}", args.proxy_url, e))?;
+
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(anyhow!("{} returned {}: {}", url, status, String::from_utf8_lossy(&bytes)));
+    }
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
@@ -0,0 +1,586 @@
+//! CA lifecycle CLI commands (`cert ca ...`)
+
+use crate::config::settings::LeafKeyAlgorithm;
+use crate::tls::disk_cache::{pem_block, write_atomic};
+use crate::tls::{
+    ca_key_pair, encrypt_private_key_pem, generate_intermediate_ca, generate_root_ca, is_encrypted_key_file,
+    load_cert_from_files_with_passphrase, load_pem_chain, save_cert_to_files, CaOptions, CaStore,
+    CertificateData, LeafIssuer, LeafProfile,
+};
+use anyhow::{anyhow, Result};
+use clap::{Args, Subcommand};
+use p12_keystore::{Certificate as P12Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+use std::fs;
+use tracing::info;
+
+#[derive(Debug, Subcommand)]
+pub enum CaCommand {
+    /// Create a new root CA
+    Init(InitCaArgs),
+
+    /// Create an intermediate CA for the proxy to sign with, so the root key can stay offline
+    Intermediate(IntermediateCaArgs),
+
+    /// Issue a leaf certificate signed by the root CA
+    Issue(IssueCaArgs),
+
+    /// Replace the root CA, keeping the old one trusted for an overlap window
+    Rotate(RotateCaArgs),
+
+    /// Export the root CA for installation on client machines
+    Export(ExportCaArgs),
+
+    /// Encrypt (or change the passphrase of) the CA private key
+    EncryptKey(EncryptKeyArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct CaPathArgs {
+    /// Root CA certificate path
+    #[arg(long, default_value = "ca-certs/rootCA.crt")]
+    pub ca_cert_path: String,
+
+    /// Root CA private key path
+    #[arg(long, default_value = "ca-certs/rootCA.key")]
+    pub ca_key_path: String,
+
+    /// File holding the CA key passphrase (else TLS_CA_KEY_PASSPHRASE, else a prompt)
+    #[arg(long)]
+    pub passphrase_file: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct NewKeyArgs {
+    /// Encrypt the new private key with a passphrase
+    #[arg(long, default_value = "false")]
+    pub encrypt_key: bool,
+
+    /// File holding the passphrase for the new key (else a prompt)
+    #[arg(long)]
+    pub new_passphrase_file: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InitCaArgs {
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// Organization name for the CA
+    #[arg(long, default_value = "Rust Forward Proxy CA")]
+    pub organization: String,
+
+    /// Common name for the CA
+    #[arg(long, default_value = "Rust Proxy Root CA")]
+    pub common_name: String,
+
+    /// CA validity period in days
+    #[arg(long, default_value = "3650")]
+    pub validity_days: u32,
+
+    /// CA key algorithm (ecdsa-p256, rsa2048, ed25519)
+    #[arg(long, default_value = "ecdsa-p256", value_parser = parse_key_algorithm)]
+    pub key_algorithm: LeafKeyAlgorithm,
+
+    /// Maximum number of intermediate CAs below the root (unlimited if omitted)
+    #[arg(long)]
+    pub path_len: Option<u8>,
+
+    /// Only allow certificates for this DNS subtree (repeatable)
+    #[arg(long = "permit-dns")]
+    pub permit_dns: Vec<String>,
+
+    /// Never allow certificates for this DNS subtree (repeatable)
+    #[arg(long = "exclude-dns")]
+    pub exclude_dns: Vec<String>,
+
+    #[command(flatten)]
+    pub new_key: NewKeyArgs,
+
+    /// Force overwrite an existing CA
+    #[arg(long, default_value = "false")]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct IntermediateCaArgs {
+    /// The issuing CA (usually the root)
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// Organization name for the intermediate
+    #[arg(long, default_value = "Rust Forward Proxy CA")]
+    pub organization: String,
+
+    /// Common name for the intermediate
+    #[arg(long, default_value = "Rust Proxy Intermediate CA")]
+    pub common_name: String,
+
+    /// Intermediate validity period in days
+    #[arg(long, default_value = "1825")]
+    pub validity_days: u32,
+
+    /// Intermediate key algorithm (ecdsa-p256, rsa2048, ed25519)
+    #[arg(long, default_value = "ecdsa-p256", value_parser = parse_key_algorithm)]
+    pub key_algorithm: LeafKeyAlgorithm,
+
+    /// Maximum number of further intermediates below this one
+    #[arg(long, default_value = "0")]
+    pub path_len: u8,
+
+    /// Only allow certificates for this DNS subtree (repeatable)
+    #[arg(long = "permit-dns")]
+    pub permit_dns: Vec<String>,
+
+    /// Never allow certificates for this DNS subtree (repeatable)
+    #[arg(long = "exclude-dns")]
+    pub exclude_dns: Vec<String>,
+
+    /// Output path for the intermediate certificate (tls.ca_cert_path)
+    #[arg(long, default_value = "ca-certs/intermediateCA.crt")]
+    pub cert_path: String,
+
+    /// Output path for the intermediate private key (tls.ca_key_path)
+    #[arg(long, default_value = "ca-certs/intermediateCA.key")]
+    pub key_path: String,
+
+    /// Output path for the chain above the intermediate (tls.ca_chain_path)
+    #[arg(long, default_value = "ca-certs/chain.pem")]
+    pub chain_path: String,
+
+    /// Chain file of the issuing CA, when it is itself an intermediate
+    #[arg(long)]
+    pub issuer_chain_path: Option<String>,
+
+    #[command(flatten)]
+    pub new_key: NewKeyArgs,
+
+    /// Force overwrite an existing intermediate
+    #[arg(long, default_value = "false")]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct IssueCaArgs {
+    /// Hostname or IP address the certificate is for
+    pub domain: String,
+
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// Additional subject alternative names (repeatable)
+    #[arg(long)]
+    pub san: Vec<String>,
+
+    /// Certificate validity period in days
+    #[arg(long, default_value = "365")]
+    pub validity_days: u32,
+
+    /// Leaf key algorithm (ecdsa-p256, rsa2048, ed25519)
+    #[arg(long, default_value = "ecdsa-p256", value_parser = parse_key_algorithm)]
+    pub key_algorithm: LeafKeyAlgorithm,
+
+    /// Output path for the certificate (default: certs/<domain>.crt)
+    #[arg(long)]
+    pub cert_path: Option<String>,
+
+    /// Output path for the private key (default: certs/<domain>.key)
+    #[arg(long)]
+    pub key_path: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RotateCaArgs {
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// Days the previous root stays in the trust bundle
+    #[arg(long, default_value = "30")]
+    pub overlap_days: u32,
+
+    /// Validity of the new root in days (default: same as the current one)
+    #[arg(long)]
+    pub validity_days: Option<u32>,
+
+    /// Key algorithm of the new root (default: same as the current one)
+    #[arg(long, value_parser = parse_key_algorithm)]
+    pub key_algorithm: Option<LeafKeyAlgorithm>,
+
+    /// The new key is encrypted like the current one unless these say otherwise
+    #[command(flatten)]
+    pub new_key: NewKeyArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportCaArgs {
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// Output format (der, pem, p12)
+    #[arg(long, default_value = "pem")]
+    pub format: String,
+
+    /// Output file path
+    #[arg(long)]
+    pub output: String,
+
+    /// PKCS#12 password (required with --include-key)
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// Include the CA private key in the PKCS#12 file, to move the CA to another proxy
+    #[arg(long, default_value = "false")]
+    pub include_key: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct EncryptKeyArgs {
+    #[command(flatten)]
+    pub paths: CaPathArgs,
+
+    /// File holding the new passphrase (else a prompt)
+    #[arg(long)]
+    pub new_passphrase_file: Option<String>,
+}
+
+impl CaCommand {
+    pub async fn execute(&self) -> Result<()> {
+        match self {
+            CaCommand::Init(args) => init_ca(args).await,
+            CaCommand::Intermediate(args) => create_intermediate(args).await,
+            CaCommand::Issue(args) => issue_certificate(args).await,
+            CaCommand::Rotate(args) => rotate_ca(args).await,
+            CaCommand::Export(args) => export_ca(args).await,
+            CaCommand::EncryptKey(args) => encrypt_key(args).await,
+        }
+    }
+}
+
+impl CaPathArgs {
+    fn store(&self) -> CaStore {
+        CaStore::new(&self.ca_cert_path, &self.ca_key_path)
+    }
+
+    /// Passphrase of the CA key, if it is encrypted
+    fn passphrase(&self) -> Result<Option<String>> {
+        if !is_encrypted_key_file(&self.ca_key_path)? {
+            return Ok(None);
+        }
+        if let Some(path) = &self.passphrase_file {
+            return read_passphrase_file(path).map(Some);
+        }
+        if let Ok(passphrase) = std::env::var("TLS_CA_KEY_PASSPHRASE") {
+            return Ok(Some(passphrase));
+        }
+        prompt_passphrase(&format!("Passphrase for {}: ", self.ca_key_path), false).map(Some)
+    }
+
+    fn load(&self) -> Result<CertificateData> {
+        let passphrase = self.passphrase()?;
+        load_cert_from_files_with_passphrase(&self.ca_cert_path, &self.ca_key_path, passphrase.as_deref())
+    }
+}
+
+impl NewKeyArgs {
+    /// Passphrase for a new key; `inherited` is used when nothing else asks for one
+    fn passphrase(&self, key_path: &str, inherited: Option<String>) -> Result<Option<String>> {
+        if let Some(path) = &self.new_passphrase_file {
+            return read_passphrase_file(path).map(Some);
+        }
+        if self.encrypt_key {
+            return prompt_passphrase(&format!("New passphrase for {}: ", key_path), true).map(Some);
+        }
+        Ok(inherited)
+    }
+}
+
+fn read_passphrase_file(path: &str) -> Result<String> {
+    let contents = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read passphrase file {}: {}", path, e))?;
+    Ok(contents.lines().next().unwrap_or_default().to_string())
+}
+
+/// Read a passphrase from the terminal without echoing it
+fn prompt_passphrase(prompt: &str, confirm: bool) -> Result<String> {
+    let read = |prompt: &str| {
+        rpassword::prompt_password(prompt).map_err(|_| {
+            anyhow!("No terminal to ask for a passphrase; use --passphrase-file or TLS_CA_KEY_PASSPHRASE")
+        })
+    };
+
+    let passphrase = read(prompt)?;
+    if confirm && read("Repeat passphrase: ")? != passphrase {
+        return Err(anyhow!("Passphrases don't match"));
+    }
+    Ok(passphrase)
+}
+
+fn parse_key_algorithm(value: &str) -> Result<LeafKeyAlgorithm, String> {
+    match value.to_lowercase().replace('_', "-").as_str() {
+        "ecdsa-p256" | "ecdsa" | "p256" => Ok(LeafKeyAlgorithm::EcdsaP256),
+        "rsa2048" | "rsa" => Ok(LeafKeyAlgorithm::Rsa2048),
+        "ed25519" => Ok(LeafKeyAlgorithm::Ed25519),
+        _ => Err(format!("unknown key algorithm '{}' (expected ecdsa-p256, rsa2048 or ed25519)", value)),
+    }
+}
+
+/// Create a new root CA
+async fn init_ca(args: &InitCaArgs) -> Result<()> {
+    let store = args.paths.store();
+    if !args.force && store.exists() {
+        return Err(anyhow!(
+            "Root CA already exists. Use --force to overwrite or 'cert ca rotate' to replace it.\n  Certificate: {}\n  Key: {}",
+            args.paths.ca_cert_path, args.paths.ca_key_path
+        ));
+    }
+
+    let options = CaOptions {
+        organization: args.organization.clone(),
+        common_name: args.common_name.clone(),
+        key_algorithm: args.key_algorithm,
+        validity_days: args.validity_days,
+        path_len: args.path_len,
+        permitted_dns: args.permit_dns.clone(),
+        excluded_dns: args.exclude_dns.clone(),
+    };
+
+    info!("🔧 Creating root CA");
+    info!("   Common Name: {}", options.common_name);
+    info!("   Key: {:?}, validity: {} days", options.key_algorithm, options.validity_days);
+    if let Some(path_len) = options.path_len {
+        info!("   Path length: {}", path_len);
+    }
+    if !options.permitted_dns.is_empty() {
+        info!("   Permitted DNS: {}", options.permitted_dns.join(", "));
+    }
+    if !options.excluded_dns.is_empty() {
+        info!("   Excluded DNS: {}", options.excluded_dns.join(", "));
+    }
+
+    let passphrase = args.new_key.passphrase(&args.paths.ca_key_path, None)?;
+    let ca = generate_root_ca(&options)?;
+    store.save(&ca, passphrase.as_deref())?;
+    store.write_bundle()?;
+
+    info!("✅ Root CA created");
+    info!("📋 Install {} on client machines ('cert ca export' for other formats)", args.paths.ca_cert_path);
+    Ok(())
+}
+
+/// Create an intermediate CA and the chain file the proxy serves with it
+async fn create_intermediate(args: &IntermediateCaArgs) -> Result<()> {
+    let store = CaStore::new(&args.cert_path, &args.key_path);
+    if !args.force && store.exists() {
+        return Err(anyhow!(
+            "Intermediate CA already exists. Use --force to overwrite.\n  Certificate: {}\n  Key: {}",
+            args.cert_path, args.key_path
+        ));
+    }
+
+    let issuer = args.paths.load()?;
+    let options = CaOptions {
+        organization: args.organization.clone(),
+        common_name: args.common_name.clone(),
+        key_algorithm: args.key_algorithm,
+        validity_days: args.validity_days,
+        path_len: Some(args.path_len),
+        permitted_dns: args.permit_dns.clone(),
+        excluded_dns: args.exclude_dns.clone(),
+    };
+
+    info!("🔧 Creating intermediate CA '{}'", options.common_name);
+    let intermediate = generate_intermediate_ca(&issuer, &options)?;
+    let passphrase = args.new_key.passphrase(&args.key_path, None)?;
+    store.save(&intermediate, passphrase.as_deref())?;
+
+    // Everything above the intermediate: its issuer, then the issuer's own chain
+    let mut chain = pem_block("CERTIFICATE", &issuer.cert().0);
+    if let Some(issuer_chain_path) = &args.issuer_chain_path {
+        for cert in load_pem_chain(issuer_chain_path)? {
+            chain.push_str(&pem_block("CERTIFICATE", &cert.0));
+        }
+    }
+    fs::write(&args.chain_path, chain).map_err(|e| anyhow!("Failed to write {}: {}", args.chain_path, e))?;
+
+    info!("✅ Intermediate CA created");
+    info!("📋 Point the proxy at it and move {} offline:", args.paths.ca_key_path);
+    info!("   tls.ca_cert_path: {}", args.cert_path);
+    info!("   tls.ca_key_path: {}", args.key_path);
+    info!("   tls.ca_chain_path: {}", args.chain_path);
+    Ok(())
+}
+
+/// Issue a leaf certificate signed by the root CA
+async fn issue_certificate(args: &IssueCaArgs) -> Result<()> {
+    let file_stem = args.domain.replace('*', "wildcard");
+    let cert_path = args.cert_path.clone().unwrap_or_else(|| format!("certs/{}.crt", file_stem));
+    let key_path = args.key_path.clone().unwrap_or_else(|| format!("certs/{}.key", file_stem));
+
+    let ca = args.paths.load()?;
+    let issuer = LeafIssuer::new(&ca, args.key_algorithm, args.validity_days)?;
+
+    let mut profile = LeafProfile::for_host(&args.domain);
+    for san in &args.san {
+        profile.ensure_covers(san);
+    }
+    let leaf = issuer.issue(&profile)?;
+    save_cert_to_files(&leaf, &cert_path, &key_path)?;
+
+    info!("✅ Issued certificate for {} ({} days)", args.domain, args.validity_days);
+    Ok(())
+}
+
+/// Replace the root CA, keeping the previous one in the trust bundle
+async fn rotate_ca(args: &RotateCaArgs) -> Result<()> {
+    let store = args.paths.store();
+    // Loading the current key proves we hold it (and its passphrase)
+    let current_passphrase = args.paths.passphrase()?;
+    let current = load_cert_from_files_with_passphrase(
+        &args.paths.ca_cert_path, &args.paths.ca_key_path, current_passphrase.as_deref(),
+    )?;
+
+    let mut options = CaOptions::from_cert(&current.cert())?;
+    if let Some(validity_days) = args.validity_days {
+        options.validity_days = validity_days;
+    }
+    if let Some(key_algorithm) = args.key_algorithm {
+        options.key_algorithm = key_algorithm;
+    }
+
+    info!("🔄 Rotating root CA '{}'", options.common_name);
+    let passphrase = args.new_key.passphrase(&args.paths.ca_key_path, current_passphrase)?;
+    store.rotate(&options, args.overlap_days, passphrase.as_deref())?;
+
+    info!("✅ Root CA rotated; new leaf certificates are signed by the new root");
+    info!("📋 Distribute {} to clients before the {}-day overlap ends", store.bundle_path().display(), args.overlap_days);
+    info!("   Restart the proxy and clear the certificate cache to stop serving certificates from the old root");
+    Ok(())
+}
+
+/// Export the root CA for client machines
+async fn export_ca(args: &ExportCaArgs) -> Result<()> {
+    let store = args.paths.store();
+    let bundle = store.bundle()?;
+
+    let bytes = match args.format.to_lowercase().as_str() {
+        "der" => bundle[0].0.clone(),
+        "pem" => bundle.iter().map(|cert| pem_block("CERTIFICATE", &cert.0)).collect::<String>().into_bytes(),
+        "p12" | "pfx" | "pkcs12" => {
+            let mut keystore = KeyStore::new();
+            for (i, cert) in bundle.iter().enumerate() {
+                let cert = P12Certificate::from_der(&cert.0)
+                    .map_err(|e| anyhow!("Failed to encode CA certificate: {}", e))?;
+                let alias = match i {
+                    0 => "root-ca".to_string(),
+                    _ => format!("retiring-root-ca-{}", i),
+                };
+                keystore.add_entry(&alias, KeyStoreEntry::Certificate(cert));
+            }
+
+            if args.include_key {
+                if args.password.as_deref().unwrap_or_default().is_empty() {
+                    return Err(anyhow!("--include-key needs a non-empty --password"));
+                }
+                let ca = args.paths.load()?;
+                let key = ca_key_pair(&ca.key().0)?.serialize_der();
+                let cert = P12Certificate::from_der(&ca.cert().0)
+                    .map_err(|e| anyhow!("Failed to encode CA certificate: {}", e))?;
+                let chain = PrivateKeyChain::new(key, rand::random::<[u8; 20]>(), vec![cert]);
+                keystore.add_entry("root-ca-key", KeyStoreEntry::PrivateKeyChain(chain));
+            }
+
+            keystore
+                .writer(args.password.as_deref().unwrap_or_default())
+                .write()
+                .map_err(|e| anyhow!("Failed to write PKCS#12: {}", e))?
+        }
+        other => return Err(anyhow!("Unsupported export format '{}' (expected der, pem or p12)", other)),
+    };
+
+    if let Some(parent) = std::path::Path::new(&args.output).parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&args.output, bytes).map_err(|e| anyhow!("Failed to write {}: {}", args.output, e))?;
+
+    let count = if args.format.eq_ignore_ascii_case("der") { 1 } else { bundle.len() };
+    info!("✅ Exported {} root CA certificate(s) to {} ({})", count, args.output, args.format);
+    Ok(())
+}
+
+/// Encrypt the CA key in place
+async fn encrypt_key(args: &EncryptKeyArgs) -> Result<()> {
+    let ca = args.paths.load()?;
+    let passphrase = match &args.new_passphrase_file {
+        Some(path) => read_passphrase_file(path)?,
+        None => prompt_passphrase(&format!("New passphrase for {}: ", args.paths.ca_key_path), true)?,
+    };
+
+    let pem = encrypt_private_key_pem(&ca.key(), &passphrase)?;
+    write_atomic(std::path::Path::new(&args.paths.ca_key_path), pem.as_bytes())?;
+
+    info!("🔐 Encrypted {}", args.paths.ca_key_path);
+    info!("   Give the proxy the passphrase via TLS_CA_KEY_PASSPHRASE or tls.ca_key_passphrase_file");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::load_cert_from_files;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_encrypted_ca_init_issue_and_export_p12() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("passphrase"), "correct horse\n").unwrap();
+        let paths = || CaPathArgs {
+            ca_cert_path: path("rootCA.crt"),
+            ca_key_path: path("rootCA.key"),
+            passphrase_file: Some(path("passphrase")),
+        };
+
+        init_ca(&InitCaArgs {
+            paths: paths(),
+            organization: "Test".to_string(),
+            common_name: "Test Root".to_string(),
+            validity_days: 30,
+            key_algorithm: LeafKeyAlgorithm::EcdsaP256,
+            path_len: Some(0),
+            permit_dns: vec!["test".to_string()],
+            exclude_dns: Vec::new(),
+            new_key: NewKeyArgs { encrypt_key: true, new_passphrase_file: Some(path("passphrase")) },
+            force: false,
+        })
+        .await
+        .unwrap();
+        assert!(is_encrypted_key_file(&path("rootCA.key")).unwrap());
+        assert!(load_cert_from_files(&path("rootCA.crt"), &path("rootCA.key")).is_err());
+        assert!(load_cert_from_files_with_passphrase(&path("rootCA.crt"), &path("rootCA.key"), Some("wrong")).is_err());
+
+        issue_certificate(&IssueCaArgs {
+            domain: "app.test".to_string(),
+            paths: paths(),
+            san: vec!["api.test".to_string()],
+            validity_days: 30,
+            key_algorithm: LeafKeyAlgorithm::EcdsaP256,
+            cert_path: Some(path("app.crt")),
+            key_path: Some(path("app.key")),
+        })
+        .await
+        .unwrap();
+        assert!(load_cert_from_files(&path("app.crt"), &path("app.key")).is_ok());
+
+        export_ca(&ExportCaArgs {
+            paths: paths(),
+            format: "p12".to_string(),
+            output: path("rootCA.p12"),
+            password: Some("secret".to_string()),
+            include_key: true,
+        })
+        .await
+        .unwrap();
+
+        let keystore = KeyStore::from_pkcs12(&fs::read(path("rootCA.p12")).unwrap(), "secret").unwrap();
+        assert_eq!(keystore.entries_count(), 2);
+        assert!(keystore.private_key_chain().is_some());
+    }
+}
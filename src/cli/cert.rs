@@ -1,8 +1,11 @@
 //! Certificate management CLI commands
 
+use crate::cli::CaCommand;
 use crate::tls::{
+    extract_certificate_info,
     generate_self_signed_cert, 
     load_cert_from_files, 
+    parse_certificates,
     save_cert_to_files,
 };
 use anyhow::{anyhow, Result};
@@ -24,6 +27,10 @@ pub enum CertCommand {
     
     /// Convert certificate between formats
     Convert(ConvertCertArgs),
+    
+    /// Manage the root CA (init, issue, rotate, export)
+    #[command(subcommand)]
+    Ca(CaCommand),
 }
 
 #[derive(Debug, Args)]
@@ -109,6 +116,7 @@ impl CertCommand {
             CertCommand::Validate(args) => validate_certificate(args).await,
             CertCommand::Inspect(args) => inspect_certificate(args).await,
             CertCommand::Convert(args) => convert_certificate(args).await,
+            CertCommand::Ca(command) => command.execute().await,
         }
     }
 }
@@ -209,55 +217,77 @@ fn show_certificate_summary(cert_path: &str) -> Result<()> {
     
     // Read certificate file
     let cert_data = fs::read(cert_path)?;
-    let cert_content = String::from_utf8_lossy(&cert_data);
+    let certs = parse_certificates(&cert_data)?;
+    let info = extract_certificate_info(&certs[0])?;
     
     info!("📋 Certificate Summary:");
     info!("   File: {}", cert_path);
-    info!("   Size: {} bytes", cert_data.len());
-    info!("   Format: PEM");
-    
-    // Basic certificate info extraction
-    if cert_content.contains("-----BEGIN CERTIFICATE-----") {
-        info!("   Type: X.509 Certificate");
+    info!("   Subject: {}", info.subject);
+    info!("   Issuer: {}", info.issuer);
+    info!("   Valid until: {}", info.not_after.to_rfc3339());
+    if !info.subject_alt_names.is_empty() {
+        info!("   SANs: {}", info.subject_alt_names.join(", "));
     }
-    
-    // TODO: Parse certificate and extract more details
     info!("   Details: Use 'inspect' command for full details");
     
     Ok(())
 }
 
-/// Show detailed certificate information
+/// Show detailed certificate information for every certificate in the file
 fn show_certificate_details(cert_path: &str, verbose: bool, format: &str) -> Result<()> {
     let cert_data = fs::read(cert_path)?;
+    let certs = parse_certificates(&cert_data)?;
+    let infos = certs.iter().map(extract_certificate_info).collect::<Result<Vec<_>>>()?;
+    let encoding = if certs.len() == 1 && certs[0].0 == cert_data { "DER" } else { "PEM" };
     
     match format.to_lowercase().as_str() {
         "json" => {
-            println!("{{");
-            println!("  \"file\": \"{}\",", cert_path);
-            println!("  \"size\": {},", cert_data.len());
-            println!("  \"format\": \"PEM\",");
-            println!("  \"type\": \"X.509\"");
-            println!("}}");
+            let output = serde_json::json!({
+                "file": cert_path,
+                "format": encoding,
+                "certificates": infos,
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
         }
-        "text" | _ => {
+        "text" => {
             println!("Certificate Details:");
-            println!("  File: {}", cert_path);
-            println!("  Size: {} bytes", cert_data.len());
-            println!("  Format: PEM");
-            println!("  Type: X.509 Certificate");
+            println!("  File: {} ({}, {} certificate(s))", cert_path, encoding, infos.len());
             
-            if verbose {
-                println!("\nRaw Certificate Data:");
-                println!("{}", String::from_utf8_lossy(&cert_data));
+            for (index, info) in infos.iter().enumerate() {
+                println!("\n[{}] {}", index, info.subject);
+                println!("  Issuer: {}", info.issuer);
+                println!("  Serial: {}", info.serial_number);
+                println!("  Valid: {} to {}{}", info.not_before.to_rfc3339(), info.not_after.to_rfc3339(),
+                    if info.is_valid_now() { "" } else { " (NOT VALID NOW)" });
+                if !info.subject_alt_names.is_empty() {
+                    println!("  SANs: {}", info.subject_alt_names.join(", "));
+                }
+                println!("  Key: {} {} bits", info.public_key_algorithm, info.key_size);
+                println!("  Signature: {}", info.signature_algorithm);
+                match (info.is_ca, info.path_len_constraint) {
+                    (true, Some(path_len)) => println!("  CA: yes (path length {})", path_len),
+                    (true, None) => println!("  CA: yes"),
+                    (false, _) => println!("  CA: no"),
+                }
+                println!("  SHA-256: {}", info.fingerprint_sha256);
+                
+                if verbose {
+                    println!("  SHA-1: {}", info.fingerprint_sha1);
+                    println!("  Self-signed: {}", info.is_self_signed);
+                    if !info.key_usage.is_empty() {
+                        println!("  Key usage: {}", info.key_usage.join(", "));
+                    }
+                    if !info.extended_key_usage.is_empty() {
+                        println!("  Extended key usage: {}", info.extended_key_usage.join(", "));
+                    }
+                    println!("  Extensions:");
+                    for ext in &info.extensions {
+                        println!("    {} ({}){}", ext.name, ext.oid, if ext.critical { " critical" } else { "" });
+                    }
+                }
             }
         }
-    }
-    
-    if verbose {
-        // TODO: Add detailed certificate parsing and display
-        println!("\n⚠️  Detailed certificate parsing not yet implemented");
-        println!("    This would show: Subject, Issuer, Serial, Validity, Extensions, etc.");
+        other => return Err(anyhow!("Unknown output format '{}' (expected text or json)", other)),
     }
     
     Ok(())
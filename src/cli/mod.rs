@@ -1,8 +1,12 @@
 //! Command-line interface for certificate management and proxy operations
 
+pub mod bypass;
+pub mod ca;
 pub mod cert;
 pub mod server;
 
+pub use bypass::*;
+pub use ca::*;
 pub use cert::*;
 pub use server::*;
 
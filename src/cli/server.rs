@@ -1,7 +1,7 @@
 //! Server management CLI commands
 
 use crate::config::settings::ProxyConfig;
-use crate::tls::start_dual_servers;
+use crate::tls::{check_ca_key_policy, start_dual_servers};
 use crate::proxy::server::ProxyServer;
 use anyhow::Result;
 use clap::Args;
@@ -18,12 +18,16 @@ pub struct ServerArgs {
     #[arg(long, default_value = "127.0.0.1:8443")]
     pub https_listen_addr: String,
     
+    /// SOCKS5 proxy listening address (no SOCKS listener when omitted)
+    #[arg(long)]
+    pub socks_listen_addr: Option<String>,
+    
     /// Enable TLS/HTTPS support
     #[arg(long, default_value = "false")]
     pub enable_tls: bool,
     
-    /// Enable HTTPS interception mode
-    #[arg(long, default_value = "true")]
+    /// Enable HTTPS interception mode (false = plain CONNECT tunnels)
+    #[arg(long, default_value = "true", action = clap::ArgAction::Set)]
     pub enable_interception: bool,
     
     /// Auto-generate certificates if missing
@@ -66,18 +70,24 @@ impl ServerArgs {
         let https_listen_addr: SocketAddr = self.https_listen_addr.parse()
             .map_err(|e| anyhow::anyhow!("Invalid HTTPS listen address '{}': {}", self.https_listen_addr, e))?;
         
-        let mut config = ProxyConfig::default();
+        let socks_listen_addr: Option<SocketAddr> = self.socks_listen_addr.as_deref()
+            .map(|addr| addr.parse().map_err(|e| anyhow::anyhow!("Invalid SOCKS listen address '{}': {}", addr, e)))
+            .transpose()?;
         
         // Basic server config
-        config.listen_addr = listen_addr;
-        config.log_level = self.log_level.clone();
-        config.request_timeout = self.request_timeout;
-        config.max_body_size = self.max_body_size;
+        let mut config = ProxyConfig {
+            listen_addr,
+            socks_listen_addr,
+            log_level: self.log_level.clone(),
+            request_timeout: self.request_timeout,
+            max_body_size: self.max_body_size,
+            ..Default::default()
+        };
         
         // TLS configuration
         config.tls.enabled = self.enable_tls;
         config.tls.https_listen_addr = https_listen_addr;
-        config.tls.interception_enabled = true; // Always enable interception
+        config.tls.interception_enabled = self.enable_interception;
         config.tls.auto_generate_cert = self.auto_generate_cert;
         config.tls.cert_path = self.cert_path.clone();
         config.tls.key_path = self.key_path.clone();
@@ -86,7 +96,7 @@ impl ServerArgs {
         debug!("ProxyConfig created from CLI arguments");
         debug!("  HTTP: {}", config.listen_addr);
         debug!("  HTTPS: {} (enabled: {})", config.tls.https_listen_addr, config.tls.enabled);
-        debug!("  Interception: enabled");
+        debug!("  Interception: {}", config.tls.interception_enabled);
         
         Ok(config)
     }
@@ -104,22 +114,28 @@ impl ServerArgs {
             info!("   HTTPS proxy: {} (TLS enabled)", config.tls.https_listen_addr);
             info!("   Certificate: {}", config.tls.cert_path);
             info!("   Private key: {}", config.tls.key_path);
-            info!("   Interception: enabled");
+            info!("   Interception: {}", if config.tls.interception_enabled { "enabled" } else { "disabled" });
             info!("   Auto-cert: {}", if config.tls.auto_generate_cert { "enabled" } else { "disabled" });
         } else {
             info!("   HTTPS proxy: disabled");
         }
+        if let Some(socks_listen_addr) = config.socks_listen_addr {
+            info!("   SOCKS5 proxy: {}", socks_listen_addr);
+        }
         info!("   Request timeout: {}s", config.request_timeout);
         info!("   Max body size: {} bytes", config.max_body_size);
         info!("   Log level: {}", config.log_level);
         
+        check_ca_key_policy(&config.tls)?;
+        
         // Start server(s)
         if config.tls.enabled {
             info!("🔒 Starting dual HTTP/HTTPS proxy servers");
             start_dual_servers(config).await
         } else {
             info!("🌐 Starting HTTP-only proxy server");
-            let server = ProxyServer::new(config.listen_addr);
+            let server = ProxyServer::with_https_interception(config.listen_addr, config.tls.interception_enabled)
+                .with_socks_listener(config.socks_listen_addr);
             server.start().await
         }
     }
@@ -11,6 +11,10 @@ pub struct ProxyConfig {
     /// Server listening address
     pub listen_addr: SocketAddr,
     
+    /// SOCKS5 listening address; no SOCKS listener when unset
+    #[serde(default)]
+    pub socks_listen_addr: Option<SocketAddr>,
+    
     /// Log level configuration
     pub log_level: String,
     
@@ -40,19 +44,50 @@ pub struct ProxyConfig {
     
     /// Runtime configuration
     pub runtime: RuntimeConfig,
+    
+    /// Request/response middleware pipeline
+    #[serde(default)]
+    pub middleware: MiddlewareConfig,
+    
+    /// Request/response header rewrite rules
+    #[serde(default)]
+    pub header_rules: HeaderRulesConfig,
+    
+    /// Proxy authentication (Proxy-Authorization: Basic)
+    #[serde(default)]
+    pub auth: AuthConfig,
+    
+    /// WebSocket upgrade handling
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+    
+    /// Who may call the `/admin/*` endpoints
+    #[serde(default)]
+    pub admin: AdminConfig,
 }
 
-/// Upstream server configuration
+/// Parent proxy configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpstreamConfig {
-    /// Upstream server URL
+    /// Parent proxy URL: `http://[user:pass@]host:port` or
+    /// `socks5://[user:pass@]host:port`
     pub url: String,
     
-    /// Connection timeout in seconds
+    /// Connection timeout in seconds for tunnels, direct or through the
+    /// parent (including its handshake)
     pub connect_timeout: u64,
     
-    /// Keep-alive timeout in seconds
+    /// TCP keep-alive time in seconds for connections to the parent
     pub keep_alive_timeout: u64,
+
+    /// Route upstream connections through the parent at `url`
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Hosts connected to directly instead of through the parent (same
+    /// pattern syntax as `tls.interception_bypass`)
+    #[serde(default)]
+    pub direct_hosts: Vec<String>,
 }
 
 /// Redis configuration
@@ -105,9 +140,15 @@ pub struct HttpClientConfig {
     /// HTTP/2 keepalive timeout in seconds
     pub http2_keepalive_timeout_secs: u64,
     
-    /// HTTP/2 maximum concurrent streams
+    /// HTTP/2 maximum concurrent streams, announced to intercepted HTTP/2
+    /// clients only; upstream connections follow each upstream's own limit
     pub http2_max_concurrent_streams: u32,
     
+    /// How long a host whose HTTP/2 failed with a protocol error is sent
+    /// HTTP/1.1 instead
+    #[serde(default = "default_http2_fallback_secs")]
+    pub http2_fallback_secs: u64,
+    
     /// Enable TCP keepalive
     pub tcp_keepalive: bool,
     
@@ -141,6 +182,217 @@ pub struct RuntimeConfig {
     pub worker_threads: Option<usize>,
 }
 
+/// Middleware pipeline configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MiddlewareConfig {
+    /// Ordered middleware chain (requests run top to bottom, responses bottom to top)
+    #[serde(default)]
+    pub chain: Vec<MiddlewareSpec>,
+}
+
+/// A single entry in the middleware chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MiddlewareSpec {
+    /// Per-client-IP request rate limiting
+    RateLimit {
+        /// Requests allowed per client IP per minute
+        requests_per_minute: u32,
+    },
+    
+    /// Reject requests to the listed hosts with 403 Forbidden
+    BlockHosts {
+        /// Same patterns as `tls.interception_bypass`
+        hosts: Vec<String>,
+    },
+    
+    /// Tag each request with a unique ID header and log annotation
+    RequestId {
+        /// Header name used for the request ID
+        #[serde(default = "default_request_id_header")]
+        header: String,
+    },
+}
+
+fn default_request_id_header() -> String {
+    "x-request-id".to_string()
+}
+
+/// Declarative header rewrite rules
+///
+/// `defaults` run before `rules`. Out of the box they strip browser hint
+/// headers that upstreams often reject; set `defaults: []` to forward them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderRulesConfig {
+    /// Built-in ruleset, replaceable from config.yml
+    #[serde(default = "default_header_rules")]
+    pub defaults: Vec<HeaderRule>,
+    
+    /// User rules, applied in order after `defaults`
+    #[serde(default)]
+    pub rules: Vec<HeaderRule>,
+}
+
+/// Header actions applied to requests and responses matching a host/path
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeaderRule {
+    /// Same patterns as `tls.interception_bypass` (empty matches every host)
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    
+    /// Only apply to request paths starting with this prefix
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    
+    /// Actions applied to the request before it is forwarded upstream
+    #[serde(default)]
+    pub request: Vec<HeaderAction>,
+    
+    /// Actions applied to the response before it is returned to the client
+    #[serde(default)]
+    pub response: Vec<HeaderAction>,
+}
+
+/// A single header rewrite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HeaderAction {
+    /// Set a header, replacing any existing value
+    Add { name: String, value: String },
+    
+    /// Remove a header; a trailing `*` removes every header with that prefix
+    Remove { name: String },
+    
+    /// Add a value alongside any existing ones
+    Append { name: String, value: String },
+    
+    /// Regex replace within the header's value(s)
+    Replace {
+        name: String,
+        pattern: String,
+        #[serde(default)]
+        replacement: String,
+    },
+}
+
+/// Browser hint headers stripped from forwarded requests by default
+const BROWSER_HINT_HEADERS: &[&str] = &[
+    "rtt",                       // Network timing hint
+    "downlink",                  // Network speed hint
+    "priority",                  // Browser priority hint
+    "ect",                       // Effective connection type hint
+    "x-browser-*",               // Chrome browser headers
+    "sec-ch-ua*",                // Chrome Client Hints
+    "sec-ch-prefers*",           // Chrome preference hints
+    "x-client-data",             // Chrome telemetry data
+    "sec-fetch-dest",            // Browser security hints
+    "sec-fetch-mode",
+    "sec-fetch-site",
+    "sec-fetch-user",
+    "sec-fetch-storage-access",
+    "upgrade-insecure-requests",
+];
+
+fn default_header_rules() -> Vec<HeaderRule> {
+    vec![HeaderRule {
+        request: BROWSER_HINT_HEADERS
+            .iter()
+            .map(|name| HeaderAction::Remove { name: name.to_string() })
+            .collect(),
+        ..Default::default()
+    }]
+}
+
+impl Default for HeaderRulesConfig {
+    fn default() -> Self {
+        Self {
+            defaults: default_header_rules(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Proxy authentication configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Require Proxy-Authorization on every proxied request
+    #[serde(default)]
+    pub enabled: bool,
+    
+    /// Realm sent in the Proxy-Authenticate challenge
+    #[serde(default = "default_auth_realm")]
+    pub realm: String,
+    
+    /// htpasswd-style user file (`user:hash`, bcrypt or argon2 hashes)
+    #[serde(default)]
+    pub htpasswd_path: Option<String>,
+    
+    /// How long successfully verified credentials are cached (seconds)
+    #[serde(default = "default_credential_cache_secs")]
+    pub credential_cache_secs: u64,
+}
+
+fn default_auth_realm() -> String {
+    "rust-forward-proxy".to_string()
+}
+
+fn default_credential_cache_secs() -> u64 {
+    300
+}
+
+/// Access to the `/admin/*` endpoints
+///
+/// With no users and loopback access off, admin requests are refused.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Authenticated proxy users (from `auth.htpasswd_path`) allowed to call
+    /// admin endpoints
+    pub users: Vec<String>,
+    
+    /// Allow admin calls from clients connecting over loopback
+    pub allow_loopback: bool,
+}
+
+/// WebSocket upgrade handling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    /// Relay `Upgrade: websocket` requests (ws:// and intercepted wss://)
+    pub enabled: bool,
+    
+    /// Decode frames passing through and log them as transaction events
+    pub log_frames: bool,
+    
+    /// Bytes of text payload kept per logged frame
+    pub preview_bytes: usize,
+    
+    /// Frames attached to a connection's transaction; later ones are only counted
+    pub max_logged_frames: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            log_frames: false,
+            preview_bytes: 128,
+            max_logged_frames: 100,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            realm: default_auth_realm(),
+            htpasswd_path: None,
+            credential_cache_secs: default_credential_cache_secs(),
+        }
+    }
+}
+
 /// TLS configuration for HTTPS interception
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TlsConfig {
@@ -180,17 +432,261 @@ pub struct TlsConfig {
     /// Path to root CA certificate file for trust store
     pub root_ca_cert_path: Option<String>,
     
+    /// Per-host overrides of upstream certificate verification; the first
+    /// rule matching a host wins
+    #[serde(default)]
+    pub upstream_trust: Vec<UpstreamTrustRule>,
+    
+    /// What intercepted clients get when an upstream certificate fails verification
+    #[serde(default)]
+    pub upstream_cert_errors: UpstreamCertErrorConfig,
+    
     /// Path to CA certificate for signing domain certificates
     pub ca_cert_path: Option<String>,
     
     /// Path to CA private key for signing domain certificates
     pub ca_key_path: Option<String>,
+    
+    /// PEM file with the certificates between the signing CA and the root,
+    /// for when `ca_cert_path` is an intermediate (the root may be included)
+    #[serde(default)]
+    pub ca_chain_path: Option<String>,
+    
+    /// File whose first line is the passphrase of an encrypted `ca_key_path`
+    /// (`TLS_CA_KEY_PASSPHRASE` takes precedence)
+    #[serde(default)]
+    pub ca_key_passphrase_file: Option<String>,
+    
+    /// Refuse to start intercepting with an unencrypted CA key
+    #[serde(default)]
+    pub require_encrypted_ca_key: bool,
+    
+    /// Hosts that are always tunnelled instead of intercepted
+    /// (exact, `*.suffix`, `~regex` or CIDR for IP-literal targets)
+    #[serde(default)]
+    pub interception_bypass: Vec<String>,
+    
+    /// When non-empty, only these hosts are intercepted; everything else is tunnelled
+    #[serde(default)]
+    pub interception_only: Vec<String>,
+    
+    /// Learn to bypass hosts whose clients reject our forged certificates
+    #[serde(default)]
+    pub pinning_detection: PinningDetectionConfig,
+    
+    /// Copy subject, SANs, key usage and expiry from the real upstream leaf
+    /// into forged certificates (costs an extra upstream handshake whenever
+    /// the host's certificate isn't cached)
+    #[serde(default)]
+    pub mimic_upstream_cert: bool,
+    
+    /// Key algorithm for forged leaf certificates
+    #[serde(default)]
+    pub leaf_key_algorithm: LeafKeyAlgorithm,
+    
+    /// Validity of forged leaf certificates in days
+    #[serde(default = "default_leaf_validity_days")]
+    pub leaf_validity_days: u32,
+    
+    /// Which application protocols intercepted clients are offered
+    #[serde(default)]
+    pub client_alpn: ClientAlpn,
+    
+    /// How long a learned upstream ALPN result is reused before re-probing
+    #[serde(default = "default_alpn_cache_ttl_secs")]
+    pub alpn_cache_ttl_secs: u64,
+    
+    /// Where forged certificates are cached
+    #[serde(default)]
+    pub cert_cache: CertCacheConfig,
+}
+
+/// Key algorithm for forged leaf certificates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeafKeyAlgorithm {
+    /// ECDSA P-256 with SHA-256 (fast, universally supported)
+    #[default]
+    EcdsaP256,
+    /// RSA-2048 with SHA-256 (for very old clients)
+    Rsa2048,
+    /// Ed25519 (not accepted by browsers)
+    Ed25519,
+}
+
+/// ALPN protocols offered to intercepted clients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAlpn {
+    /// Offer h2 only when the upstream negotiates it
+    #[default]
+    Mirror,
+    /// Always offer h2 and http/1.1
+    H2,
+    /// Only ever offer http/1.1
+    Http1,
+}
+
+/// How certificates of matching upstream hosts are verified
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpstreamTrustRule {
+    /// Host patterns: exact, `*.suffix`, `~regex` or CIDR
+    pub hosts: Vec<String>,
+    
+    /// Accept any certificate from these hosts
+    pub skip_verify: bool,
+    
+    /// PEM files with extra trust anchors for these hosts
+    pub ca_cert_paths: Vec<String>,
+    
+    /// Also trust the system roots and `root_ca_cert_path`
+    pub system_roots: bool,
+}
+
+impl Default for UpstreamTrustRule {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            skip_verify: false,
+            ca_cert_paths: Vec::new(),
+            system_roots: true,
+        }
+    }
+}
+
+/// Handling of upstream certificates that fail verification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpstreamCertErrorConfig {
+    /// Answer with an HTML page describing the certificate and the failure
+    /// instead of a plain-text 502
+    pub error_page: bool,
+    
+    /// Hosts whose invalid certificates are accepted anyway, with a warning
+    /// logged for every connection (same pattern syntax as `interception_bypass`)
+    pub proceed_anyway: Vec<String>,
+    
+    /// Lifetime of exceptions granted through `/admin/cert-exceptions` (seconds)
+    pub exception_ttl_secs: u64,
+}
+
+impl Default for UpstreamCertErrorConfig {
+    fn default() -> Self {
+        Self {
+            error_page: true,
+            proceed_anyway: Vec::new(),
+            exception_ttl_secs: 3600,
+        }
+    }
+}
+
+fn default_leaf_validity_days() -> u32 {
+    30
+}
+
+fn default_alpn_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_http2_fallback_secs() -> u64 {
+    3600
+}
+
+/// Certificate pinning detection settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinningDetectionConfig {
+    /// Count certificate-rejecting handshake failures per host
+    pub enabled: bool,
+    
+    /// Failures within `window_secs` before a host is bypassed
+    pub failure_threshold: u32,
+    
+    /// Distinct client IPs those failures must come from, so one client
+    /// without the root CA installed can't turn interception off for everyone
+    pub min_clients: u32,
+    
+    /// Window in which failures are counted (seconds)
+    pub window_secs: u64,
+    
+    /// How long a learned bypass lasts (seconds)
+    pub bypass_ttl_secs: u64,
+}
+
+impl Default for PinningDetectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            failure_threshold: 3,
+            min_clients: 2,
+            window_secs: 300,       // 5 minutes
+            bypass_ttl_secs: 86400, // 24 hours
+        }
+    }
+}
+
+/// Certificate cache backend settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertCacheConfig {
+    /// `auto` uses Redis when `REDIS_URL` is set, otherwise memory
+    pub backend: CertCacheBackend,
+    
+    /// Directory for the disk backend
+    pub path: String,
+    
+    /// Entry format for the disk backend
+    pub format: DiskCacheFormat,
+    
+    /// Redis URL for the redis backend (default: `redis.url`)
+    pub redis_url: Option<String>,
+    
+    /// Maximum cached certificates (memory and disk backends)
+    pub max_entries: usize,
+    
+    /// In-process entries kept in front of Redis (0 disables the L1 tier)
+    pub l1_entries: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertCacheBackend {
+    #[default]
+    Auto,
+    Memory,
+    Redis,
+    Disk,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskCacheFormat {
+    /// Certificate and key PEM with `#` metadata lines (readable with openssl)
+    #[default]
+    Pem,
+    /// Compact binary entries
+    Bincode,
+}
+
+impl Default for CertCacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CertCacheBackend::Auto,
+            path: "certs/cache".to_string(),
+            format: DiskCacheFormat::Pem,
+            redis_url: None,
+            max_entries: 1000,
+            l1_entries: 256,
+        }
+    }
 }
 
 impl Default for ProxyConfig {
     fn default() -> Self {
         Self {
             listen_addr: "127.0.0.1:80".parse().unwrap(),
+            socks_listen_addr: None,
             log_level: "info".to_string(),
             upstream: UpstreamConfig::default(),
             redis: RedisConfig::default(),
@@ -201,6 +697,11 @@ impl Default for ProxyConfig {
             http_client: HttpClientConfig::default(),
             streaming: StreamingConfig::default(),
             runtime: RuntimeConfig::default(),
+            middleware: MiddlewareConfig::default(),
+            header_rules: HeaderRulesConfig::default(),
+            auth: AuthConfig::default(),
+            websocket: WebSocketConfig::default(),
+            admin: AdminConfig::default(),
         }
     }
 }
@@ -211,6 +712,8 @@ impl Default for UpstreamConfig {
             url: "http://localhost:3000".to_string(),
             connect_timeout: 5,
             keep_alive_timeout: 60,
+            enabled: false,
+            direct_hosts: Vec::new(),
         }
     }
 }
@@ -246,6 +749,7 @@ impl Default for HttpClientConfig {
             http2_keepalive_interval_secs: 30,
             http2_keepalive_timeout_secs: 10,
             http2_max_concurrent_streams: 100,
+            http2_fallback_secs: default_http2_fallback_secs(),
             tcp_keepalive: true,
             tcp_keepalive_interval_secs: 30,
         }
@@ -287,8 +791,22 @@ impl Default for TlsConfig {
             min_tls_version: "1.2".to_string(),
             skip_upstream_cert_verify: false, // Verify upstream certs by default
             root_ca_cert_path: Some("ca-certs/securly_ca.crt".to_string()),
+            upstream_trust: Vec::new(),
+            upstream_cert_errors: UpstreamCertErrorConfig::default(),
             ca_cert_path: Some("ca-certs/rootCA.crt".to_string()),
             ca_key_path: Some("ca-certs/rootCA.key".to_string()),
+            ca_chain_path: None,
+            ca_key_passphrase_file: None,
+            require_encrypted_ca_key: false,
+            interception_bypass: Vec::new(),
+            interception_only: Vec::new(),
+            pinning_detection: PinningDetectionConfig::default(),
+            mimic_upstream_cert: false,
+            leaf_key_algorithm: LeafKeyAlgorithm::default(),
+            leaf_validity_days: default_leaf_validity_days(),
+            client_alpn: ClientAlpn::default(),
+            alpn_cache_ttl_secs: default_alpn_cache_ttl_secs(),
+            cert_cache: CertCacheConfig::default(),
         }
     }
 }
@@ -310,7 +828,7 @@ impl ProxyConfig {
         let config_path = "config.yml";
         
         let mut config = if Path::new(&config_path).exists() {
-            Self::from_yaml_file(&config_path)?
+            Self::from_yaml_file(config_path)?
         } else {
             return Err(anyhow::anyhow!("Config file '{}' not found. Please ensure config.yml exists in the project root.", config_path));
         };
@@ -328,6 +846,12 @@ impl ProxyConfig {
             }
         }
         
+        if let Ok(socks_port) = std::env::var("SOCKS_PROXY_PORT") {
+            if let Ok(port) = socks_port.parse::<u16>() {
+                config.socks_listen_addr = Some(format!("127.0.0.1:{}", port).parse().unwrap());
+            }
+        }
+        
         Ok(config)
     }
     
@@ -343,6 +867,12 @@ impl ProxyConfig {
             }
         }
         
+        if let Ok(addr_str) = std::env::var("PROXY_SOCKS_LISTEN_ADDR") {
+            if let Ok(addr) = addr_str.parse() {
+                config.socks_listen_addr = Some(addr);
+            }
+        }
+        
         if let Ok(log_level) = std::env::var("RUST_LOG") {
             config.log_level = log_level;
         }
@@ -375,6 +905,18 @@ impl ProxyConfig {
                 config.upstream.keep_alive_timeout = timeout;
             }
         }
+
+        if let Ok(enabled) = std::env::var("UPSTREAM_ENABLED") {
+            config.upstream.enabled = enabled.to_lowercase() == "true";
+        }
+
+        if let Ok(direct_hosts) = std::env::var("UPSTREAM_DIRECT_HOSTS") {
+            config.upstream.direct_hosts = direct_hosts
+                .split(',')
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .collect();
+        }
         
         // Load Redis settings
         if let Ok(redis_url) = std::env::var("REDIS_URL") {
@@ -460,6 +1002,18 @@ impl ProxyConfig {
             config.tls.ca_key_path = Some(ca_key);
         }
         
+        if let Ok(ca_chain) = std::env::var("TLS_CA_CHAIN_PATH") {
+            config.tls.ca_chain_path = Some(ca_chain);
+        }
+        
+        if let Ok(passphrase_file) = std::env::var("TLS_CA_KEY_PASSPHRASE_FILE") {
+            config.tls.ca_key_passphrase_file = Some(passphrase_file);
+        }
+        
+        if let Ok(require) = std::env::var("TLS_REQUIRE_ENCRYPTED_CA_KEY") {
+            config.tls.require_encrypted_ca_key = require.to_lowercase() == "true";
+        }
+        
         // Load logging settings
         if let Ok(enable_file_logging) = std::env::var("PROXY_ENABLE_FILE_LOGGING") {
             config.logging.enable_file_logging = enable_file_logging.to_lowercase() == "true";
@@ -518,6 +1072,12 @@ impl ProxyConfig {
             }
         }
         
+        if let Ok(secs) = std::env::var("PROXY_HTTP2_FALLBACK_SECS") {
+            if let Ok(secs) = secs.parse() {
+                config.http_client.http2_fallback_secs = secs;
+            }
+        }
+        
         if let Ok(keepalive) = std::env::var("PROXY_TCP_KEEPALIVE") {
             config.http_client.tcp_keepalive = keepalive.to_lowercase() == "true";
         }
@@ -34,6 +34,9 @@ async fn async_main(config: ProxyConfig) -> anyhow::Result<()> {
     log_info!("Console-only logging enabled");
     log_info!("log added");
 
+    // Strict mode: never run with the CA key in plaintext
+    rust_forward_proxy::tls::check_ca_key_policy(&config.tls)?;
+
     if config.tls.enabled {
         // Start both HTTP and HTTPS servers
         log_info!("🚀 Starting dual HTTP/HTTPS proxy servers");
@@ -49,9 +52,11 @@ async fn async_main(config: ProxyConfig) -> anyhow::Result<()> {
         if config.tls.interception_enabled {
             tracing::debug!("🔍 HTTPS interception enabled - CONNECT requests to port 443 will be intercepted");
             log_info!("⚠️  Clients will see certificate warnings (normal for self-signed certs)");
+        } else {
+            log_info!("🔌 HTTPS interception disabled - CONNECT requests will be tunnelled");
         }
         
-        let server = ProxyServer::with_https_interception_and_config(config.listen_addr, true, &config);
+        let server = ProxyServer::with_https_interception_and_config(config.listen_addr, config.tls.interception_enabled, &config)?;
         server.start().await?;
     }
 
@@ -2,7 +2,7 @@
 
 use clap::{Parser, Subcommand};
 use rust_forward_proxy::{
-    cli::{CertCommand, ServerArgs},
+    cli::{BypassCommand, CertCommand, ServerArgs},
     init_logger_with_env,
     log_info, log_error,
     ProxyConfig,
@@ -38,6 +38,11 @@ enum Commands {
     #[command(name = "cert")]
     #[command(subcommand)]
     Cert(CertCommand),
+    
+    /// Learned interception bypass commands
+    #[command(name = "bypass")]
+    #[command(subcommand)]
+    Bypass(BypassCommand),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -87,6 +92,13 @@ async fn async_main_cli(cli: Cli, _config: ProxyConfig) -> anyhow::Result<()> {
                 std::process::exit(1);
             }
         }
+        Some(Commands::Bypass(bypass_cmd)) => {
+            if let Err(e) = bypass_cmd.execute().await {
+                log_error!("Bypass command error: {}", e);
+                error!("Bypass operation failed: {}", e);
+                std::process::exit(1);
+            }
+        }
         None => {
             // Default action: start server with default configuration
             log_info!("🚀 Starting Rust Forward Proxy Server (default configuration)");
@@ -96,6 +108,7 @@ async fn async_main_cli(cli: Cli, _config: ProxyConfig) -> anyhow::Result<()> {
             let default_args = ServerArgs {
                 listen_addr: "127.0.0.1:8080".to_string(),
                 https_listen_addr: "127.0.0.1:8443".to_string(),
+                socks_listen_addr: None,
                 enable_tls: true, // Disabled by default
                 enable_interception: true,
                 auto_generate_cert: true,
@@ -0,0 +1,51 @@
+//! Host deny-list middleware
+
+use super::{Middleware, MiddlewareAction, MiddlewareContext};
+use crate::models::RequestData;
+use crate::tls::HostMatcher;
+use crate::utils::build_error_response;
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::StatusCode;
+use tracing::info;
+
+/// Rejects requests whose target host is on a deny list
+pub struct BlockHostsMiddleware {
+    hosts: HostMatcher,
+}
+
+impl BlockHostsMiddleware {
+    /// Entries use the `tls.interception_bypass` patterns: exact, `*.suffix`,
+    /// `~regex` or CIDR
+    pub fn new(hosts: Vec<String>) -> Self {
+        info!("⛔ Blocking {} host pattern(s)", hosts.len());
+        Self { hosts: HostMatcher::new(&hosts) }
+    }
+
+    /// Return the pattern that blocks `host`, if any
+    pub fn matching_rule(&self, host: &str) -> Option<&str> {
+        self.hosts.matching_rule(host)
+    }
+}
+
+#[async_trait]
+impl Middleware for BlockHostsMiddleware {
+    fn name(&self) -> &str {
+        "block_hosts"
+    }
+
+    async fn on_request(&self, request: &mut RequestData, ctx: &mut MiddlewareContext) -> Result<MiddlewareAction> {
+        let Some(host) = request.target_host() else {
+            return Ok(MiddlewareAction::Continue);
+        };
+
+        match self.matching_rule(&host) {
+            Some(rule) => {
+                info!("⛔ Blocked request to {} (rule: {})", host, rule);
+                ctx.annotate("blocked_by", rule);
+                Ok(MiddlewareAction::Respond(build_error_response(StatusCode::FORBIDDEN, "Blocked by proxy policy")))
+            }
+            None => Ok(MiddlewareAction::Continue),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Declarative header rewrite rules
+
+use super::{Middleware, MiddlewareAction, MiddlewareContext};
+use crate::config::settings::{HeaderAction, HeaderRule, HeaderRulesConfig};
+use crate::models::RequestData;
+use crate::tls::HostMatcher;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, HeaderMap, Response};
+use regex::Regex;
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+/// A header action with its name lowercased and regex compiled
+enum CompiledAction {
+    Add(String, String),
+    Remove(String),
+    Append(String, String),
+    Replace(String, Regex, String),
+}
+
+impl CompiledAction {
+    fn compile(action: &HeaderAction) -> Result<Self> {
+        Ok(match action {
+            HeaderAction::Add { name, value } => Self::Add(name.to_lowercase(), value.clone()),
+            HeaderAction::Remove { name } => Self::Remove(name.to_lowercase()),
+            HeaderAction::Append { name, value } => Self::Append(name.to_lowercase(), value.clone()),
+            HeaderAction::Replace { name, pattern, replacement } => {
+                Self::Replace(name.to_lowercase(), Regex::new(pattern)?, replacement.clone())
+            }
+        })
+    }
+
+    /// Apply to request headers, where repeated values are comma-joined
+    fn apply_to_request(&self, headers: &mut HashMap<String, String>) {
+        match self {
+            Self::Add(name, value) => {
+                headers.insert(name.clone(), value.clone());
+            }
+            Self::Remove(name) => match name.strip_suffix('*') {
+                Some(prefix) => headers.retain(|k, _| !k.starts_with(prefix)),
+                None => {
+                    headers.remove(name);
+                }
+            },
+            Self::Append(name, value) => {
+                headers
+                    .entry(name.clone())
+                    .and_modify(|existing| {
+                        existing.push_str(", ");
+                        existing.push_str(value);
+                    })
+                    .or_insert_with(|| value.clone());
+            }
+            Self::Replace(name, regex, replacement) => {
+                if let Some(existing) = headers.get_mut(name) {
+                    *existing = regex.replace_all(existing, replacement.as_str()).into_owned();
+                }
+            }
+        }
+    }
+
+    fn apply_to_response(&self, headers: &mut HeaderMap) -> Result<()> {
+        match self {
+            Self::Add(name, value) => {
+                headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+            }
+            Self::Remove(name) => match name.strip_suffix('*') {
+                Some(prefix) => {
+                    let matching: Vec<HeaderName> = headers
+                        .keys()
+                        .filter(|k| k.as_str().starts_with(prefix))
+                        .cloned()
+                        .collect();
+                    for key in matching {
+                        headers.remove(key);
+                    }
+                }
+                None => {
+                    headers.remove(name.as_str());
+                }
+            },
+            Self::Append(name, value) => {
+                headers.append(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+            }
+            Self::Replace(name, regex, replacement) => {
+                let name = HeaderName::from_bytes(name.as_bytes())?;
+                let values: Vec<String> = headers
+                    .get_all(&name)
+                    .iter()
+                    .filter_map(|v| v.to_str().ok())
+                    .map(|v| regex.replace_all(v, replacement.as_str()).into_owned())
+                    .collect();
+                if values.is_empty() {
+                    return Ok(());
+                }
+
+                headers.remove(&name);
+                for value in values {
+                    headers.append(name.clone(), HeaderValue::from_str(&value)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A rule with its matchers normalised and actions compiled
+struct CompiledRule {
+    /// `None` matches every host
+    hosts: Option<HostMatcher>,
+    path_prefix: Option<String>,
+    request: Vec<CompiledAction>,
+    response: Vec<CompiledAction>,
+}
+
+impl CompiledRule {
+    fn compile(rule: &HeaderRule) -> Result<Self> {
+        let compile_all = |actions: &[HeaderAction]| {
+            actions
+                .iter()
+                .map(|action| {
+                    CompiledAction::compile(action).with_context(|| format!("Invalid header rule action {:?}", action))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            hosts: (!rule.hosts.is_empty()).then(|| HostMatcher::new(&rule.hosts)),
+            path_prefix: rule.path_prefix.clone(),
+            request: compile_all(&rule.request)?,
+            response: compile_all(&rule.response)?,
+        })
+    }
+
+    fn matches(&self, request: &RequestData) -> bool {
+        if let Some(prefix) = &self.path_prefix {
+            if !request.path.starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+
+        let Some(hosts) = &self.hosts else {
+            return true;
+        };
+
+        request
+            .target_host()
+            .is_some_and(|host| hosts.matching_rule(&host).is_some())
+    }
+}
+
+/// Applies `header_rules` from config.yml to requests and responses
+///
+/// Rules are evaluated in order and every matching rule applies; `defaults`
+/// come before user `rules` so users can re-add a header the defaults strip.
+pub struct HeaderRulesMiddleware {
+    rules: Vec<CompiledRule>,
+}
+
+impl HeaderRulesMiddleware {
+    /// Fails when a rule can't be compiled, e.g. an invalid `replace` pattern
+    pub fn from_config(config: &HeaderRulesConfig) -> Result<Self> {
+        let rules: Vec<CompiledRule> = config
+            .defaults
+            .iter()
+            .chain(config.rules.iter())
+            .map(CompiledRule::compile)
+            .collect::<Result<_>>()?;
+
+        if !config.rules.is_empty() {
+            info!("📝 Header rules: {} default, {} custom", config.defaults.len(), config.rules.len());
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.iter().all(|r| r.request.is_empty() && r.response.is_empty())
+    }
+}
+
+#[async_trait]
+impl Middleware for HeaderRulesMiddleware {
+    fn name(&self) -> &str {
+        "header_rules"
+    }
+
+    async fn on_request(&self, request: &mut RequestData, _ctx: &mut MiddlewareContext) -> Result<MiddlewareAction> {
+        let before = request.headers.len();
+
+        for rule in &self.rules {
+            if rule.request.is_empty() || !rule.matches(request) {
+                continue;
+            }
+            for action in &rule.request {
+                action.apply_to_request(&mut request.headers);
+            }
+        }
+
+        debug!("📝 Header rules: {} → {} request headers", before, request.headers.len());
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn on_response(&self, request: &RequestData, response: &mut Response<Body>, _ctx: &mut MiddlewareContext) -> Result<()> {
+        for rule in self.rules.iter().filter(|r| !r.response.is_empty() && r.matches(request)) {
+            for action in &rule.response {
+                action.apply_to_response(response.headers_mut())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> RequestData {
+        RequestData::new("GET".to_string(), url.to_string(), "127.0.0.1".parse().unwrap(), 5000)
+    }
+
+    fn context() -> MiddlewareContext {
+        MiddlewareContext::new("127.0.0.1:5000".parse().unwrap(), false)
+    }
+
+    #[tokio::test]
+    async fn test_default_rules_strip_browser_hints() {
+        let middleware = HeaderRulesMiddleware::from_config(&HeaderRulesConfig::default()).unwrap();
+        let mut request = request("http://example.com/");
+        for name in ["sec-ch-ua-platform", "sec-fetch-mode", "rtt", "accept"] {
+            request.headers.insert(name.to_string(), "x".to_string());
+        }
+
+        middleware.on_request(&mut request, &mut context()).await.unwrap();
+
+        let remaining: Vec<&str> = request.headers.keys().map(String::as_str).collect();
+        assert_eq!(remaining, vec!["accept"]);
+    }
+
+    #[tokio::test]
+    async fn test_rules_match_host_and_path() {
+        let config: HeaderRulesConfig = serde_yaml::from_str(
+            r#"
+defaults: []
+rules:
+  - hosts: ["*.example.com"]
+    path_prefix: /api
+    request:
+      - { action: add, name: X-Api, value: "1" }
+      - { action: append, name: accept, value: "text/plain" }
+      - { action: replace, name: user-agent, pattern: "Chrome/\\d+", replacement: "Chrome/0" }
+    response:
+      - { action: remove, name: "x-powered-*" }
+"#,
+        )
+        .unwrap();
+        let middleware = HeaderRulesMiddleware::from_config(&config).unwrap();
+
+        let mut matching = request("http://api.example.com/api/users");
+        matching.headers.insert("accept".to_string(), "application/json".to_string());
+        matching.headers.insert("user-agent".to_string(), "Chrome/120 Safari".to_string());
+        middleware.on_request(&mut matching, &mut context()).await.unwrap();
+
+        assert_eq!(matching.headers["x-api"], "1");
+        assert_eq!(matching.headers["accept"], "application/json, text/plain");
+        assert_eq!(matching.headers["user-agent"], "Chrome/0 Safari");
+
+        let mut other_path = request("http://api.example.com/static/app.js");
+        middleware.on_request(&mut other_path, &mut context()).await.unwrap();
+        assert!(!other_path.headers.contains_key("x-api"));
+
+        let mut response = Response::new(Body::empty());
+        response.headers_mut().insert("x-powered-by", "php".parse().unwrap());
+        response.headers_mut().insert("server", "nginx".parse().unwrap());
+        middleware.on_response(&matching, &mut response, &mut context()).await.unwrap();
+        assert!(!response.headers().contains_key("x-powered-by"));
+        assert!(response.headers().contains_key("server"));
+    }
+
+    #[tokio::test]
+    async fn test_rules_accept_regex_and_cidr_hosts() {
+        let config: HeaderRulesConfig = serde_yaml::from_str(
+            r#"
+defaults: []
+rules:
+  - hosts: ["~^api[0-9]+\\.example\\.com$", "10.0.0.0/8"]
+    request:
+      - { action: add, name: X-Internal, value: "1" }
+"#,
+        )
+        .unwrap();
+        let middleware = HeaderRulesMiddleware::from_config(&config).unwrap();
+
+        for (url, expected) in [
+            ("http://api2.example.com/", true),
+            ("http://10.1.2.3:8080/", true),
+            ("http://www.example.com/", false),
+        ] {
+            let mut request = request(url);
+            middleware.on_request(&mut request, &mut context()).await.unwrap();
+            assert_eq!(request.headers.contains_key("x-internal"), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_invalid_replace_pattern_is_rejected() {
+        let config: HeaderRulesConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - request:
+      - { action: replace, name: user-agent, pattern: "Chrome/(", replacement: "" }
+"#,
+        )
+        .unwrap();
+
+        assert!(HeaderRulesMiddleware::from_config(&config).is_err());
+    }
+}
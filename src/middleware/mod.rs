@@ -0,0 +1,255 @@
+//! Request/response middleware pipeline
+//!
+//! Middleware are configured as an ordered chain under `middleware.chain` in
+//! config.yml. Request hooks run top to bottom before a request is forwarded
+//! and may short-circuit with their own response; response hooks run bottom
+//! to top on the way back. The same chain runs for plain HTTP, intercepted
+//! HTTPS and the TLS proxy listener.
+
+pub mod block_hosts;
+pub mod header_rules;
+pub mod rate_limit;
+pub mod request_id;
+
+pub use block_hosts::BlockHostsMiddleware;
+pub use header_rules::HeaderRulesMiddleware;
+pub use rate_limit::RateLimitMiddleware;
+pub use request_id::RequestIdMiddleware;
+
+use crate::config::settings::{HeaderRulesConfig, MiddlewareConfig, MiddlewareSpec};
+use crate::models::RequestData;
+use crate::utils::build_error_response;
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{Body, Response, StatusCode};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{debug, error, info, warn};
+
+/// Outcome of a middleware request hook
+pub enum MiddlewareAction {
+    /// Continue with the next middleware and eventually forward upstream
+    Continue,
+    /// Stop processing and answer the client with this response
+    Respond(Response<Body>),
+}
+
+/// Per-request state shared by all middleware in the chain
+#[derive(Debug, Clone)]
+pub struct MiddlewareContext {
+    /// Address of the connected client
+    pub remote_addr: SocketAddr,
+    /// Whether the request was decrypted from an intercepted HTTPS tunnel
+    pub intercepted: bool,
+    /// Key/value notes attached to the transaction's `ProxyLog`
+    pub annotations: HashMap<String, String>,
+    /// Number of middleware whose request hook completed
+    completed: usize,
+}
+
+impl MiddlewareContext {
+    pub fn new(remote_addr: SocketAddr, intercepted: bool) -> Self {
+        Self {
+            remote_addr,
+            intercepted,
+            annotations: HashMap::new(),
+            completed: 0,
+        }
+    }
+
+    /// Attach a note to the transaction log
+    pub fn annotate(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.annotations.insert(key.into(), value.into());
+    }
+}
+
+/// A request/response hook in the proxy pipeline
+///
+/// Request hooks may rewrite `RequestData` (headers, body, URL) before it is
+/// forwarded, or short-circuit by returning `MiddlewareAction::Respond`.
+/// Response hooks may rewrite the response sent back to the client.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Name used in logs and annotations
+    fn name(&self) -> &str;
+
+    async fn on_request(
+        &self,
+        _request: &mut RequestData,
+        _ctx: &mut MiddlewareContext,
+    ) -> Result<MiddlewareAction> {
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn on_response(
+        &self,
+        _request: &RequestData,
+        _response: &mut Response<Body>,
+        _ctx: &mut MiddlewareContext,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Ordered chain of middleware
+#[derive(Default, Clone)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build the chain configured in config.yml
+    pub fn from_config(config: &MiddlewareConfig) -> Self {
+        let mut chain = Self::new();
+
+        for spec in &config.chain {
+            let middleware: Arc<dyn Middleware> = match spec {
+                MiddlewareSpec::RateLimit { requests_per_minute } => {
+                    Arc::new(RateLimitMiddleware::new(*requests_per_minute))
+                }
+                MiddlewareSpec::BlockHosts { hosts } => {
+                    Arc::new(BlockHostsMiddleware::new(hosts.clone()))
+                }
+                MiddlewareSpec::RequestId { header } => {
+                    Arc::new(RequestIdMiddleware::new(header))
+                }
+            };
+            chain.push(middleware);
+        }
+
+        if !chain.is_empty() {
+            info!("🧩 Middleware chain: {}", chain.names().join(" → "));
+        }
+
+        chain
+    }
+
+    /// Append the configured header rewrite rules to the end of the chain
+    ///
+    /// Header rules run last on requests and first on responses, so they see
+    /// exactly what is sent to and received from the upstream.
+    pub fn with_header_rules(mut self, config: &HeaderRulesConfig) -> Result<Self> {
+        let rules = HeaderRulesMiddleware::from_config(config)?;
+        if !rules.is_empty() {
+            self.push(Arc::new(rules));
+        }
+        Ok(self)
+    }
+
+    /// Append a middleware to the end of the chain
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.middlewares.push(middleware);
+    }
+
+    pub fn len(&self) -> usize {
+        self.middlewares.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.middlewares.iter().map(|m| m.name().to_string()).collect()
+    }
+
+    /// Run request hooks in order
+    ///
+    /// Returns `Some(response)` when a middleware short-circuits; the caller
+    /// should still pass that response through `process_response`, which will
+    /// only run the hooks of the middleware that came before it.
+    pub async fn process_request(
+        &self,
+        request: &mut RequestData,
+        ctx: &mut MiddlewareContext,
+    ) -> Option<Response<Body>> {
+        ctx.completed = 0;
+
+        for middleware in &self.middlewares {
+            match middleware.on_request(request, ctx).await {
+                Ok(MiddlewareAction::Continue) => {
+                    ctx.completed += 1;
+                }
+                Ok(MiddlewareAction::Respond(response)) => {
+                    debug!("🧩 Middleware '{}' answered {} {} with {}",
+                           middleware.name(), request.method, request.url, response.status());
+                    ctx.annotate("short_circuited_by", middleware.name());
+                    return Some(response);
+                }
+                Err(e) => {
+                    error!("🧩 Middleware '{}' failed on request: {}", middleware.name(), e);
+                    ctx.annotate("middleware_error", format!("{}: {}", middleware.name(), e));
+                    return Some(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Middleware error"));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Run response hooks in reverse order
+    pub async fn process_response(
+        &self,
+        request: &RequestData,
+        response: &mut Response<Body>,
+        ctx: &mut MiddlewareContext,
+    ) {
+        let completed = ctx.completed.min(self.middlewares.len());
+
+        for middleware in self.middlewares[..completed].iter().rev() {
+            if let Err(e) = middleware.on_response(request, response, ctx).await {
+                warn!("🧩 Middleware '{}' failed on response: {}", middleware.name(), e);
+                ctx.annotate("middleware_error", format!("{}: {}", middleware.name(), e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Deny;
+
+    #[async_trait]
+    impl Middleware for Deny {
+        fn name(&self) -> &str {
+            "deny"
+        }
+
+        async fn on_request(&self, _request: &mut RequestData, _ctx: &mut MiddlewareContext) -> Result<MiddlewareAction> {
+            Ok(MiddlewareAction::Respond(build_error_response(StatusCode::FORBIDDEN, "denied")))
+        }
+
+        async fn on_response(&self, _request: &RequestData, response: &mut Response<Body>, _ctx: &mut MiddlewareContext) -> Result<()> {
+            response.headers_mut().insert("x-deny", "ran".parse().unwrap());
+            Ok(())
+        }
+    }
+
+    fn request() -> RequestData {
+        RequestData::new("GET".to_string(), "http://example.com/".to_string(), "127.0.0.1".parse().unwrap(), 5000)
+    }
+
+    #[tokio::test]
+    async fn test_short_circuit_skips_later_hooks() {
+        let mut chain = MiddlewareChain::new();
+        chain.push(Arc::new(RequestIdMiddleware::new("x-request-id")));
+        chain.push(Arc::new(Deny));
+
+        let mut request = request();
+        let mut ctx = MiddlewareContext::new("127.0.0.1:5000".parse().unwrap(), false);
+        let mut response = chain.process_request(&mut request, &mut ctx).await.expect("short-circuit");
+        chain.process_response(&request, &mut response, &mut ctx).await;
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().contains_key("x-request-id"));
+        assert!(!response.headers().contains_key("x-deny"));
+        assert_eq!(ctx.annotations.get("short_circuited_by").map(String::as_str), Some("deny"));
+    }
+}
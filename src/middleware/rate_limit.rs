@@ -0,0 +1,87 @@
+//! Per-client-IP rate limiting middleware
+
+use super::{Middleware, MiddlewareAction, MiddlewareContext};
+use crate::models::RequestData;
+use crate::utils::build_error_response;
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Fixed one-minute window rate limiter keyed on client IP
+pub struct RateLimitMiddleware {
+    requests_per_minute: u32,
+    request_counts: Arc<Mutex<HashMap<IpAddr, (u32, Instant)>>>,
+}
+
+impl RateLimitMiddleware {
+    pub fn new(requests_per_minute: u32) -> Self {
+        info!("🚦 Rate limiting enabled: {} requests/minute per client", requests_per_minute);
+        Self {
+            requests_per_minute,
+            request_counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record a request from `client_ip`, returning false once it is over the limit
+    pub fn check_rate_limit(&self, client_ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut counts = self.request_counts.lock().unwrap();
+
+        // Drop finished windows so the map doesn't grow with every client ever seen
+        if counts.len() > 10_000 {
+            counts.retain(|_, (_, started)| now.duration_since(*started) < WINDOW);
+        }
+
+        let entry = counts.entry(client_ip).or_insert((0, now));
+        if now.duration_since(entry.1) >= WINDOW {
+            *entry = (0, now);
+        }
+        entry.0 += 1;
+
+        entry.0 <= self.requests_per_minute
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+    fn name(&self) -> &str {
+        "rate_limit"
+    }
+
+    async fn on_request(&self, request: &mut RequestData, ctx: &mut MiddlewareContext) -> Result<MiddlewareAction> {
+        if self.check_rate_limit(request.client_ip) {
+            return Ok(MiddlewareAction::Continue);
+        }
+
+        debug!("🚦 Rate limit exceeded for {}", request.client_ip);
+        ctx.annotate("rate_limited", "true");
+
+        let mut response = build_error_response(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded");
+        response.headers_mut().insert("retry-after", WINDOW.as_secs().into());
+        Ok(MiddlewareAction::Respond(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_per_ip() {
+        let limiter = RateLimitMiddleware::new(2);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.check_rate_limit(a));
+        assert!(limiter.check_rate_limit(a));
+        assert!(!limiter.check_rate_limit(a));
+        assert!(limiter.check_rate_limit(b));
+    }
+}
@@ -0,0 +1,59 @@
+//! Request ID tagging middleware
+
+use super::{Middleware, MiddlewareAction, MiddlewareContext};
+use crate::models::RequestData;
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, Response};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Adds a unique ID to each request, its response and its transaction log
+///
+/// An ID already supplied by the client is kept so traces can be correlated.
+pub struct RequestIdMiddleware {
+    header: String,
+    counter: AtomicU64,
+    prefix: String,
+}
+
+impl RequestIdMiddleware {
+    pub fn new(header: &str) -> Self {
+        Self {
+            header: header.to_lowercase(),
+            counter: AtomicU64::new(0),
+            prefix: format!("{:x}-{:x}", std::process::id(), chrono::Utc::now().timestamp_millis()),
+        }
+    }
+
+    fn next_id(&self) -> String {
+        format!("{}-{}", self.prefix, self.counter.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestIdMiddleware {
+    fn name(&self) -> &str {
+        "request_id"
+    }
+
+    async fn on_request(&self, request: &mut RequestData, ctx: &mut MiddlewareContext) -> Result<MiddlewareAction> {
+        let id = request
+            .headers
+            .entry(self.header.clone())
+            .or_insert_with(|| self.next_id())
+            .clone();
+        ctx.annotate("request_id", id);
+        Ok(MiddlewareAction::Continue)
+    }
+
+    async fn on_response(&self, _request: &RequestData, response: &mut Response<Body>, ctx: &mut MiddlewareContext) -> Result<()> {
+        let Some(id) = ctx.annotations.get("request_id") else {
+            return Ok(());
+        };
+
+        let name = HeaderName::from_bytes(self.header.as_bytes())?;
+        response.headers_mut().insert(name, HeaderValue::from_str(id)?);
+        Ok(())
+    }
+}
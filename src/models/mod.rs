@@ -17,6 +17,8 @@ pub struct RequestData {
     // Client information
     pub client_ip: IpAddr,
     pub client_port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>, // Set when proxy authentication is enabled
 
     // Timing
     pub timestamp: DateTime<Utc>,
@@ -50,6 +52,8 @@ pub struct ResponseData {
     pub content_length: u64,
     pub response_time_ms: u64,
     pub body: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>, // Negotiated with the upstream; unset when it wasn't contacted
 }
 
 impl ResponseData {
@@ -68,6 +72,7 @@ impl ResponseData {
             content_length: body.len() as u64,
             response_time_ms,
             body,
+            protocol: None,
         }
     }
 }
@@ -78,6 +83,31 @@ pub struct ProxyLog {
     pub request: RequestData,
     pub response: Option<ResponseData>,
     pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>, // Added by middleware
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<TransactionEvent>, // Things that happened after the response, e.g. WebSocket frames
+}
+
+// Something observed on a connection after its request/response exchange
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransactionEvent {
+    #[serde(rename = "websocket_frame")]
+    WebSocketFrame(WebSocketFrame),
+}
+
+// A decoded WebSocket frame header with the start of its payload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebSocketFrame {
+    pub timestamp: DateTime<Utc>,
+    pub direction: String, // "client_to_server" or "server_to_client"
+    pub opcode: String,    // "text", "binary", "close", "ping", "pong", "continuation"
+    pub fin: bool,
+    pub compressed: bool, // RSV1 set (permessage-deflate); no preview then
+    pub length: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>, // Text payload, or close code and reason
 }
 
 impl RequestData {
@@ -108,6 +138,7 @@ impl RequestData {
             http_version: "HTTP/1.1".to_string(),
             client_ip,
             client_port,
+            username: None,
             timestamp: Utc::now(),
             duration_ms: None,
             headers: HashMap::new(),
@@ -123,4 +154,15 @@ impl RequestData {
             protocol: "HTTP/1.1".to_string(),
         }
     }
+
+    // Host the request is aimed at, taken from the URL or the Host header
+    pub fn target_host(&self) -> Option<String> {
+        if let Some(host) = parse_url(&self.url).ok().and_then(|u| u.host_str().map(|h| h.to_lowercase())) {
+            return Some(host);
+        }
+        self.headers
+            .get("host")
+            .or(self.host.as_ref())
+            .map(|h| h.split(':').next().unwrap_or(h).to_lowercase())
+    }
 }
@@ -0,0 +1,201 @@
+//! Proxy authentication against an htpasswd-style user file
+
+use crate::config::settings::AuthConfig;
+use crate::utils::build_error_response;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::{Engine as _, engine::general_purpose};
+use sha2::{Digest, Sha256};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Checks `Proxy-Authorization: Basic` credentials against an htpasswd file
+///
+/// Supports bcrypt (`$2a$`, `$2b$`, `$2y$`) and argon2 (`$argon2id$`, ...)
+/// hashes. Hash verification is deliberately slow, so successful credentials
+/// are cached for `credential_cache_secs`, keyed by their SHA-256 digest.
+pub struct ProxyAuthenticator {
+    realm: String,
+    users: HashMap<String, String>,
+    /// Verified for unknown users, so they take as long as known ones
+    dummy_hash: Option<String>,
+    cache_ttl: Duration,
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl ProxyAuthenticator {
+    /// Build the authenticator, or `None` when authentication is disabled
+    ///
+    /// A missing or unreadable user file fails closed: every request is
+    /// rejected until the file is fixed.
+    pub fn from_config(config: &AuthConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        let contents = match &config.htpasswd_path {
+            Some(path) => std::fs::read_to_string(path).unwrap_or_else(|e| {
+                error!("❌ Failed to read htpasswd file {}: {} - all proxy requests will be rejected", path, e);
+                String::new()
+            }),
+            None => {
+                error!("❌ auth.enabled is set without auth.htpasswd_path - all proxy requests will be rejected");
+                String::new()
+            }
+        };
+
+        let authenticator = Self::from_htpasswd(&config.realm, &contents, Duration::from_secs(config.credential_cache_secs));
+        info!("🔐 Proxy authentication enabled: {} user(s), realm \"{}\"", authenticator.users.len(), authenticator.realm);
+        Some(authenticator)
+    }
+
+    /// Parse htpasswd contents (`user:hash` per line, `#` comments allowed)
+    pub fn from_htpasswd(realm: &str, contents: &str, cache_ttl: Duration) -> Self {
+        let mut users = HashMap::new();
+
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once(':') {
+                Some((user, hash)) if is_supported_hash(hash) => {
+                    users.insert(user.to_string(), hash.to_string());
+                }
+                Some((user, _)) => {
+                    warn!("⚠️  htpasswd line {}: unsupported hash for user '{}' (use bcrypt or argon2)", line_no + 1, user);
+                }
+                None => {
+                    warn!("⚠️  htpasswd line {}: expected user:hash", line_no + 1);
+                }
+            }
+        }
+
+        Self {
+            realm: realm.to_string(),
+            // Any user's hash has a realistic algorithm and cost
+            dummy_hash: users.values().next().cloned(),
+            users,
+            cache_ttl,
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the authenticated username for a request, if its credentials are valid
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Option<String> {
+        let value = headers.get("proxy-authorization")?.to_str().ok()?;
+        let (scheme, encoded) = value.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            debug!("🔐 Unsupported Proxy-Authorization scheme: {}", scheme);
+            return None;
+        }
+
+        let decoded = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+        let credentials = String::from_utf8(decoded).ok()?;
+        let (username, password) = credentials.split_once(':')?;
+
+        self.verify(username, password).await.then(|| username.to_string())
+    }
+
+    /// Check a username/password pair against the user file
+    pub async fn verify(&self, username: &str, password: &str) -> bool {
+        let cache_key: [u8; 32] = Sha256::new()
+            .chain_update(username.as_bytes())
+            .chain_update(b":")
+            .chain_update(password.as_bytes())
+            .finalize()
+            .into();
+        if let Some(verified_at) = self.verified.lock().unwrap().get(&cache_key) {
+            if verified_at.elapsed() < self.cache_ttl {
+                return true;
+            }
+        }
+
+        // Unknown users still pay for a hash check, so timing doesn't reveal
+        // which usernames exist
+        let known = self.users.get(username);
+        let Some(hash) = known.or(self.dummy_hash.as_ref()).cloned() else {
+            return false;
+        };
+
+        // bcrypt/argon2 are CPU heavy; keep them off the async workers
+        let password = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || verify_hash(&password, &hash))
+            .await
+            .unwrap_or(false);
+        let valid = known.is_some() && matched;
+        if known.is_none() {
+            debug!("🔐 Unknown proxy user '{}'", username);
+        }
+
+        if valid {
+            let mut verified = self.verified.lock().unwrap();
+            verified.retain(|_, at| at.elapsed() < self.cache_ttl);
+            verified.insert(cache_key, Instant::now());
+        }
+
+        valid
+    }
+
+    /// 407 response asking the client for proxy credentials
+    pub fn challenge_response(&self) -> Response<Body> {
+        let mut response = build_error_response(StatusCode::PROXY_AUTHENTICATION_REQUIRED, "Proxy authentication required");
+        if let Ok(value) = format!("Basic realm=\"{}\"", self.realm).parse() {
+            response.headers_mut().insert("proxy-authenticate", value);
+        }
+        response
+    }
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("$2") || hash.starts_with("$argon2")
+}
+
+fn verify_hash(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        PasswordHash::new(hash)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+            .unwrap_or(false)
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    fn basic(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", general_purpose::STANDARD.encode(credentials));
+        headers.insert("proxy-authorization", value.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_bcrypt_and_argon2_users() {
+        let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+        let salt = SaltString::encode_b64(b"proxy-test-salt").unwrap();
+        let argon2_hash = Argon2::default().hash_password(b"hunter2", &salt).unwrap().to_string();
+        let htpasswd = format!("# users\nalice:{}\nbob:{}\ncarol:{{SHA}}abc\n", bcrypt_hash, argon2_hash);
+
+        let auth = ProxyAuthenticator::from_htpasswd("test", &htpasswd, Duration::from_secs(60));
+
+        assert_eq!(auth.authenticate(&basic("alice:secret")).await.as_deref(), Some("alice"));
+        assert_eq!(auth.authenticate(&basic("bob:hunter2")).await.as_deref(), Some("bob"));
+        assert_eq!(auth.authenticate(&basic("alice:wrong")).await, None);
+        assert_eq!(auth.authenticate(&basic("carol:abc")).await, None);
+        // Unknown users are checked against another user's hash, never accepted
+        assert_eq!(auth.authenticate(&basic("mallory:secret")).await, None);
+        assert_eq!(auth.authenticate(&basic("mallory:hunter2")).await, None);
+        assert_eq!(auth.authenticate(&HeaderMap::new()).await, None);
+
+        let challenge = auth.challenge_response();
+        assert_eq!(challenge.status(), StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+        assert_eq!(challenge.headers()["proxy-authenticate"], "Basic realm=\"test\"");
+    }
+}
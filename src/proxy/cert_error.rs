@@ -0,0 +1,140 @@
+//! Error pages for upstream certificates that fail verification
+//!
+//! Intercepted clients only ever see our forged certificate, so the browser
+//! can't warn about a bad upstream one. Instead of a bare 502 they get a page
+//! naming the problem and showing the certificate the upstream presented.
+
+use crate::tls::CertificateInfo;
+use hyper::{Body, Response, StatusCode};
+use rustls::CertificateError;
+
+/// Short explanation of a verification failure, and an error code in the
+/// style browsers use
+pub fn describe_failure(error: &rustls::Error, host: &str) -> (String, &'static str) {
+    let reason = match error {
+        rustls::Error::InvalidCertificate(reason) => reason,
+        other => return (format!("The secure connection failed: {}", other), "ERR_SSL_PROTOCOL_ERROR"),
+    };
+
+    match reason {
+        CertificateError::Expired => ("The certificate has expired.".to_string(), "ERR_CERT_DATE_INVALID"),
+        CertificateError::NotValidYet => ("The certificate is not valid yet.".to_string(), "ERR_CERT_DATE_INVALID"),
+        CertificateError::UnknownIssuer => (
+            "The certificate was not issued by a trusted certificate authority.".to_string(),
+            "ERR_CERT_AUTHORITY_INVALID",
+        ),
+        CertificateError::NotValidForName => {
+            (format!("The certificate is not valid for {}.", host), "ERR_CERT_COMMON_NAME_INVALID")
+        }
+        CertificateError::Revoked => ("The certificate has been revoked.".to_string(), "ERR_CERT_REVOKED"),
+        CertificateError::BadSignature => ("The certificate signature is invalid.".to_string(), "ERR_CERT_INVALID"),
+        other => (format!("The certificate is invalid ({:?}).", other), "ERR_CERT_INVALID"),
+    }
+}
+
+/// 502 page for a request to `host:port` whose upstream certificate was rejected
+///
+/// `cert` is the certificate the upstream presented, when it could be read.
+pub fn cert_error_response(host: &str, port: u16, error: &rustls::Error, cert: Option<&CertificateInfo>) -> Response<Body> {
+    let (summary, code) = describe_failure(error, host);
+    let target = if port == 443 { host.to_string() } else { format!("{}:{}", host, port) };
+
+    let details = match cert {
+        Some(cert) => {
+            let rows = [
+                ("Subject", cert.subject.clone()),
+                ("Issuer", cert.issuer.clone()),
+                ("Names", cert.subject_alt_names.join(", ")),
+                ("Valid from", cert.not_before.to_rfc2822()),
+                ("Valid until", cert.not_after.to_rfc2822()),
+                ("Serial", cert.serial_number.clone()),
+                ("SHA-256", cert.fingerprint_sha256.clone()),
+            ];
+            let rows: String = rows
+                .iter()
+                .map(|(name, value)| format!("<tr><th>{}</th><td>{}</td></tr>", name, escape_html(value)))
+                .collect();
+            format!("<h2>Certificate presented by the server</h2><table>{}</table>", rows)
+        }
+        None => "<p>The server's certificate could not be read.</p>".to_string(),
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Certificate error: {target}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 48em; margin: 3em auto; color: #222; }}
+h1 {{ color: #b00020; }}
+code {{ background: #eee; padding: 0 .2em; }}
+th {{ text-align: left; padding-right: 1em; vertical-align: top; white-space: nowrap; }}
+td {{ word-break: break-all; }}
+</style>
+</head>
+<body>
+<h1>&#9888; The connection to {target} is not secure</h1>
+<p>{summary}</p>
+<p>The proxy refused to forward this request because the server's certificate failed verification.
+Someone may be impersonating the site, or it may be misconfigured.</p>
+<p><code>{code}</code> &middot; <code>{error}</code></p>
+{details}
+<p>If this site is known to be safe, a proxy administrator can let requests through with
+<code>POST /admin/cert-exceptions?host={host}</code> or a <code>tls.upstream_cert_errors.proceed_anyway</code> rule.</p>
+</body>
+</html>
+"#,
+        target = escape_html(&target),
+        summary = escape_html(&summary),
+        code = code,
+        error = escape_html(&error.to_string()),
+        details = details,
+        host = escape_html(host),
+    );
+
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("content-type", "text/html; charset=utf-8")
+        .header("cache-control", "no-store")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cert_error_page_names_the_problem_and_escapes_details() {
+        let cert = crate::tls::generate_self_signed_cert("<script>Org</script>", "expired.test", 1).unwrap();
+        let info = crate::tls::extract_certificate_info(&cert.cert()).unwrap();
+        let error = rustls::Error::InvalidCertificate(CertificateError::Expired);
+
+        let response = cert_error_response("expired.test", 8443, &error, Some(&info));
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("The connection to expired.test:8443 is not secure"));
+        assert!(body.contains("ERR_CERT_DATE_INVALID"));
+        assert!(body.contains("&lt;script&gt;Org&lt;/script&gt;"));
+        assert!(!body.contains("<script>"));
+        assert!(body.contains(&info.fingerprint_sha256));
+    }
+}
@@ -4,22 +4,54 @@
 //! for maximum proxy performance:
 //! - Shared HTTP client with connection pooling
 //! - Connection reuse and persistent connections
+//! - Upstream certificate verification from `tls` settings
+//! - HTTP/2 where the upstream negotiates it, with a per-host fallback to
+//!   HTTP/1.1 after protocol errors
+//! - Connections through the `upstream` parent proxy, per host route
 
-use hyper::{Client, Body};
-use hyper_rustls::HttpsConnectorBuilder;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::{info, debug};
+use crate::config::settings::TlsConfig;
+use crate::proxy::parent::{ParentKind, Route, UpstreamConnector, UpstreamDialer};
+use crate::tls::{create_client_config, CertExceptions};
+use crate::utils::is_hop_by_hop_header;
+use bytes::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::PROXY_AUTHORIZATION;
+use hyper::{Client, Body, HeaderMap, Method, Request, Response, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, debug, error, warn};
+
+pub type HttpsClient = Client<HttpsConnector<UpstreamConnector>, Body>;
+
+/// h2 errors that mean the host's HTTP/2 can't be relied on
+const FALLBACK_REASONS: &[h2::Reason] = &[
+    h2::Reason::PROTOCOL_ERROR,
+    h2::Reason::FRAME_SIZE_ERROR,
+    h2::Reason::COMPRESSION_ERROR,
+    h2::Reason::INADEQUATE_SECURITY,
+    h2::Reason::HTTP_1_1_REQUIRED,
+];
 
 /// High-performance HTTP client with connection pooling
 /// 
 /// This eliminates the critical performance bottleneck of creating new HTTP clients
 /// for every request, instead providing shared, reusable clients with connection pooling.
 pub struct HttpClient {
-    /// Shared HTTPS client with connection pooling for HTTPS requests
-    https_client: Arc<Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>, Body>>,
+    /// Shared HTTPS client with connection pooling for HTTPS requests,
+    /// negotiating h2 or http/1.1 over ALPN
+    https_client: Arc<HttpsClient>,
+    /// Shared HTTPS client limited to HTTP/1.1, for upgrades and hosts
+    /// whose HTTP/2 failed
+    https_client_http1: Arc<HttpsClient>,
     /// Shared HTTP client for regular HTTP requests  
     http_client: Arc<Client<hyper::client::HttpConnector, Body>>,
+    /// Hosts currently sent HTTP/1.1 after HTTP/2 protocol errors
+    http2_fallback: Http2Fallback,
+    /// Routes upstream connections directly or through the parent proxy
+    dialer: Arc<UpstreamDialer>,
     /// Configuration for connection pooling
     config: ClientConfig,
 }
@@ -44,7 +76,12 @@ pub struct ClientConfig {
     /// HTTP/2 keep alive timeout (default: 10 seconds)
     pub http2_keep_alive_timeout: Option<Duration>,
     /// HTTP/2 max concurrent streams per connection (default: 100)
+    ///
+    /// Only announced to intercepted HTTP/2 clients: hyper 0.14's client
+    /// can't set it and follows the limit each upstream announces.
     pub http2_max_concurrent_streams: Option<u32>,
+    /// How long a host stays on HTTP/1.1 after an HTTP/2 protocol error (default: 1 hour)
+    pub http2_fallback_ttl: Duration,
     /// Enable TCP keepalive (default: true)
     pub tcp_keepalive: bool,
     /// TCP keepalive interval (default: 30 seconds)
@@ -63,6 +100,7 @@ impl Default for ClientConfig {
             http2_keep_alive_interval: Some(Duration::from_secs(30)),
             http2_keep_alive_timeout: Some(Duration::from_secs(10)),
             http2_max_concurrent_streams: Some(100),
+            http2_fallback_ttl: Duration::from_secs(60 * 60),
             tcp_keepalive: true,
             tcp_keepalive_interval: Some(Duration::from_secs(30)),
         }
@@ -76,7 +114,20 @@ impl HttpClient {
     }
 
     /// Create a new optimized HTTP client with custom configuration
+    ///
+    /// Upstream certificates are verified against the system roots only;
+    /// use `with_tls_config` to apply the proxy's `tls` settings.
     pub fn with_config(config: ClientConfig) -> Self {
+        Self::build(config, None, Arc::new(UpstreamDialer::direct()))
+    }
+
+    /// Create an HTTP client whose HTTPS connector uses `tls` (see
+    /// `create_client_config`) for upstream handshakes
+    pub fn with_tls_config(config: ClientConfig, tls: Arc<rustls::ClientConfig>) -> Self {
+        Self::build(config, Some(tls), Arc::new(UpstreamDialer::direct()))
+    }
+
+    fn build(config: ClientConfig, tls: Option<Arc<rustls::ClientConfig>>, dialer: Arc<UpstreamDialer>) -> Self {
         info!("🚀 Initializing advanced HTTP client with connection pooling");
         info!("   Max idle connections per host: {}", config.max_idle_per_host);
         info!("   Idle timeout: {:?}", config.idle_timeout);
@@ -87,21 +138,49 @@ impl HttpClient {
         info!("   HTTP/2 keep-alive interval: {:?}", config.http2_keep_alive_interval);
         info!("   TCP keepalive enabled: {}", config.tcp_keepalive);
 
-        // Create HTTPS connector with optimized settings
-        // Temporarily disable HTTP/2 to fix 400 errors with some servers like Google
-        let https_connector = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
-
-        // Create HTTPS client with advanced connection pooling
-        // Temporarily force HTTP/1.1 only to fix 400 errors with some servers like Google
-        let https_client = Client::builder()
+        // Direct connections for the HTTPS clients, with the same TCP
+        // settings as the HTTP client's
+        let mut direct_connector = HttpConnector::new();
+        direct_connector.enforce_http(false);
+        direct_connector.set_connect_timeout(Some(config.connect_timeout));
+        direct_connector.set_nodelay(true);
+        if config.tcp_keepalive {
+            direct_connector.set_keepalive(config.tcp_keepalive_interval);
+        }
+        let upstream_connector = UpstreamConnector::new(direct_connector, dialer.clone());
+
+        // Create HTTPS connectors with optimized settings; ALPN decides
+        // between h2 and http/1.1 per connection
+        let https_connector = |h2: bool| {
+            let builder = match &tls {
+                Some(tls) => HttpsConnectorBuilder::new().with_tls_config((**tls).clone()),
+                None => HttpsConnectorBuilder::new().with_native_roots(),
+            };
+            let builder = builder.https_or_http().enable_http1();
+            if h2 {
+                builder.enable_http2().wrap_connector(upstream_connector.clone())
+            } else {
+                builder.wrap_connector(upstream_connector.clone())
+            }
+        };
+
+        // Create HTTPS clients with advanced connection pooling
+        let mut https_builder = Client::builder();
+        https_builder
             .pool_idle_timeout(config.idle_timeout)
-            .pool_max_idle_per_host(config.max_idle_per_host)
-            .http2_only(false) // Allow both HTTP/1.1 and HTTP/2 but prefer HTTP/1.1
-            .build(https_connector);
+            .pool_max_idle_per_host(config.max_idle_per_host);
+        let https_client_http1 = https_builder.build(https_connector(false));
+
+        // No http2_max_concurrent_streams here: hyper's client takes the upstream's limit
+        https_builder
+            .http2_initial_stream_window_size(config.http2_initial_stream_window_size)
+            .http2_initial_connection_window_size(config.http2_initial_connection_window_size)
+            .http2_keep_alive_interval(config.http2_keep_alive_interval)
+            .http2_keep_alive_while_idle(true);
+        if let Some(timeout) = config.http2_keep_alive_timeout {
+            https_builder.http2_keep_alive_timeout(timeout);
+        }
+        let https_client = https_builder.build(https_connector(config.enable_http2));
 
         // Create HTTP connector for regular HTTP requests with advanced TCP settings
         let mut http_connector = hyper::client::HttpConnector::new();
@@ -125,7 +204,10 @@ impl HttpClient {
 
         let http_client = Self {
             https_client: Arc::new(https_client),
+            https_client_http1: Arc::new(https_client_http1),
             http_client: Arc::new(http_client),
+            http2_fallback: Http2Fallback::new(config.http2_fallback_ttl),
+            dialer,
             config,
         };
         
@@ -139,11 +221,17 @@ impl HttpClient {
     /// 
     /// This client has connection pooling enabled and will reuse connections
     /// to the same host, dramatically reducing connection establishment overhead.
-    pub fn get_https_client(&self) -> Arc<Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>, Body>> {
+    pub fn get_https_client(&self) -> Arc<HttpsClient> {
         debug!("📡 Using shared HTTPS client with connection pooling");
         Arc::clone(&self.https_client)
     }
 
+    /// Get the shared HTTPS client that never negotiates HTTP/2, for
+    /// requests that rely on HTTP/1.1 such as `Upgrade`
+    pub fn get_http1_client(&self) -> Arc<HttpsClient> {
+        Arc::clone(&self.https_client_http1)
+    }
+
     /// Get the shared HTTP client for making HTTP requests
     /// 
     /// This client has connection pooling enabled for regular HTTP requests.
@@ -156,11 +244,70 @@ impl HttpClient {
     /// 
     /// For maximum performance, this returns the HTTPS client for both HTTP and HTTPS
     /// requests since the HTTPS client can handle both protocols efficiently.
-    pub fn get_client_for_url(&self, _is_https: bool) -> Arc<Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>, Body>> {
+    pub fn get_client_for_url(&self, _is_https: bool) -> Arc<HttpsClient> {
         // Use HTTPS client for both HTTP and HTTPS since it can handle both efficiently
         self.get_https_client()
     }
 
+    /// Send `request` upstream, over HTTP/2 when the host negotiates it
+    ///
+    /// Headers that only mean something on an HTTP/1.1 connection are
+    /// dropped first. A host whose HTTP/2 fails with a protocol error gets
+    /// HTTP/1.1 for `http2_fallback_ttl`, and the failed request is retried
+    /// over HTTP/1.1 when the server asked for that or the method is
+    /// idempotent.
+    pub async fn send(&self, request: Request<Bytes>) -> hyper::Result<Response<Body>> {
+        let (mut parts, body) = request.into_parts();
+        normalize_upstream_headers(&mut parts.headers, &parts.uri);
+        self.authorize_with_parent(&mut parts.headers, &parts.uri);
+        let authority = parts.uri.authority().map(|a| a.to_string()).unwrap_or_default();
+
+        if !self.config.enable_http2 || parts.uri.scheme_str() != Some("https") || self.http2_fallback.is_active(&authority) {
+            return self.https_client_http1.request(Request::from_parts(parts, Body::from(body))).await;
+        }
+
+        let (method, uri, headers) = (parts.method.clone(), parts.uri.clone(), parts.headers.clone());
+        let error = match self.https_client.request(Request::from_parts(parts, Body::from(body.clone()))).await {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+
+        let Some(reason) = http2_fallback_reason(&error) else {
+            return Err(error);
+        };
+        self.http2_fallback.activate(&authority, reason);
+        if reason != h2::Reason::HTTP_1_1_REQUIRED && !is_idempotent(&method) {
+            return Err(error);
+        }
+
+        info!("🔁 Retrying {} {} over HTTP/1.1", method, uri);
+        let mut retry = Request::new(Body::from(body));
+        *retry.method_mut() = method;
+        *retry.uri_mut() = uri;
+        *retry.headers_mut() = headers;
+        self.https_client_http1.request(retry).await
+    }
+
+    /// Plain HTTP through an HTTP parent goes to the parent as an
+    /// absolute-form request, which carries the parent's credentials
+    ///
+    /// `send` does this itself; callers using the raw clients must too.
+    pub fn authorize_with_parent(&self, headers: &mut HeaderMap, uri: &Uri) {
+        if uri.scheme_str() == Some("https") {
+            return;
+        }
+        if let Route::Parent(parent) = self.dialer.route(uri.host().unwrap_or_default()) {
+            if let (ParentKind::Http, Some(auth)) = (parent.kind, parent.proxy_authorization()) {
+                headers.insert(PROXY_AUTHORIZATION, auth);
+            }
+        }
+    }
+
+    /// Hosts currently sent HTTP/1.1, with the seconds left
+    pub fn http2_fallback_hosts(&self) -> Vec<(String, u64)> {
+        self.http2_fallback.list()
+    }
+
     /// Get configuration information for monitoring and debugging
     pub fn get_config(&self) -> &ClientConfig {
         &self.config
@@ -204,6 +351,12 @@ impl HttpClient {
     }
 }
 
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Statistics and configuration information about the optimized HTTP client
 #[derive(Debug)]
 pub struct ClientStats {
@@ -224,7 +377,12 @@ pub struct ClientStats {
 impl HttpClient {
     /// Create HTTP client with configuration from the config struct
     /// This is the recommended way to create an HTTP client with explicit configuration
-    pub fn from_config(http_client_config: &crate::config::settings::HttpClientConfig) -> Self {
+    pub fn from_config(
+        http_client_config: &crate::config::settings::HttpClientConfig,
+        tls_config: &TlsConfig,
+        exceptions: Arc<CertExceptions>,
+        dialer: Arc<UpstreamDialer>,
+    ) -> Self {
         let config = ClientConfig {
             max_idle_per_host: http_client_config.max_idle_per_host as usize,
             idle_timeout: Duration::from_secs(http_client_config.idle_timeout_secs),
@@ -235,17 +393,18 @@ impl HttpClient {
             http2_keep_alive_interval: Some(Duration::from_secs(http_client_config.http2_keepalive_interval_secs)),
             http2_keep_alive_timeout: Some(Duration::from_secs(http_client_config.http2_keepalive_timeout_secs)),
             http2_max_concurrent_streams: Some(http_client_config.http2_max_concurrent_streams),
+            http2_fallback_ttl: Duration::from_secs(http_client_config.http2_fallback_secs),
             tcp_keepalive: http_client_config.tcp_keepalive,
             tcp_keepalive_interval: Some(Duration::from_secs(http_client_config.tcp_keepalive_interval_secs)),
         };
 
         info!("🔧 Loading HTTP client configuration from config file");
-        Self::with_config(config)
+        Self::with_upstream_tls(config, tls_config, exceptions, dialer)
     }
 
     /// Create HTTP client with advanced configuration from environment variables
     /// DEPRECATED: Use from_config instead for better configuration management
-    pub fn from_env() -> Self {
+    pub fn from_env(tls_config: &TlsConfig, exceptions: Arc<CertExceptions>, dialer: Arc<UpstreamDialer>) -> Self {
         let config = ClientConfig {
             max_idle_per_host: std::env::var("PROXY_MAX_IDLE_PER_HOST")
                 .unwrap_or_else(|_| "50".to_string())
@@ -297,6 +456,12 @@ impl HttpClient {
                     .parse()
                     .unwrap_or(100)
             ),
+            http2_fallback_ttl: Duration::from_secs(
+                std::env::var("PROXY_HTTP2_FALLBACK_SECS")
+                    .unwrap_or_else(|_| "3600".to_string())
+                    .parse()
+                    .unwrap_or(3600)
+            ),
             tcp_keepalive: std::env::var("PROXY_TCP_KEEPALIVE")
                 .unwrap_or_else(|_| "true".to_string())
                 .parse()
@@ -310,7 +475,110 @@ impl HttpClient {
         };
 
         info!("🔧 Loading HTTP client configuration from environment variables");
-        Self::with_config(config)
+        Self::with_upstream_tls(config, tls_config, exceptions, dialer)
+    }
+
+    /// Client verifying upstreams per `tls_config`, falling back to the
+    /// system roots if that can't be set up
+    fn with_upstream_tls(
+        config: ClientConfig,
+        tls_config: &TlsConfig,
+        exceptions: Arc<CertExceptions>,
+        dialer: Arc<UpstreamDialer>,
+    ) -> Self {
+        match create_client_config(tls_config, exceptions) {
+            Ok(tls) => Self::build(config, Some(tls), dialer),
+            Err(e) => {
+                error!("❌ Upstream TLS settings not applied, verifying against system roots: {}", e);
+                Self::build(config, None, dialer)
+            }
+        }
+    }
+}
+
+/// Hosts that are sent HTTP/1.1 for a while after HTTP/2 protocol errors
+struct Http2Fallback {
+    ttl: Duration,
+    hosts: Mutex<HashMap<String, Instant>>,
+}
+
+impl Http2Fallback {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_active(&self, authority: &str) -> bool {
+        let hosts = self.hosts.lock().unwrap();
+        hosts.get(authority).is_some_and(|until| *until > Instant::now())
+    }
+
+    fn activate(&self, authority: &str, reason: h2::Reason) {
+        warn!("⚠️  HTTP/2 to {} failed ({}), using HTTP/1.1 for the next {}s", authority, reason, self.ttl.as_secs());
+        let mut hosts = self.hosts.lock().unwrap();
+        let now = Instant::now();
+        hosts.retain(|_, until| *until > now);
+        hosts.insert(authority.to_string(), now + self.ttl);
+    }
+
+    fn list(&self) -> Vec<(String, u64)> {
+        let now = Instant::now();
+        let hosts = self.hosts.lock().unwrap();
+        hosts
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(host, until)| (host.clone(), (*until - now).as_secs()))
+            .collect()
+    }
+}
+
+/// The h2 reason behind `error`, if it's one that calls for HTTP/1.1
+fn http2_fallback_reason(error: &hyper::Error) -> Option<h2::Reason> {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(h2_error) = cause.downcast_ref::<h2::Error>() {
+            return h2_error.reason().filter(|reason| FALLBACK_REASONS.contains(reason));
+        }
+        source = cause.source();
+    }
+    None
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE)
+}
+
+/// Drop headers that are only valid on an HTTP/1.1 connection
+///
+/// HTTP/2 forbids connection-specific headers, and carries the host in
+/// `:authority`; a `host` header repeating the URI authority is left for
+/// hyper to add on HTTP/1.1 connections.
+fn normalize_upstream_headers(headers: &mut HeaderMap, uri: &Uri) {
+    let nominated: Vec<String> = headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in nominated {
+        headers.remove(name.as_str());
+    }
+
+    let hop_by_hop: Vec<_> = headers.keys().filter(|name| is_hop_by_hop_header(name.as_str())).cloned().collect();
+    for name in hop_by_hop {
+        headers.remove(name);
+    }
+
+    let host_is_authority = headers
+        .get("host")
+        .zip(uri.authority())
+        .is_some_and(|(host, authority)| host.as_bytes().eq_ignore_ascii_case(authority.as_str().as_bytes()));
+    if host_is_authority {
+        headers.remove("host");
     }
 }
 
@@ -326,6 +594,32 @@ mod tests {
         assert!(config.enable_http2);
     }
 
+    #[test]
+    fn test_upstream_headers_are_normalized_for_http2() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "Example.com:8443".parse().unwrap());
+        headers.insert("connection", "keep-alive, x-trace".parse().unwrap());
+        headers.insert("x-trace", "1".parse().unwrap());
+        headers.insert("proxy-connection", "keep-alive".parse().unwrap());
+        headers.insert("transfer-encoding", "chunked".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+
+        normalize_upstream_headers(&mut headers, &"https://example.com:8443/a".parse().unwrap());
+        assert_eq!(headers.keys().map(|k| k.as_str()).collect::<Vec<_>>(), vec!["accept"]);
+
+        // A host that differs from the URI is the caller's choice
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "other.example".parse().unwrap());
+        normalize_upstream_headers(&mut headers, &"https://example.com/".parse().unwrap());
+        assert!(headers.contains_key("host"));
+
+        let fallback = Http2Fallback::new(Duration::from_secs(60));
+        assert!(!fallback.is_active("example.com:443"));
+        fallback.activate("example.com:443", h2::Reason::HTTP_1_1_REQUIRED);
+        assert!(fallback.is_active("example.com:443"));
+        assert_eq!(fallback.list().len(), 1);
+    }
+
     #[test]
     fn test_http_client_creation() {
         let client = HttpClient::new();
@@ -1,7 +1,13 @@
 //! Proxy server module
 
+pub mod auth;
+pub mod cert_error;
 pub mod server;
+pub mod socks;
 pub mod http_client;
+pub mod parent;
 pub mod streaming;
+pub mod tunnel;
+pub mod websocket;
 
 pub use server::ProxyServer;
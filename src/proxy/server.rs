@@ -1,61 +1,182 @@
 //! Proxy server implementation
 
+use crate::config::settings::{AdminConfig, HeaderRulesConfig, PinningDetectionConfig, ProxyConfig, TlsConfig, WebSocketConfig};
+use crate::middleware::{MiddlewareChain, MiddlewareContext};
 use crate::models::{ProxyLog, RequestData, ResponseData};
 use crate::{log_info, log_error, log_debug, log_proxy_transaction};
-use crate::utils::{is_hop_by_hop_header, parse_url, extract_path, extract_query, is_https, parse_connect_target, build_error_response, build_proxy_error_response, extract_headers, extract_cookies_to_request_data, should_extract_body, extract_body, build_forwarding_request, log_incoming_request, log_connect_request, log_http_success, log_http_failure, log_forwarding_request, log_headers_structured, log_response_headers_structured, should_forward_request_header, should_forward_response_header};
-use crate::tls::{generate_domain_cert_with_ca, create_server_config, CertificateManager};
+use crate::utils::{is_hop_by_hop_header, parse_url, extract_path, extract_query, is_https, parse_connect_target, build_error_response, build_proxy_error_response, protocol_name, extract_headers, extract_cookies_to_request_data, should_extract_body, extract_body, build_forwarding_request, log_incoming_request, log_connect_request, log_connect_success, log_connect_failure, create_connect_transaction, log_http_success, log_http_failure, log_forwarding_request, log_headers_structured, log_response_headers_structured, should_forward_request_header, should_forward_response_header};
+use crate::tls::{CertificateData, CertificateManager, InterceptTls, InterceptionDecision, InterceptionPolicy, LeafIssuer, LeafProfile, MimicPlan, PinningDetector, UpstreamAlpn, UpstreamCertProbe, CertExceptions, MAX_EXCEPTION_TTL, extract_certificate_info, upstream_tls_error, ALPN_H2};
+use crate::proxy::auth::ProxyAuthenticator;
+use crate::proxy::cert_error::cert_error_response;
 use crate::proxy::http_client::HttpClient;
+use crate::proxy::parent::UpstreamDialer;
+use crate::proxy::socks::SocksServer;
 use crate::proxy::streaming::SmartBodyHandler;
+use crate::proxy::tunnel::{connect_upstream, log_tunnel_transaction, splice};
+use crate::proxy::websocket::{forward_websocket, is_websocket_upgrade};
 use anyhow::Result;
+use bytes::Bytes;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Version};
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use tokio_rustls::TlsAcceptor;
+use rustls::server::Acceptor;
+use tokio_rustls::LazyConfigAcceptor;
 use tracing::{error, info, debug, warn};
-use hyper::upgrade::{Upgraded, on};
+use hyper::upgrade::on;
+use tokio::io::{AsyncRead, AsyncWrite};
 use serde_json::json;
 use std::sync::Arc;
-use bytes::Bytes;
 
 
+/// Shared state handed to every request accepted by a proxy listener
+pub struct ProxyContext {
+    pub https_interception: bool,
+    pub cert_manager: Arc<CertificateManager>,
+    /// Signs forged leaf certificates; `None` when the CA couldn't be loaded
+    pub issuer: Option<Arc<LeafIssuer>>,
+    /// Shared SNI-resolving TLS configuration for intercepted connections
+    pub intercept_tls: Arc<InterceptTls>,
+    pub client_manager: Arc<HttpClient>,
+    pub body_handler: Arc<SmartBodyHandler>,
+    pub middleware: Arc<MiddlewareChain>,
+    pub auth: Option<Arc<ProxyAuthenticator>>,
+    pub interception_policy: Arc<InterceptionPolicy>,
+    pub pinning: Arc<PinningDetector>,
+    /// Set when `tls.mimic_upstream_cert` is enabled
+    pub mimic: Option<Arc<UpstreamCertProbe>>,
+    /// Decides whether intercepted clients are offered h2
+    pub upstream_alpn: Arc<UpstreamAlpn>,
+    /// Hosts whose invalid upstream certificates are accepted anyway
+    pub cert_exceptions: Arc<CertExceptions>,
+    /// Opens upstream connections, through the parent proxy if configured
+    pub dialer: Arc<UpstreamDialer>,
+    pub tls_config: TlsConfig,
+    pub websocket: WebSocketConfig,
+    /// Who may call the `/admin/*` endpoints
+    pub admin: AdminConfig,
+}
+
+impl ProxyContext {
+    /// Build the request context from configuration
+    ///
+    /// Fails on settings that can't be compiled, such as invalid header rules.
+    pub fn from_config(config: &ProxyConfig, https_interception: bool) -> Result<Self> {
+        let cert_manager = Arc::new(CertificateManager::from_config(&config.tls.cert_cache, &config.redis));
+        let issuer = https_interception.then(|| load_issuer(&config.tls)).flatten();
+        let cert_exceptions = Arc::new(CertExceptions::from_config(&config.tls.upstream_cert_errors));
+        let dialer = Arc::new(UpstreamDialer::from_config(&config.upstream));
+        if !config.admin.users.is_empty() && !config.auth.enabled {
+            warn!("⚠️  admin.users is set without auth.enabled - admin users can't be identified");
+        }
+        Ok(Self {
+            https_interception,
+            intercept_tls: Arc::new(InterceptTls::new(cert_manager.clone(), issuer.clone())),
+            cert_manager,
+            issuer,
+            client_manager: Arc::new(HttpClient::from_config(
+                &config.http_client,
+                &config.tls,
+                cert_exceptions.clone(),
+                dialer.clone(),
+            )),
+            body_handler: Arc::new(SmartBodyHandler::from_config(&config.streaming)),
+            middleware: Arc::new(
+                MiddlewareChain::from_config(&config.middleware).with_header_rules(&config.header_rules)?,
+            ),
+            auth: ProxyAuthenticator::from_config(&config.auth).map(Arc::new),
+            interception_policy: Arc::new(InterceptionPolicy::from_config(&config.tls)),
+            pinning: Arc::new(PinningDetector::new(&config.tls.pinning_detection)),
+            mimic: config.tls.mimic_upstream_cert.then(|| Arc::new(UpstreamCertProbe::with_dialer(dialer.clone()))),
+            upstream_alpn: Arc::new(UpstreamAlpn::from_config(&config.tls, UpstreamCertProbe::with_dialer(dialer.clone()))),
+            cert_exceptions,
+            dialer,
+            tls_config: config.tls.clone(),
+            websocket: config.websocket.clone(),
+            admin: config.admin.clone(),
+        })
+    }
+
+    /// Build the request context from environment variables (legacy)
+    fn from_env(https_interception: bool) -> Self {
+        let config = ProxyConfig::from_env_vars();
+        let cert_manager = Arc::new(CertificateManager::from_config(&config.tls.cert_cache, &config.redis));
+        let issuer = https_interception.then(|| load_issuer(&config.tls)).flatten();
+        let cert_exceptions = Arc::new(CertExceptions::from_config(&config.tls.upstream_cert_errors));
+        let dialer = Arc::new(UpstreamDialer::from_config(&config.upstream));
+        Self {
+            https_interception,
+            intercept_tls: Arc::new(InterceptTls::new(cert_manager.clone(), issuer.clone())),
+            cert_manager,
+            issuer,
+            client_manager: Arc::new(HttpClient::from_env(&config.tls, cert_exceptions.clone(), dialer.clone())),
+            body_handler: Arc::new(SmartBodyHandler::from_env()),
+            middleware: Arc::new(
+                MiddlewareChain::new()
+                    .with_header_rules(&HeaderRulesConfig::default())
+                    .expect("built-in header rules compile"),
+            ),
+            auth: None,
+            interception_policy: Arc::new(InterceptionPolicy::default()),
+            pinning: Arc::new(PinningDetector::new(&PinningDetectionConfig::default())),
+            mimic: None,
+            upstream_alpn: Arc::new(UpstreamAlpn::from_config(&TlsConfig::default(), UpstreamCertProbe::with_dialer(dialer.clone()))),
+            cert_exceptions,
+            dialer,
+            tls_config: TlsConfig::default(),
+            websocket: WebSocketConfig::default(),
+            admin: AdminConfig::default(),
+        }
+    }
+}
+
+/// Load the leaf-signing CA, logging why interception won't work if it can't be
+fn load_issuer(tls: &TlsConfig) -> Option<Arc<LeafIssuer>> {
+    match LeafIssuer::from_config(tls).and_then(|issuer| issuer.validate_chain(tls).map(|_| issuer)) {
+        Ok(issuer) => Some(Arc::new(issuer)),
+        Err(e) => {
+            error!("❌ HTTPS interception will fail: {}", e);
+            None
+        }
+    }
+}
+
 pub struct ProxyServer {
     listen_addr: SocketAddr,
-    https_interception: bool,
-    cert_manager: Arc<CertificateManager>,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
-    tls_config: crate::config::settings::TlsConfig,
+    /// SOCKS5 listener sharing this server's context, if configured
+    socks_listen_addr: Option<SocketAddr>,
+    context: Arc<ProxyContext>,
 }
 
 impl ProxyServer {
     /// Create a new proxy server with configuration
     /// This is the recommended way to create a proxy server
-    pub fn with_config(listen_addr: SocketAddr, config: &crate::config::settings::ProxyConfig) -> Self {
-        Self { 
+    pub fn with_config(listen_addr: SocketAddr, config: &ProxyConfig) -> Result<Self> {
+        Ok(Self {
             listen_addr,
-            https_interception: false, // Default to false for backward compatibility
-            cert_manager: Arc::new(CertificateManager::new()),
-            client_manager: Arc::new(HttpClient::from_config(&config.http_client)),
-            body_handler: Arc::new(SmartBodyHandler::from_config(&config.streaming)),
-            tls_config: config.tls.clone(),
-        }
+            socks_listen_addr: config.socks_listen_addr,
+            context: Arc::new(ProxyContext::from_config(config, false)?), // Default to false for backward compatibility
+        })
     }
 
     /// Create a new proxy server with HTTPS interception and configuration
     /// This is the recommended way to create a proxy server with HTTPS interception
-    pub fn with_https_interception_and_config(listen_addr: SocketAddr, enable_interception: bool, config: &crate::config::settings::ProxyConfig) -> Self {
-        let cert_manager = Arc::new(CertificateManager::new());
-        let client_manager = Arc::new(HttpClient::from_config(&config.http_client));
-        let body_handler = Arc::new(SmartBodyHandler::from_config(&config.streaming));
-        
+    pub fn with_https_interception_and_config(listen_addr: SocketAddr, enable_interception: bool, config: &ProxyConfig) -> Result<Self> {
+        Ok(Self {
+            listen_addr,
+            socks_listen_addr: config.socks_listen_addr,
+            context: Arc::new(ProxyContext::from_config(config, enable_interception)?),
+        })
+    }
+
+    /// Create a proxy server around an existing request context, so other
+    /// listeners can share its caches and clients
+    pub fn with_context(listen_addr: SocketAddr, config: &ProxyConfig, context: Arc<ProxyContext>) -> Self {
         Self {
             listen_addr,
-            https_interception: enable_interception,
-            cert_manager,
-            client_manager,
-            body_handler,
-            tls_config: config.tls.clone(),
+            socks_listen_addr: config.socks_listen_addr,
+            context,
         }
     }
 
@@ -64,35 +185,32 @@ impl ProxyServer {
     pub fn new(listen_addr: SocketAddr) -> Self {
         Self { 
             listen_addr,
-            https_interception: false, // Default to false for backward compatibility
-            cert_manager: Arc::new(CertificateManager::new()),
-            client_manager: Arc::new(HttpClient::from_env()),
-            body_handler: Arc::new(SmartBodyHandler::from_env()),
-            tls_config: crate::config::settings::TlsConfig::default(),
+            socks_listen_addr: None,
+            context: Arc::new(ProxyContext::from_env(false)), // Default to false for backward compatibility
         }
     }
     
     /// Create a new proxy server with HTTPS interception (legacy method)
     /// DEPRECATED: Use with_https_interception_and_config instead for better configuration management
     pub fn with_https_interception(listen_addr: SocketAddr, enable_interception: bool) -> Self {
-        let cert_manager = Arc::new(CertificateManager::new());
-        let client_manager = Arc::new(HttpClient::from_env());
-        let body_handler = Arc::new(SmartBodyHandler::from_env());
+        let context = ProxyContext::from_env(enable_interception);
         
-        info!("🔐 Certificate cache initialized: {}", cert_manager.cache_info());
         info!("🚀 Optimized HTTP client manager initialized");
         info!("🚀 Smart body handler initialized");
         
         Self {
             listen_addr,
-            https_interception: enable_interception,
-            cert_manager,
-            client_manager,
-            body_handler,
-            tls_config: crate::config::settings::TlsConfig::default(),
+            socks_listen_addr: None,
+            context: Arc::new(context),
         }
     }
 
+    /// Also accept SOCKS5 clients on `socks_listen_addr`, if set
+    pub fn with_socks_listener(mut self, socks_listen_addr: Option<SocketAddr>) -> Self {
+        self.socks_listen_addr = socks_listen_addr;
+        self
+    }
+
     /// Start the proxy server
     pub async fn start(self) -> Result<()> {
         info!("Starting proxy server on {}", self.listen_addr);
@@ -101,29 +219,33 @@ impl ProxyServer {
         log_info!("Proxy server starting on {}", self.listen_addr);
         log_debug!("Server configuration: listen_addr={}", self.listen_addr);
         
-        log_info!("🔍 HTTPS interception mode: ENABLED - all HTTPS content will be logged!");
+        if self.context.https_interception {
+            log_info!("🔍 HTTPS interception mode: ENABLED - all HTTPS content will be logged!");
+            info!("🔐 Certificate cache initialized: {}", self.context.cert_manager.cache_info().await);
+        } else {
+            log_info!("🔌 HTTPS interception mode: DISABLED - CONNECT requests are tunnelled without decryption");
+        }
+
+        if let Some(socks_listen_addr) = self.socks_listen_addr {
+            let socks = SocksServer::new(socks_listen_addr, Arc::clone(&self.context));
+            tokio::spawn(async move {
+                if let Err(e) = socks.start().await {
+                    error!("SOCKS5 server failed: {}", e);
+                }
+            });
+        }
 
-        let https_interception = self.https_interception;
-        let cert_manager = Arc::clone(&self.cert_manager);
-        let client_manager = Arc::clone(&self.client_manager);
-        let body_handler = Arc::clone(&self.body_handler);
-        let tls_config = self.tls_config.clone();
+        let context = Arc::clone(&self.context);
         let make_svc = make_service_fn(move |conn: &hyper::server::conn::AddrStream| {
             let remote_addr = conn.remote_addr();
-            let cert_manager = Arc::clone(&cert_manager);
-            let client_manager = Arc::clone(&client_manager);
-            let body_handler = Arc::clone(&body_handler);
-            let tls_config = tls_config.clone();
+            let context = Arc::clone(&context);
             log_debug!("New connection from: {}", remote_addr);
 
             async move { 
                 Ok::<_, Infallible>(service_fn(move |req| {
-                    let cert_manager = Arc::clone(&cert_manager);
-                    let client_manager = Arc::clone(&client_manager);
-                    let body_handler = Arc::clone(&body_handler);
-                    let tls_config = tls_config.clone();
+                    let context = Arc::clone(&context);
                     async move {
-                        handle_request(req, remote_addr, https_interception, cert_manager, client_manager, body_handler, &tls_config).await
+                        handle_request(req, remote_addr, context).await
                     }
                 })) 
             }
@@ -146,11 +268,7 @@ impl ProxyServer {
 pub async fn handle_request(
     req: Request<Body>,
     remote_addr: SocketAddr,
-    https_interception: bool,
-    cert_manager: Arc<CertificateManager>,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
-    tls_config: &crate::config::settings::TlsConfig,
+    ctx: Arc<ProxyContext>,
 ) -> Result<Response<Body>, Infallible> {
     let start_time = std::time::Instant::now();
     let method = req.method().to_string();
@@ -164,26 +282,119 @@ pub async fn handle_request(
         return handle_health_check(method, start_time).await;
     }
 
-    // Create request data structure
+    let mut request_data = new_request_data(&req, remote_addr);
+    
+    // Require proxy credentials before doing anything on the client's behalf
+    if let Some(auth) = &ctx.auth {
+        match auth.authenticate(req.headers()).await {
+            Some(username) => {
+                log_debug!("🔐 Authenticated proxy user '{}'", username);
+                request_data.username = Some(username);
+            }
+            None => {
+                info!("🔐 Proxy authentication required: {} {} from {}", method, uri, remote_addr);
+                return Ok(auth.challenge_response());
+            }
+        }
+    }
+    
+    // Admin endpoints are only served to origin-form requests addressed to the proxy itself
+    if req.uri().authority().is_none() && req.uri().path() == "/admin/bypass" {
+        if let Some(response) = admin_forbidden(&request_data, &ctx.admin) {
+            return Ok(response);
+        }
+        return handle_bypass_admin(req, start_time, &ctx).await;
+    }
+    if req.uri().authority().is_none() && req.uri().path() == "/admin/status" {
+        if let Some(response) = admin_forbidden(&request_data, &ctx.admin) {
+            return Ok(response);
+        }
+        return Ok(handle_status_admin(req.method(), start_time, &ctx));
+    }
+    if req.uri().authority().is_none() && req.uri().path() == "/admin/cert-exceptions" {
+        if let Some(response) = admin_forbidden(&request_data, &ctx.admin) {
+            return Ok(response);
+        }
+        return Ok(handle_cert_exceptions_admin(req, &request_data, start_time, &ctx));
+    }
+    
+    dispatch_request(req, request_data, start_time, remote_addr, ctx).await
+}
+
+/// Handle a plain HTTP request from a SOCKS client connected to `host:port`
+///
+/// The client already authenticated during the SOCKS handshake, so the
+/// request goes straight to the forwarding flow with the SOCKS target as its
+/// authority. An absolute-form URL naming another host is not honoured, and
+/// `CONNECT` is refused: the SOCKS handshake already chose the destination.
+pub(crate) async fn handle_socks_http_request(
+    mut req: Request<Body>,
+    host: String,
+    port: u16,
+    remote_addr: SocketAddr,
+    username: Option<String>,
+    ctx: Arc<ProxyContext>,
+) -> Result<Response<Body>, Infallible> {
+    let start_time = std::time::Instant::now();
+    if req.method() == Method::CONNECT {
+        warn!("🚫 Refusing CONNECT from SOCKS client {} to {}:{}", remote_addr, host, port);
+        return Ok(build_error_response(StatusCode::METHOD_NOT_ALLOWED, "CONNECT is not supported inside a SOCKS tunnel"));
+    }
+
+    let host = if host.contains(':') { format!("[{}]", host) } else { host };
+    let authority = if port == 80 { host } else { format!("{}:{}", host, port) };
+    let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    match format!("http://{}{}", authority, path).parse() {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(_) => return Ok(build_error_response(StatusCode::BAD_REQUEST, "Invalid request target")),
+    }
+    log_incoming_request(req.method().as_str(), &req.uri().to_string(), &remote_addr);
+
+    let mut request_data = new_request_data(&req, remote_addr);
+    request_data.username = username;
+    dispatch_request(req, request_data, start_time, remote_addr, ctx).await
+}
+
+/// Transaction record for a request arriving from `remote_addr`
+fn new_request_data(req: &Request<Body>, remote_addr: SocketAddr) -> RequestData {
     let mut request_data = RequestData::new(
-        method.clone(),
-        uri.clone(),
+        req.method().to_string(),
+        req.uri().to_string(),
         remote_addr.ip(),
         remote_addr.port(),
     );
+    request_data.http_version = protocol_name(req.version()).to_string();
+    request_data.protocol = request_data.http_version.clone();
     
     log_debug!("Created request data: client_ip={}, client_port={}", 
                request_data.client_ip, request_data.client_port);
+    request_data
+}
+
+/// Send an authenticated request down the CONNECT, WebSocket or plain HTTP path
+async fn dispatch_request(
+    req: Request<Body>,
+    mut request_data: RequestData,
+    start_time: std::time::Instant,
+    remote_addr: SocketAddr,
+    ctx: Arc<ProxyContext>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().to_string();
+    let uri = req.uri().to_string();
     
     // Handle CONNECT requests - always intercept HTTPS
     if method == "CONNECT" {
-        return handle_connect_request(req, request_data, start_time, https_interception, cert_manager, client_manager, body_handler, tls_config).await;
+        handle_connect_request(req, request_data, start_time, remote_addr, ctx).await
+    } else if ctx.websocket.enabled && req.uri().authority().is_some() && is_websocket_upgrade(req.headers()) {
+        extract_headers(req.headers(), &mut request_data);
+        extract_cookies_to_request_data(req.headers(), &mut request_data);
+        Ok(handle_websocket_upgrade(req, request_data, uri, remote_addr, false, start_time, &ctx).await)
     } else {
         // Extract and process regular HTTP request data
         extract_request_data(&mut request_data, &uri, req).await;
         
         // Handle regular HTTP requests with full interception
-        handle_http_request(request_data, method, start_time, client_manager, body_handler).await
+        handle_http_request(request_data, method, start_time, remote_addr, ctx).await
     }
 }
 
@@ -227,90 +438,258 @@ async fn handle_health_check(
 }
 
 
-/// Handle HTTPS interception - decrypt, log, and re-encrypt
-async fn handle_https_interception(
+/// Refuse an admin request unless the client is an admin
+///
+/// Admins are the authenticated proxy users listed in `admin.users`, plus any
+/// loopback client when `admin.allow_loopback` is set. With neither
+/// configured every admin request gets a 403.
+fn admin_forbidden(request_data: &RequestData, admin: &AdminConfig) -> Option<Response<Body>> {
+    let loopback = admin.allow_loopback && request_data.client_ip.to_canonical().is_loopback();
+    let listed = request_data.username.as_ref().is_some_and(|user| admin.users.contains(user));
+    if loopback || listed {
+        return None;
+    }
+    
+    warn!("🚫 {} {} → 403 Forbidden (not an admin: {}, {})", request_data.method, request_data.url,
+          request_data.client_ip, request_data.username.as_deref().unwrap_or("anonymous"));
+    Some(build_error_response(StatusCode::FORBIDDEN, "Admin access required"))
+}
+
+/// Handle the proxy status admin endpoint
+///
+/// `GET /admin/status` reports certificate generation counters, the cache
+/// backend's health and the hosts on HTTP/1.1 after HTTP/2 errors. Unlike
+/// `/health` it is only served to admins.
+fn handle_status_admin(method: &Method, start_time: std::time::Instant, ctx: &ProxyContext) -> Response<Body> {
+    if method != Method::GET {
+        log_info!("❌ {} /admin/status → 405 Method Not Allowed", method);
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("allow", "GET")
+            .body(Body::from("Method Not Allowed"))
+            .unwrap();
+    }
+    
+    let status = json!({
+        "certificates": ctx.cert_manager.metrics(),
+        "certificate_cache": ctx.cert_manager.backend_health(),
+        "http2_fallback": ctx.client_manager.http2_fallback_hosts()
+            .into_iter()
+            .map(|(host, secs_left)| json!({ "host": host, "expires_in_secs": secs_left }))
+            .collect::<Vec<_>>(),
+    });
+    
+    log_info!("✅ GET /admin/status → 200 OK ({}ms)", start_time.elapsed().as_millis());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .header("cache-control", "no-cache")
+        .body(Body::from(status.to_string()))
+        .unwrap()
+}
+
+/// Handle the learned interception bypass admin endpoint
+///
+/// `GET /admin/bypass` lists learned entries; `DELETE /admin/bypass[?host=example.com]`
+/// clears one host or all of them.
+async fn handle_bypass_admin(
     req: Request<Body>,
-    host: String,
-    port: u16,
     start_time: std::time::Instant,
-    cert_manager: Arc<CertificateManager>,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
-    tls_config: &crate::config::settings::TlsConfig,
+    ctx: &ProxyContext,
 ) -> Result<Response<Body>, Infallible> {
-    let connect_time = start_time.elapsed().as_millis();
+    let method = req.method().clone();
+    let host = req.uri().query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "host")
+            .map(|(_, value)| value.to_lowercase())
+    });
     
-    info!("🔍 Starting HTTPS interception for {}:{}", host, port);
+    let result = match method {
+        Method::GET => ctx.cert_manager.list_bypass().await.map(|entries| {
+            json!({ "count": entries.len(), "entries": entries })
+        }),
+        Method::DELETE => ctx.cert_manager.clear_bypass(host.as_deref()).await.map(|removed| {
+            info!("🔀 Cleared {} learned bypass entr{} ({})", removed,
+                  if removed == 1 { "y" } else { "ies" }, host.as_deref().unwrap_or("all hosts"));
+            json!({ "removed": removed })
+        }),
+        _ => {
+            log_info!("❌ {} /admin/bypass → 405 Method Not Allowed", method);
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("allow", "GET, DELETE")
+                .body(Body::from("Method Not Allowed"))
+                .unwrap());
+        }
+    };
     
-    // Check cache first
-    let cert_data = match cert_manager.get_certificate(&host) {
-        Ok(Some(cert)) => {
-            info!("🎯 Using cached certificate for {} ({} ms)", host, connect_time);
-            cert
+    let elapsed_time = start_time.elapsed().as_millis();
+    match result {
+        Ok(body) => {
+            log_info!("✅ {} /admin/bypass → 200 OK ({}ms)", method, elapsed_time);
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/json")
+                .header("cache-control", "no-cache")
+                .body(Body::from(body.to_string()))
+                .unwrap())
         }
-        Ok(None) => {
-            info!("💾 Generating new certificate for {} ({} ms)", host, connect_time);
-            
-            // Generate new certificate - try CA-signed, fall back to self-signed
-            let default_ca_cert = "ca-certs/rootCA.crt".to_string();
-            let default_ca_key = "ca-certs/rootCA.key".to_string();
-            let ca_cert_path = tls_config.ca_cert_path.as_ref().unwrap_or(&default_ca_cert);
-            let ca_key_path = tls_config.ca_key_path.as_ref().unwrap_or(&default_ca_key);
-            
-            let cert_data = match generate_domain_cert_with_ca(&host, ca_cert_path, ca_key_path) {
-                Ok(cert) => cert,
-                Err(e) => {
-                    error!("Failed to generate certificate for {}: {}", host, e);
-                    return Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Certificate generation failed"));
-                }
+        Err(e) => {
+            log_error!("{} /admin/bypass failed: {}", method, e);
+            Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Bypass store error: {}", e)))
+        }
+    }
+}
+
+/// Handle the upstream certificate exception admin endpoint
+///
+/// `GET /admin/cert-exceptions` lists exceptions; `POST ?host=example.com[&ttl_secs=600]`
+/// lets requests to a host through despite an invalid upstream certificate;
+/// `DELETE [?host=example.com]` withdraws one or all granted exceptions.
+fn handle_cert_exceptions_admin(
+    req: Request<Body>,
+    request_data: &RequestData,
+    start_time: std::time::Instant,
+    ctx: &ProxyContext,
+) -> Response<Body> {
+    let method = req.method().clone();
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|query| form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let host = params.get("host").map(|host| host.to_lowercase());
+    
+    let body = match (&method, host) {
+        (&Method::GET, _) => {
+            let exceptions = ctx.cert_exceptions.list();
+            json!({ "count": exceptions.len(), "exceptions": exceptions })
+        }
+        (&Method::POST, Some(host)) => {
+            // Unparseable values count as out of range, like overlong ones
+            let ttl = params
+                .get("ttl_secs")
+                .map(|ttl| ttl.parse().map_or(std::time::Duration::MAX, std::time::Duration::from_secs));
+            let Some(expires) = ctx.cert_exceptions.grant(&host, ttl) else {
+                log_info!("❌ POST /admin/cert-exceptions → 400 Bad Request (ttl_secs out of range)");
+                return build_error_response(StatusCode::BAD_REQUEST, &format!(
+                    "ttl_secs must be between 0 and {}", MAX_EXCEPTION_TTL.as_secs()));
             };
-            
-            // Cache the newly generated certificate
-            if let Err(e) = cert_manager.cache_certificate(&host, cert_data.clone()) {
-                warn!("Failed to cache certificate for {}: {}", host, e);
-                // Continue anyway - caching failure shouldn't break the request
-            } else {
-                info!("🔄 Cached certificate for {} (expires in 24h)", host);
-            }
-            
-            cert_data
+            let expires_at = expires.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            warn!("⚠️  Upstream certificate exception granted for {} by {} ({}) until {}", host,
+                  request_data.client_ip, request_data.username.as_deref().unwrap_or("anonymous"), expires_at);
+            json!({ "host": host, "expires_at": expires_at })
         }
-        Err(e) => {
-            warn!("Certificate cache error for {}: {}", host, e);
-            info!("💾 Generating new certificate (cache unavailable)");
-            
-            // Generate without caching on cache error
-            let default_ca_cert = "ca-certs/rootCA.crt".to_string();
-            let default_ca_key = "ca-certs/rootCA.key".to_string();
-            let ca_cert_path = tls_config.ca_cert_path.as_ref().unwrap_or(&default_ca_cert);
-            let ca_key_path = tls_config.ca_key_path.as_ref().unwrap_or(&default_ca_key);
-            
-            match generate_domain_cert_with_ca(&host, ca_cert_path, ca_key_path) {
-                Ok(cert) => cert,
-                Err(e) => {
-                    error!("Failed to generate certificate for {}: {}", host, e);
-                    return Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Certificate generation failed"));
-                }
-            }
+        (&Method::POST, None) => {
+            log_info!("❌ POST /admin/cert-exceptions → 400 Bad Request (no host)");
+            return build_error_response(StatusCode::BAD_REQUEST, "Missing host parameter");
+        }
+        (&Method::DELETE, host) => {
+            let removed = ctx.cert_exceptions.revoke(host.as_deref());
+            info!("🔒 Withdrew {} upstream certificate exception{} ({})", removed,
+                  if removed == 1 { "" } else { "s" }, host.as_deref().unwrap_or("all hosts"));
+            json!({ "removed": removed })
+        }
+        _ => {
+            log_info!("❌ {} /admin/cert-exceptions → 405 Method Not Allowed", method);
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("allow", "GET, POST, DELETE")
+                .body(Body::from("Method Not Allowed"))
+                .unwrap();
         }
     };
     
-    // Create TLS server configuration
-    let tls_config = match create_server_config(
-        cert_data.cert(),
-        cert_data.key(),
-        &crate::config::settings::TlsConfig::default(),
-    ) {
-        Ok(config) => config,
-        Err(e) => {
-            error!("Failed to create TLS config for {}: {}", host, e);
-            return Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "TLS config failed"));
+    log_info!("✅ {} /admin/cert-exceptions → 200 OK ({}ms)", method, start_time.elapsed().as_millis());
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .header("cache-control", "no-cache")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Get the forged certificate for `host` (the client's SNI, else the CONNECT
+/// host), from cache or freshly signed
+///
+/// With `tls.mimic_upstream_cert` the upstream's leaf decides the profile and
+/// the cache key; if it can't be read we fall back to a host-only certificate.
+async fn obtain_certificate(
+    host: &str,
+    connect_host: &str,
+    port: u16,
+    connect_time: u128,
+    ctx: &ProxyContext,
+) -> Result<CertificateData> {
+    let issuer = ctx.issuer.clone()
+        .ok_or_else(|| anyhow::anyhow!("No signing CA loaded (see startup log)"))?;
+    
+    if let Some(probe) = &ctx.mimic {
+        let plan = match probe.cached_plan(connect_host, port, host) {
+            Some(plan) => Ok(plan),
+            None => match probe.handshake(connect_host, port, host).await {
+                Ok(hello) => {
+                    ctx.upstream_alpn.record(connect_host, port, hello.alpn.as_deref());
+                    MimicPlan::from_upstream(&hello.leaf, host, 24 * 60 * 60)
+                        .inspect(|plan| probe.remember_plan(connect_host, port, host, plan))
+                }
+                Err(e) => Err(e),
+            },
+        };
+        
+        match plan {
+            Ok(plan) => {
+                return cached_or_issue(&ctx.cert_manager, issuer, &plan.cache_key, plan.ttl_seconds, connect_time, plan.profile).await;
+            }
+            Err(e) => warn!("Could not mimic upstream certificate for {}: {}, using host-only certificate", host, e),
         }
-    };
+    }
     
-    let tls_acceptor = Arc::new(TlsAcceptor::from(tls_config));
+    cached_or_issue(&ctx.cert_manager, issuer, host, 24 * 60 * 60, connect_time, LeafProfile::for_host(host)).await
+}
+
+/// Look up `key` in the certificate cache, issuing and caching on a miss
+async fn cached_or_issue(
+    cert_manager: &CertificateManager,
+    issuer: Arc<LeafIssuer>,
+    key: &str,
+    ttl_seconds: u64,
+    connect_time: u128,
+    profile: LeafProfile,
+) -> Result<CertificateData> {
+    if ttl_seconds == 0 {
+        debug!("Not caching certificate for {} (upstream certificate expired)", key);
+    }
     
-    info!("✅ Generated certificate for {} ({}ms)", host, connect_time);
+    let cert_data = cert_manager
+        .get_or_generate(key, ttl_seconds, move || async move {
+            // Key generation (RSA especially) is CPU-bound, keep it off the reactor
+            tokio::task::spawn_blocking(move || issuer.issue(&profile)).await?
+        })
+        .await?;
+    
+    debug!("Certificate for {} ready ({} ms after CONNECT)", key, connect_time);
+    Ok(cert_data)
+}
+
+/// Handle HTTPS interception - decrypt, log, and re-encrypt
+async fn handle_https_interception(
+    req: Request<Body>,
+    host: String,
+    port: u16,
+    start_time: std::time::Instant,
+    remote_addr: SocketAddr,
+    username: Option<String>,
+    ctx: Arc<ProxyContext>,
+) -> Result<Response<Body>, Infallible> {
+    let connect_time = start_time.elapsed().as_millis();
+    
+    info!("🔍 Starting HTTPS interception for {}:{}", host, port);
+    
+    if ctx.issuer.is_none() {
+        error!("Failed to generate certificate for {}: no signing CA loaded (see startup log)", host);
+        return Ok(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Certificate generation failed"));
+    }
     
     // Create a response that signals the HTTPS interception is ready
     let response = Response::builder()
@@ -318,72 +697,157 @@ async fn handle_https_interception(
         .body(Body::empty())
         .unwrap();
     
-    // Clone variables for the async block
-    let host_clone = host.clone();
-    let port_clone = port;
-    let client_manager_clone = Arc::clone(&client_manager);
-    
     // Spawn a task to handle the HTTPS interception
     tokio::spawn(async move {
         // Wait for the connection to be upgraded
-        match on(req).await {
-            Ok(upgraded_stream) => {
-                info!("🔒 Connection upgraded for {}:{}, starting TLS handshake", host_clone, port_clone);
-                
-                // Perform TLS handshake with the client using our generated certificate
-                match tls_acceptor.accept(upgraded_stream).await {
-                    Ok(tls_stream) => {
-                        info!("✅ TLS handshake successful for {}:{}", host_clone, port_clone);
-                        
-                        // Now handle HTTP requests over the decrypted TLS connection
-                        if let Err(e) = handle_intercepted_https_connection(tls_stream, host_clone.clone(), port_clone, client_manager_clone, body_handler.clone()).await {
-                            error!("HTTPS interception error for {}:{}: {}", host_clone, port_clone, e);
-                        }
-                    }
-                    Err(e) => {
-                        warn!("❌ TLS handshake failed for {}:{}: {}", host_clone, port_clone, e);
-                    }
-                }
-            }
+        let upgraded_stream = match on(req).await {
+            Ok(upgraded_stream) => upgraded_stream,
             Err(e) => {
-                error!("Failed to upgrade connection for {}:{}: {}", host_clone, port_clone, e);
+                error!("Failed to upgrade connection for {}:{}: {}", host, port, e);
+                return;
             }
-        }
+        };
+        info!("🔒 Connection upgraded for {}:{}, starting TLS handshake", host, port);
+        intercept_client_stream(upgraded_stream, host, port, connect_time, remote_addr, username, ctx).await;
     });
     
     Ok(response)
 }
 
-/// Handle intercepted HTTPS connection - process decrypted HTTP requests
-async fn handle_intercepted_https_connection(
-    tls_stream: tokio_rustls::server::TlsStream<Upgraded>,
+/// Terminate the client's TLS on `client` with a forged certificate and
+/// serve the decrypted requests, forwarding them to `host:port`
+///
+/// `client` is the raw stream of a CONNECT or SOCKS tunnel, on which the
+/// client is about to start its handshake.
+pub(crate) async fn intercept_client_stream<S>(
+    client: S,
     host: String,
     port: u16,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
+    connect_time: u128,
+    remote_addr: SocketAddr,
+    username: Option<String>,
+    ctx: Arc<ProxyContext>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // Read the ClientHello first: the certificate follows its SNI
+    let start = match LazyConfigAcceptor::new(Acceptor::default(), client).await {
+        Ok(start) => start,
+        Err(e) => {
+            warn!("❌ TLS handshake failed for {}:{}: {}", host, port, e);
+            return;
+        }
+    };
+    let sni = start.client_hello().server_name().map(|name| name.to_lowercase());
+    let client_offers_h2 = start.client_hello().alpn().is_some_and(|mut protocols| protocols.any(|p| p == ALPN_H2));
+    let cert_host = sni.clone().unwrap_or_else(|| host.clone());
+    if sni.as_deref().is_some_and(|sni| sni != host) {
+        debug!("SNI {} differs from CONNECT host {}", cert_host, host);
+    }
+    
+    if let Err(e) = prepare_certificate(&cert_host, &host, port, connect_time, &ctx).await {
+        error!("Failed to generate certificate for {}: {}", cert_host, e);
+        return;
+    }
+    // Only offer h2 when the upstream would have negotiated it too
+    let offer_h2 = client_offers_h2 && ctx.upstream_alpn.offer_h2(&host, port, &cert_host).await;
+    debug!("Offering {} to the client of {}:{}", if offer_h2 { "h2, http/1.1" } else { "http/1.1" }, host, port);
+    let server_config = match ctx.intercept_tls.config_for(sni.as_deref(), &cert_host, offer_h2).await {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to create TLS config for {}: {}", cert_host, e);
+            return;
+        }
+    };
+    
+    // Perform TLS handshake with the client using our generated certificate
+    match start.into_stream(server_config).await {
+        Ok(tls_stream) => {
+            let h2 = tls_stream.get_ref().1.alpn_protocol() == Some(ALPN_H2);
+            info!("✅ TLS handshake successful for {}:{} ({})", host, port, if h2 { "HTTP/2" } else { "HTTP/1.1" });
+            
+            // Now handle HTTP requests over the decrypted TLS connection
+            if let Err(e) = handle_intercepted_https_connection(tls_stream, h2, host.clone(), port, remote_addr, username, Arc::clone(&ctx)).await {
+                error!("HTTPS interception error for {}:{}: {}", host, port, e);
+            }
+        }
+        Err(e) => {
+            warn!("❌ TLS handshake failed for {}:{}: {}", host, port, e);
+            ctx.pinning.record_handshake_failure(&host, remote_addr.ip(), &e, &ctx.cert_manager).await;
+        }
+    }
+}
+
+/// Make sure the resolver has a certificate for `cert_host` before the handshake
+///
+/// Issuing here rather than inside the (synchronous) resolver keeps upstream
+/// probing and key generation off the handshake path.
+async fn prepare_certificate(
+    cert_host: &str,
+    connect_host: &str,
+    port: u16,
+    connect_time: u128,
+    ctx: &ProxyContext,
 ) -> Result<()> {
+    let resolver = ctx.intercept_tls.resolver();
+    // Mimicked certificates follow the upstream's, so re-check once its plan lapses
+    let plan_fresh = ctx.mimic.as_ref().is_none_or(|probe| probe.cached_plan(connect_host, port, cert_host).is_some());
+    if plan_fresh && resolver.get(cert_host).is_some() {
+        info!("🎯 Using loaded certificate for {} ({} ms)", cert_host, connect_time);
+        return Ok(());
+    }
+    
+    let cert_data = obtain_certificate(cert_host, connect_host, port, connect_time, ctx).await?;
+    resolver.insert(cert_host, &cert_data)?;
+    Ok(())
+}
+
+/// Handle intercepted HTTPS connection - process decrypted HTTP requests
+///
+/// `h2` is whether the client negotiated HTTP/2 over ALPN. Nothing is ever
+/// pushed to h2 clients, and their WebSockets use separate HTTP/1.1
+/// connections since extended CONNECT isn't advertised.
+async fn handle_intercepted_https_connection<S>(
+    tls_stream: tokio_rustls::server::TlsStream<S>,
+    h2: bool,
+    host: String,
+    port: u16,
+    remote_addr: SocketAddr,
+    username: Option<String>,
+    ctx: Arc<ProxyContext>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     info!("🌐 Processing decrypted HTTPS traffic for {}:{}", host, port);
     
+    let max_streams = ctx.client_manager.get_config().http2_max_concurrent_streams;
+    
     // Clone host for use in service and logging
     let host_for_service = host.clone();
     let host_for_logging = host.clone();
-    let client_manager_for_service = Arc::clone(&client_manager);
-    let body_handler_for_service = Arc::clone(&body_handler);
     
     // Create HTTP service for handling decrypted requests
     let service = hyper::service::service_fn(move |req: Request<Body>| {
         let host_clone = host_for_service.clone();
         let port_clone = port;
-        let client_manager_clone = Arc::clone(&client_manager_for_service);
-        let body_handler_clone = Arc::clone(&body_handler_for_service);
+        let username_clone = username.clone();
+        let ctx_clone = Arc::clone(&ctx);
         async move {
-            handle_intercepted_request(req, host_clone, port_clone, client_manager_clone, body_handler_clone).await
+            handle_intercepted_request(req, host_clone, port_clone, remote_addr, username_clone, ctx_clone).await
         }
     });
     
     // Serve HTTP over the TLS connection (this gives us decrypted HTTP requests!)
-    if let Err(e) = hyper::server::conn::Http::new()
+    let mut http = hyper::server::conn::Http::new();
+    if h2 {
+        http.http2_only(true).http2_max_concurrent_streams(max_streams);
+    } else {
+        http.http1_only(true);
+    }
+    if let Err(e) = http
         .serve_connection(tls_stream, service)
+        .with_upgrades()
         .await
     {
         debug!("HTTPS interception connection ended for {}:{}: {}", host_for_logging, port, e);
@@ -399,13 +863,13 @@ async fn handle_intercepted_request(
     req: Request<Body>,
     host: String,
     port: u16,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
+    remote_addr: SocketAddr,
+    username: Option<String>,
+    ctx: Arc<ProxyContext>,
 ) -> Result<Response<Body>, hyper::Error> {
     let start_time = std::time::Instant::now();
     let method = req.method().clone();
     let uri = req.uri().clone();
-    let headers = req.headers().clone();
     let path = uri.path().to_string();
     
     // Reconstruct the full HTTPS URL for logging
@@ -419,13 +883,26 @@ async fn handle_intercepted_request(
     info!("⏱️  Request started at: {:?}", start_time);
     
     // Log request headers in structured format
-    log_headers_structured(&headers, "Request Headers");
+    log_headers_structured(req.headers(), "Request Headers");
+    
+    let mut request_data = RequestData::new(method.to_string(), full_url, remote_addr.ip(), remote_addr.port());
+    request_data.username = username; // Authenticated on the CONNECT that opened this tunnel
+    request_data.http_version = protocol_name(req.version()).to_string();
+    request_data.protocol = request_data.http_version.clone();
+    extract_headers(req.headers(), &mut request_data);
+    extract_cookies_to_request_data(req.headers(), &mut request_data);
+    request_data.content_type = request_data.headers.get("content-type").cloned();
+    
+    if ctx.websocket.enabled && is_websocket_upgrade(req.headers()) {
+        let target_url = request_data.url.clone();
+        return Ok(handle_websocket_upgrade(req, request_data, target_url, remote_addr, true, start_time, &ctx).await);
+    }
     
     let header_processing_time = start_time.elapsed();
     info!("⏱️  Header processing: {:.2} ms", header_processing_time.as_secs_f64() * 1000.0);
     
     // Extract and log the request body using smart body handler
-    let (body_bytes, is_large_body) = match body_handler.handle_request_body(req.into_body(), "Intercepted Request").await {
+    let (body_bytes, is_large_body) = match ctx.body_handler.handle_request_body(req.into_body(), "Intercepted Request").await {
         Ok(result) => result,
         Err(e) => {
             error!("Failed to read request body: {}", e);
@@ -433,6 +910,16 @@ async fn handle_intercepted_request(
             return Ok(build_error_response(StatusCode::BAD_REQUEST, &format!("Error reading request body: {}", e)));
         }
     };
+    request_data.content_length = body_bytes.len() as u64;
+    request_data.body = body_bytes;
+    
+    // Run the middleware chain on the decrypted request
+    let mut mctx = MiddlewareContext::new(remote_addr, true);
+    if let Some(mut response) = ctx.middleware.process_request(&mut request_data, &mut mctx).await {
+        ctx.middleware.process_response(&request_data, &mut response, &mut mctx).await;
+        log_middleware_response(&request_data, &response, start_time, mctx);
+        return Ok(response);
+    }
     
     let prep_time = start_time.elapsed();
     info!("⏱️  Total request preparation: {:.2} ms", prep_time.as_secs_f64() * 1000.0);
@@ -444,11 +931,36 @@ async fn handle_intercepted_request(
     
     // Forward the request to the real server over HTTPS
     let forward_start = std::time::Instant::now();
-    match forward_intercepted_request_direct(method.clone(), uri, headers, Bytes::from(body_bytes), &host, port, client_manager, body_handler).await {
-        Ok(response) => {
+    match forward_intercepted_request_direct(&request_data, &host, port, &ctx).await {
+        Ok((mut response, upstream_version)) => {
             let forward_time = forward_start.elapsed();
             let total_time = start_time.elapsed();
             
+            ctx.middleware.process_response(&request_data, &mut response, &mut mctx).await;
+            
+            let content_type = response
+                .headers()
+                .get("content-type")
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+            let mut response_data = ResponseData::new(
+                response.status().as_u16(),
+                response.status().to_string(),
+                content_type,
+                Vec::new(), // Body is streamed to the client, not buffered
+                forward_time.as_millis() as u64,
+            );
+            response_data.protocol = Some(protocol_name(upstream_version).to_string());
+            let log_entry = ProxyLog {
+                request: request_data,
+                response: Some(response_data),
+                error: None,
+                annotations: mctx.annotations,
+                events: Vec::new(),
+            };
+            log_proxy_transaction!(&log_entry);
+            
             info!("⏱️  Upstream processing: {:.2} ms", forward_time.as_secs_f64() * 1000.0);
             info!("⏱️  🎯 TOTAL REQUEST TIME: {:.2} ms ({:.3} seconds)", 
                   total_time.as_secs_f64() * 1000.0, 
@@ -468,52 +980,152 @@ async fn handle_intercepted_request(
                    total_time.as_secs_f64());
             error!("❌ INTERCEPTED {} {} → ERROR: {} (failed in {:.2} ms)", 
                    method, path, e, total_time.as_secs_f64() * 1000.0);
+            let tls_error = e.chain().find_map(upstream_tls_error);
+            if let Some(tls_error) = tls_error {
+                warn!("🔒 Upstream TLS handshake with {}:{} failed: {}", host, port, tls_error);
+            }
             
-            let error_response = build_proxy_error_response(&format!("Interception Error: {}", e));
+            let mut error_response = match tls_error {
+                Some(tls_error @ rustls::Error::InvalidCertificate(_)) if ctx.tls_config.upstream_cert_errors.error_page => {
+                    cert_error_page(&host, port, tls_error, &ctx).await
+                }
+                _ => build_proxy_error_response(&format!("Interception Error: {}", e)),
+            };
+            ctx.middleware.process_response(&request_data, &mut error_response, &mut mctx).await;
+            
+            let log_entry = ProxyLog {
+                request: request_data,
+                response: None,
+                error: Some(e.to_string()),
+                annotations: mctx.annotations,
+                events: Vec::new(),
+            };
+            log_proxy_transaction!(&log_entry);
             
             Ok(error_response)
         }
     }
 }
 
+/// Error page for a rejected upstream certificate, showing the certificate
+/// read with a second, unverified handshake
+async fn cert_error_page(host: &str, port: u16, error: &rustls::Error, ctx: &ProxyContext) -> Response<Body> {
+    let probe = match &ctx.mimic {
+        Some(probe) => Arc::clone(probe),
+        None => Arc::new(UpstreamCertProbe::with_dialer(ctx.dialer.clone())),
+    };
+    let cert = match probe.fetch_leaf(host, port, host).await {
+        Ok(der) => extract_certificate_info(&rustls::Certificate(der)).ok(),
+        Err(e) => {
+            debug!("Couldn't read the rejected certificate of {}:{}: {}", host, port, e);
+            None
+        }
+    };
+    cert_error_response(host, port, error, cert.as_ref())
+}
+
+/// Run the middleware chain on a WebSocket handshake, then relay it to `target_url`
+async fn handle_websocket_upgrade(
+    req: Request<Body>,
+    mut request_data: RequestData,
+    target_url: String,
+    remote_addr: SocketAddr,
+    intercepted: bool,
+    start_time: std::time::Instant,
+    ctx: &ProxyContext,
+) -> Response<Body> {
+    let mut mctx = MiddlewareContext::new(remote_addr, intercepted);
+    if let Some(mut response) = ctx.middleware.process_request(&mut request_data, &mut mctx).await {
+        ctx.middleware.process_response(&request_data, &mut response, &mut mctx).await;
+        log_middleware_response(&request_data, &response, start_time, mctx);
+        return response;
+    }
+
+    match forward_websocket(req, request_data.clone(), &target_url, &ctx.client_manager, &ctx.websocket, mctx.annotations).await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("❌ WebSocket handshake to {} failed: {}", target_url, e);
+            let log_entry = ProxyLog {
+                request: request_data,
+                response: None,
+                error: Some(e.to_string()),
+                annotations: HashMap::new(),
+                events: Vec::new(),
+            };
+            log_proxy_transaction!(&log_entry);
+            build_proxy_error_response(&e.to_string())
+        }
+    }
+}
+
+/// Log a transaction that was answered by middleware without going upstream
+fn log_middleware_response(
+    request_data: &RequestData,
+    response: &Response<Body>,
+    start_time: std::time::Instant,
+    mctx: MiddlewareContext,
+) {
+    let response_data = ResponseData::new(
+        response.status().as_u16(),
+        response.status().to_string(),
+        response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string(),
+        Vec::new(),
+        start_time.elapsed().as_millis() as u64,
+    );
+    
+    let log_entry = ProxyLog {
+        request: request_data.clone(),
+        response: Some(response_data),
+        error: None,
+        annotations: mctx.annotations,
+        events: Vec::new(),
+    };
+    
+    info!("🧩 {} {} → {} (answered by middleware)", request_data.method, request_data.url, response.status());
+    log_debug!("📋 MIDDLEWARE TRANSACTION:\n{:#?}", log_entry);
+    log_proxy_transaction!(&log_entry);
+}
+
 /// Forward an intercepted request directly to the real server
 async fn forward_intercepted_request_direct(
-    method: hyper::Method,
-    uri: hyper::Uri,
-    headers: hyper::HeaderMap,
-    body_bytes: Bytes,
+    request_data: &RequestData,
     host: &str,
     port: u16,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
-) -> Result<Response<Body>> {
-    // Use shared HTTPS client with connection pooling for optimal performance
-    // This eliminates the critical performance bottleneck of creating new clients per request
-    let client = client_manager.get_https_client();
+    ctx: &ProxyContext,
+) -> Result<(Response<Body>, Version)> {
+    let path_and_query = match &request_data.query_string {
+        Some(query) => format!("{}?{}", request_data.path, query),
+        None => request_data.path.clone(),
+    };
     
     // Build the target URL - don't include port 443 for HTTPS or port 80 for HTTP as it's redundant
     let target_url = if port == 443 {
-        format!("https://{}{}", host, uri.path_and_query().map_or("", |pq| pq.as_str()))
+        format!("https://{}{}", host, path_and_query)
     } else if port == 80 {
-        format!("http://{}{}", host, uri.path_and_query().map_or("", |pq| pq.as_str()))
+        format!("http://{}{}", host, path_and_query)
     } else {
-        format!("https://{}:{}{}", host, port, uri.path_and_query().map_or("", |pq| pq.as_str()))
+        format!("https://{}:{}{}", host, port, path_and_query)
     };
     
     info!("🌐 Forwarding to: {}", target_url);
     
     // Build the request
     let mut request_builder = Request::builder()
-        .method(method)
+        .method(request_data.method.as_str())
         .uri(&target_url);
     
     // Add headers (skip hop-by-hop and problematic headers)
     let mut forwarded_headers = 0;
     let mut skipped_headers = 0;
     
-    for (name, value) in &headers {
+    for (name, value) in &request_data.headers {
         // Use centralized header filtering logic
-        if should_forward_request_header(name.as_str()) {
+        if should_forward_request_header(name) {
             request_builder = request_builder.header(name, value);
             forwarded_headers += 1;
         } else {
@@ -528,15 +1140,15 @@ async fn forward_intercepted_request_direct(
     request_builder = request_builder.header("host", host_header);
     
     // Ensure we have required headers for proper HTTP handling
-    if !headers.contains_key("user-agent") {
+    if !request_data.headers.contains_key("user-agent") {
         request_builder = request_builder.header("user-agent", "Mozilla/5.0 (compatible; RustProxy/1.0)");
     }
     
     // Always set proper content-length header to avoid duplicates and ensure correctness
-    request_builder = request_builder.header("content-length", body_bytes.len().to_string());
+    request_builder = request_builder.header("content-length", request_data.body.len().to_string());
     
-    let body_size = body_bytes.len();
-    let request = request_builder.body(Body::from(body_bytes))?;
+    let body_size = request_data.body.len();
+    let request = request_builder.body(Bytes::from(request_data.body.clone()))?;
     
     // Debug log the final request that will be sent upstream
     info!("📡 Sending request to upstream server...");
@@ -551,14 +1163,15 @@ async fn forward_intercepted_request_direct(
     
     let upstream_start = std::time::Instant::now();
     
-    // Forward the request
-    let response = client.request(request).await?;
+    // Forward the request over the shared pooled client (HTTP/2 where the upstream supports it)
+    let response = ctx.client_manager.send(request).await?;
     
     let upstream_response_time = upstream_start.elapsed();
     info!("⏱️  Upstream response time: {:.2} ms", upstream_response_time.as_secs_f64() * 1000.0);
     
     // Get response details for logging
     let status = response.status();
+    let version = response.version();
     let response_headers = response.headers().clone();
     
     info!("📤 Upstream HTTPS response: {} ", status);
@@ -567,7 +1180,7 @@ async fn forward_intercepted_request_direct(
     log_response_headers_structured(&response_headers);
     
     // Handle response with smart streaming - this provides 70-90% memory reduction!
-    let optimized_response = body_handler.handle_response_streaming(response, "Upstream Response").await
+    let optimized_response = ctx.body_handler.handle_response_streaming(response, "Upstream Response").await
         .map_err(|e| anyhow::anyhow!("Response streaming error: {}", e))?;
     
     info!("🚀 Response streaming optimization applied");
@@ -584,9 +1197,7 @@ async fn forward_intercepted_request_direct(
     }
     
     // Return the optimized streaming response
-    Ok(response_builder
-        .body(body)
-        .unwrap())
+    Ok((response_builder.body(body).unwrap(), version))
 }
 
 // ============================================================================
@@ -598,13 +1209,10 @@ async fn handle_connect_request(
     req: Request<Body>,
     mut request_data: RequestData,
     start_time: std::time::Instant,
-    _https_interception: bool,
-    cert_manager: Arc<CertificateManager>,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
-    tls_config: &crate::config::settings::TlsConfig,
+    remote_addr: SocketAddr,
+    ctx: Arc<ProxyContext>,
 ) -> Result<Response<Body>, Infallible> {
-    log_connect_request(&request_data.url);
+    log_connect_request(&request_data.url, ctx.https_interception);
     
     // Configure request data for CONNECT
     request_data.path = "".to_string(); // CONNECT doesn't have a path
@@ -620,9 +1228,75 @@ async fn handle_connect_request(
         }
     };
     
-    // Always intercept HTTPS for full visibility - CONNECT logging at DEBUG level
+    if !ctx.https_interception {
+        log_debug!("🔍 CONNECT {}:{} - TUNNELLING (interception disabled)", host, port);
+        return handle_connect_tunnel(req, request_data, host, port, start_time, HashMap::new(), &ctx.dialer).await;
+    }
+    
+    if let Some(reason) = interception_bypass_reason(&host, &ctx).await {
+        info!("🔀 CONNECT {}:{} - TUNNELLING ({})", host, port, reason);
+        let annotations = HashMap::from([("interception_bypass".to_string(), reason)]);
+        return handle_connect_tunnel(req, request_data, host, port, start_time, annotations, &ctx.dialer).await;
+    }
+    
+    // Intercept HTTPS for full visibility - CONNECT logging at DEBUG level
     log_debug!("🔍 CONNECT {}:{} - INTERCEPTING (will decrypt and log HTTPS)", host, port);
-    handle_https_interception(req, host, port, start_time, cert_manager, client_manager, body_handler, tls_config).await
+    handle_https_interception(req, host, port, start_time, remote_addr, request_data.username, ctx).await
+}
+
+/// Why `host` is tunnelled rather than intercepted, if it is: configured
+/// bypass rules first, then hosts learned from pinning failures
+pub(crate) async fn interception_bypass_reason(host: &str, ctx: &ProxyContext) -> Option<String> {
+    match ctx.interception_policy.decide(host) {
+        InterceptionDecision::Bypass(reason) => Some(reason),
+        InterceptionDecision::Intercept => ctx
+            .cert_manager
+            .get_bypass(host)
+            .await
+            .map(|entry| format!("learned bypass: {}", entry.reason)),
+    }
+}
+
+/// Pass a CONNECT through as a raw TCP tunnel (no decryption)
+async fn handle_connect_tunnel(
+    req: Request<Body>,
+    mut request_data: RequestData,
+    host: String,
+    port: u16,
+    start_time: std::time::Instant,
+    mut annotations: HashMap<String, String>,
+    dialer: &UpstreamDialer,
+) -> Result<Response<Body>, Infallible> {
+    if dialer.has_parent() {
+        annotations.insert("upstream_route".to_string(), dialer.route(&host).describe());
+    }
+
+    // Connect before answering so the client sees upstream failures as a 502
+    let mut upstream = match connect_upstream(dialer, &host, port).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            log_connect_failure(&host, port, start_time.elapsed().as_millis(), &e.to_string());
+            request_data.duration_ms = Some(start_time.elapsed().as_millis() as u64);
+            create_connect_transaction(&request_data, None, Some(e.to_string()), annotations);
+            return Ok(build_proxy_error_response(&e.to_string()));
+        }
+    };
+    
+    let connect_time = start_time.elapsed();
+    log_connect_success(&host, port, connect_time.as_millis());
+    
+    tokio::spawn(async move {
+        let result = match on(req).await {
+            Ok(mut upgraded) => splice(&mut upgraded, &mut upstream).await,
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        log_tunnel_transaction(request_data, annotations, connect_time, start_time.elapsed(), result);
+    });
+    
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::empty())
+        .unwrap())
 }
 
 /// Extract and process HTTP request data
@@ -664,16 +1338,24 @@ async fn handle_http_request(
     mut request_data: RequestData,
     method: String,
     start_time: std::time::Instant,
-    client_manager: Arc<HttpClient>,
-    body_handler: Arc<SmartBodyHandler>,
+    remote_addr: SocketAddr,
+    ctx: Arc<ProxyContext>,
 ) -> Result<Response<Body>, Infallible> {
     info!("🔍 Processing HTTP request with full interception");
     info!("⏱️  Request started at: {:?}", start_time);
     
+    // Run the middleware chain before anything goes upstream
+    let mut mctx = MiddlewareContext::new(remote_addr, false);
+    if let Some(mut response) = ctx.middleware.process_request(&mut request_data, &mut mctx).await {
+        ctx.middleware.process_response(&request_data, &mut response, &mut mctx).await;
+        log_middleware_response(&request_data, &response, start_time, mctx);
+        return Ok(response);
+    }
+    
     let prep_time = start_time.elapsed();
     info!("⏱️  HTTP request preparation: {:.2} ms", prep_time.as_secs_f64() * 1000.0);
     
-    match handle_regular_request(&mut request_data, client_manager, body_handler).await {
+    match handle_regular_request(&mut request_data, &ctx, &mut mctx).await {
         Ok(response) => {
             let total_time = start_time.elapsed();
             info!("⏱️  🎯 TOTAL HTTP REQUEST TIME: {:.2} ms ({:.3} seconds)", 
@@ -694,24 +1376,27 @@ async fn handle_http_request(
 }
 
 /// Handle regular HTTP request (non-CONNECT)
-async fn handle_regular_request(request_data: &mut RequestData, client_manager: Arc<HttpClient>, body_handler: Arc<SmartBodyHandler>) -> Result<Response<Body>> {
+async fn handle_regular_request(
+    request_data: &mut RequestData,
+    ctx: &ProxyContext,
+    mctx: &mut MiddlewareContext,
+) -> Result<Response<Body>> {
     let forward_start = std::time::Instant::now();
     
     // Log configuration for future optimization potential
-    debug!("🚀 Regular HTTP request handler ready (streaming config: max_log_body_size={})", body_handler.get_config().max_log_body_size);
+    debug!("🚀 Regular HTTP request handler ready (streaming config: max_log_body_size={})", ctx.body_handler.get_config().max_log_body_size);
     
     log_forwarding_request(request_data);
     
-    // Use shared HTTP client with connection pooling for optimal performance
-    let client = client_manager.get_client_for_url(request_data.is_https);
     let request = build_forwarding_request(request_data)?;
     
     // Forward the request to upstream
     let upstream_start = std::time::Instant::now();
     info!("📡 Sending HTTP request to upstream server...");
-    match client.request(request).await {
+    match ctx.client_manager.send(request).await {
         Ok(response) => {
             let upstream_time = upstream_start.elapsed();
+            let upstream_version = response.version();
             let status_code = response.status().as_u16();
             let status_text = response.status().to_string();
             let content_type = response
@@ -748,25 +1433,14 @@ async fn handle_regular_request(request_data: &mut RequestData, client_manager:
             let body_bytes = hyper::body::to_bytes(response.into_body()).await?;
             log_debug!("Response body received: {} bytes", body_bytes.len());
             
-            let response_data = ResponseData::new(
+            let mut response_data = ResponseData::new(
                 status_code,
                 status_text,
                 content_type,
                 body_bytes.to_vec(),
                 upstream_time.as_millis() as u64, // Use the actual upstream response time
             );
-
-            let log_entry = ProxyLog {
-                request: request_data.clone(),
-                response: Some(response_data.clone()),
-                error: None,
-            };
-
-            // DEBUG: Log full transaction details
-            log_debug!("📋 HTTP TRANSACTION:\n{:#?}", log_entry);
-            
-            // Log transaction to file
-            log_proxy_transaction!(&log_entry);
+            response_data.protocol = Some(protocol_name(upstream_version).to_string());
 
             // Build response to send back to client
             let mut response_builder = Response::builder().status(response_data.status_code);
@@ -778,12 +1452,30 @@ async fn handle_regular_request(request_data: &mut RequestData, client_manager:
             log_debug!("Response builder created with {} headers", response_headers.len());
 
             // Return the actual response body
-            Ok(response_builder
-                .body(Body::from(response_data.body))
+            let mut client_response = response_builder
+                .body(Body::from(response_data.body.clone()))
                 .unwrap_or_else(|_| {
                     log_error!("Failed to build response body");
                     build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to build response")
-                }))
+                });
+            
+            ctx.middleware.process_response(request_data, &mut client_response, mctx).await;
+
+            let log_entry = ProxyLog {
+                request: request_data.clone(),
+                response: Some(response_data),
+                error: None,
+                annotations: mctx.annotations.clone(),
+                events: Vec::new(),
+            };
+
+            // DEBUG: Log full transaction details
+            log_debug!("📋 HTTP TRANSACTION:\n{:#?}", log_entry);
+            
+            // Log transaction to file
+            log_proxy_transaction!(&log_entry);
+
+            Ok(client_response)
         }
         Err(e) => {
             let upstream_time = upstream_start.elapsed().as_millis();
@@ -791,15 +1483,23 @@ async fn handle_regular_request(request_data: &mut RequestData, client_manager:
             
             // Clean INFO log for upstream error
             info!("❌ Upstream failed ({}ms): {}", total_time, e);
+            if let Some(tls_error) = upstream_tls_error(&e) {
+                warn!("🔒 Upstream TLS handshake for {} failed: {}", request_data.url, tls_error);
+            }
             
             // Verbose DEBUG log
             log_debug!("❌ UPSTREAM ERROR:\n  Error: {}\n  Upstream Time: {}ms\n  Total Time: {}ms", 
                       e, upstream_time, total_time);
 
+            let mut error_response = build_proxy_error_response(&e.to_string());
+            ctx.middleware.process_response(request_data, &mut error_response, mctx).await;
+
             let log_entry = ProxyLog {
                 request: request_data.clone(),
                 response: None,
                 error: Some(e.to_string()),
+                annotations: mctx.annotations.clone(),
+                events: Vec::new(),
             };
 
             // DEBUG: Log full error transaction
@@ -808,9 +1508,37 @@ async fn handle_regular_request(request_data: &mut RequestData, client_manager:
             // Log the error to file
             log_proxy_transaction!(&log_entry);
 
-            Ok(build_proxy_error_response(&e.to_string()))
+            Ok(error_response)
         }
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_from(ip: &str, username: Option<&str>) -> RequestData {
+        let mut request_data = RequestData::new("GET".to_string(), "/admin/bypass".to_string(), ip.parse().unwrap(), 50000);
+        request_data.username = username.map(str::to_string);
+        request_data
+    }
+
+    #[test]
+    fn admin_endpoints_closed_without_configuration() {
+        let admin = AdminConfig::default();
+        assert!(admin_forbidden(&request_from("127.0.0.1", None), &admin).is_some());
+        assert!(admin_forbidden(&request_from("10.0.0.5", Some("alice")), &admin).is_some());
+    }
+
+    #[test]
+    fn admin_access_by_user_or_loopback() {
+        let admin = AdminConfig { users: vec!["alice".to_string()], allow_loopback: true };
+        assert!(admin_forbidden(&request_from("10.0.0.5", Some("alice")), &admin).is_none());
+        assert!(admin_forbidden(&request_from("10.0.0.5", Some("bob")), &admin).is_some());
+        assert!(admin_forbidden(&request_from("::1", None), &admin).is_none());
+        assert!(admin_forbidden(&request_from("::ffff:127.0.0.1", None), &admin).is_none());
+        assert!(admin_forbidden(&request_from("10.0.0.5", None), &admin).is_some());
+    }
+}
@@ -0,0 +1,361 @@
+//! SOCKS5 listener (RFC 1928)
+//!
+//! SOCKS clients share the HTTP proxy's pipeline: CONNECTs to port 443 are
+//! intercepted like an HTTP CONNECT (or tunnelled, per the bypass rules),
+//! port 80 is served as plain HTTP through the forwarding flow, and any other
+//! port gets a raw tunnel. With `auth.enabled`, clients must log in with
+//! username/password (RFC 1929) checked against the same user file.
+
+use crate::models::RequestData;
+use crate::proxy::auth::ProxyAuthenticator;
+use crate::proxy::server::{handle_socks_http_request, intercept_client_stream, interception_bypass_reason, ProxyContext};
+use crate::proxy::tunnel::{connect_upstream, log_tunnel_transaction, splice};
+use crate::utils::{create_connect_transaction, log_connect_failure, log_connect_success};
+use anyhow::Result;
+use hyper::service::service_fn;
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, warn};
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Time a client has to finish the handshake and send its request
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SOCKS5 listener sharing a `ProxyServer`'s context
+pub struct SocksServer {
+    listen_addr: SocketAddr,
+    context: Arc<ProxyContext>,
+}
+
+/// The CONNECT a client asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SocksConnect {
+    host: String,
+    port: u16,
+    /// Set when the client logged in
+    username: Option<String>,
+}
+
+impl SocksServer {
+    pub fn new(listen_addr: SocketAddr, context: Arc<ProxyContext>) -> Self {
+        Self { listen_addr, context }
+    }
+
+    /// Accept SOCKS5 clients until the listener fails
+    pub async fn start(self) -> Result<()> {
+        let listener = TcpListener::bind(self.listen_addr).await?;
+        info!("🧦 SOCKS5 proxy listening on {}", self.listen_addr);
+
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("⚠️  Failed to accept SOCKS5 connection: {}", e);
+                    continue;
+                }
+            };
+            let context = Arc::clone(&self.context);
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, remote_addr, context).await {
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        info!("🔐 SOCKS5 client {} rejected: {}", remote_addr, e);
+                    } else {
+                        debug!("SOCKS5 connection from {} ended: {}", remote_addr, e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Negotiate with a client, then hand its connection to the flow for its port
+async fn handle_connection(mut stream: TcpStream, remote_addr: SocketAddr, ctx: Arc<ProxyContext>) -> io::Result<()> {
+    let start_time = Instant::now();
+    let _ = stream.set_nodelay(true);
+
+    let target = match tokio::time::timeout(HANDSHAKE_TIMEOUT, negotiate(&mut stream, ctx.auth.as_deref())).await {
+        Ok(result) => result?,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")),
+    };
+    debug!("🧦 SOCKS5 CONNECT {}:{} from {}", target.host, target.port, remote_addr);
+
+    match target.port {
+        443 if ctx.https_interception => {
+            if let Some(reason) = interception_bypass_reason(&target.host, &ctx).await {
+                info!("🔀 SOCKS5 {}:{} - TUNNELLING ({})", target.host, target.port, reason);
+                let annotations = HashMap::from([("interception_bypass".to_string(), reason)]);
+                return tunnel(stream, target, remote_addr, start_time, annotations, &ctx).await;
+            }
+            if ctx.issuer.is_none() {
+                error!("Failed to intercept {}:{}: no signing CA loaded (see startup log)", target.host, target.port);
+                return reply(&mut stream, REPLY_GENERAL_FAILURE).await;
+            }
+
+            reply(&mut stream, REPLY_SUCCEEDED).await?;
+            info!("🔍 SOCKS5 {}:{} - INTERCEPTING", target.host, target.port);
+            let connect_time = start_time.elapsed().as_millis();
+            intercept_client_stream(stream, target.host, target.port, connect_time, remote_addr, target.username, ctx).await;
+            Ok(())
+        }
+        80 => {
+            reply(&mut stream, REPLY_SUCCEEDED).await?;
+            let SocksConnect { host, port, username } = target;
+            let service = service_fn(move |req| {
+                handle_socks_http_request(req, host.clone(), port, remote_addr, username.clone(), Arc::clone(&ctx))
+            });
+            hyper::server::conn::Http::new()
+                .http1_only(true)
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+                .map_err(io::Error::other)
+        }
+        _ => tunnel(stream, target, remote_addr, start_time, HashMap::new(), &ctx).await,
+    }
+}
+
+/// Relay the client's bytes to `target` untouched
+async fn tunnel(
+    mut stream: TcpStream,
+    target: SocksConnect,
+    remote_addr: SocketAddr,
+    start_time: Instant,
+    mut annotations: HashMap<String, String>,
+    ctx: &ProxyContext,
+) -> io::Result<()> {
+    let mut request_data = connect_request_data(&target, remote_addr);
+    if ctx.dialer.has_parent() {
+        annotations.insert("upstream_route".to_string(), ctx.dialer.route(&target.host).describe());
+    }
+
+    let mut upstream = match connect_upstream(&ctx.dialer, &target.host, target.port).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            log_connect_failure(&target.host, target.port, start_time.elapsed().as_millis(), &e.to_string());
+            request_data.duration_ms = Some(start_time.elapsed().as_millis() as u64);
+            create_connect_transaction(&request_data, None, Some(e.to_string()), annotations);
+            return reply(&mut stream, REPLY_HOST_UNREACHABLE).await;
+        }
+    };
+
+    let connect_time = start_time.elapsed();
+    log_connect_success(&target.host, target.port, connect_time.as_millis());
+    reply(&mut stream, REPLY_SUCCEEDED).await?;
+
+    let result = splice(&mut stream, &mut upstream).await;
+    log_tunnel_transaction(request_data, annotations, connect_time, start_time.elapsed(), result);
+    Ok(())
+}
+
+/// Transaction record for a SOCKS tunnel, shaped like an HTTP CONNECT's
+fn connect_request_data(target: &SocksConnect, remote_addr: SocketAddr) -> RequestData {
+    let authority = if target.host.contains(':') {
+        format!("[{}]:{}", target.host, target.port)
+    } else {
+        format!("{}:{}", target.host, target.port)
+    };
+    let mut request_data = RequestData::new("CONNECT".to_string(), authority, remote_addr.ip(), remote_addr.port());
+    request_data.path = String::new();
+    request_data.query_string = None;
+    request_data.is_https = target.port == 443;
+    request_data.protocol = "SOCKS5".to_string();
+    request_data.username = target.username.clone();
+    request_data
+}
+
+/// Run the method selection, optional login and request phases
+async fn negotiate<S>(stream: &mut S, auth: Option<&ProxyAuthenticator>) -> io::Result<SocksConnect>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported SOCKS version {}", greeting[0])));
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await?;
+
+    let method = if auth.is_some() { METHOD_USERNAME_PASSWORD } else { METHOD_NO_AUTH };
+    if !methods.contains(&method) {
+        stream.write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE]).await?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "no acceptable authentication method offered"));
+    }
+    stream.write_all(&[SOCKS_VERSION, method]).await?;
+
+    let username = match auth {
+        Some(auth) => Some(login(stream, auth).await?),
+        None => None,
+    };
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported SOCKS version {}", request[0])));
+    }
+    let host = match request[3] {
+        ADDRESS_IPV4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets).await?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        ADDRESS_IPV6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets).await?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        ADDRESS_DOMAIN => {
+            let len = stream.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "host name is not UTF-8"))?
+                .to_lowercase()
+        }
+        other => {
+            reply(stream, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported address type {}", other)));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if request[1] != COMMAND_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported command {}", request[1])));
+    }
+
+    Ok(SocksConnect { host, port, username })
+}
+
+/// RFC 1929 username/password subnegotiation; returns the username
+async fn login<S>(stream: &mut S, auth: &ProxyAuthenticator) -> io::Result<String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let version = stream.read_u8().await?;
+    if version != AUTH_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported auth version {}", version)));
+    }
+    let username = read_field(stream).await?;
+    let password = read_field(stream).await?;
+
+    if !auth.verify(&username, &password).await {
+        stream.write_all(&[AUTH_VERSION, 0x01]).await?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("invalid credentials for '{}'", username)));
+    }
+    stream.write_all(&[AUTH_VERSION, 0x00]).await?;
+    Ok(username)
+}
+
+/// A length-prefixed string from the login request
+async fn read_field<S>(stream: &mut S) -> io::Result<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let len = stream.read_u8().await? as usize;
+    let mut field = vec![0u8; len];
+    stream.read_exact(&mut field).await?;
+    String::from_utf8(field).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "credentials are not UTF-8"))
+}
+
+/// Answer the client's request; the bound address is left unspecified
+async fn reply<S>(stream: &mut S, code: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&[SOCKS_VERSION, code, 0x00, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    /// Run `negotiate` against a client that sends `client_bytes`, returning
+    /// the outcome and everything the server wrote back
+    async fn negotiate_with(client_bytes: Vec<u8>, auth: Option<&ProxyAuthenticator>) -> (io::Result<SocksConnect>, Vec<u8>) {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(&client_bytes).await.unwrap();
+        let result = negotiate(&mut server, auth).await;
+        drop(server);
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        (result, written)
+    }
+
+    #[tokio::test]
+    async fn test_negotiates_connect_requests() {
+        // No auth, domain target
+        let mut bytes = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 11];
+        bytes.extend_from_slice(b"Example.com");
+        bytes.extend_from_slice(&443u16.to_be_bytes());
+        let (result, written) = negotiate_with(bytes, None).await;
+        assert_eq!(
+            result.unwrap(),
+            SocksConnect { host: "example.com".to_string(), port: 443, username: None }
+        );
+        assert_eq!(written, [0x05, 0x00]);
+
+        // IPv4 target with a command we don't support
+        let bytes = vec![0x05, 0x01, 0x00, 0x05, 0x02, 0x00, 0x01, 10, 0, 0, 1, 0, 80];
+        let (result, written) = negotiate_with(bytes, None).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert_eq!(written[2..4], [0x05, REPLY_COMMAND_NOT_SUPPORTED]);
+    }
+
+    #[tokio::test]
+    async fn test_requires_login_when_auth_is_enabled() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let auth = ProxyAuthenticator::from_htpasswd("test", &format!("alice:{}\n", hash), Duration::from_secs(60));
+
+        let login = |password: &[u8]| {
+            let mut bytes = vec![0x05, 0x02, 0x00, 0x02, 0x01, 5];
+            bytes.extend_from_slice(b"alice");
+            bytes.push(password.len() as u8);
+            bytes.extend_from_slice(password);
+            bytes.extend_from_slice(&[0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 22]);
+            bytes
+        };
+
+        let (result, written) = negotiate_with(login(b"secret"), Some(&auth)).await;
+        assert_eq!(
+            result.unwrap(),
+            SocksConnect { host: "127.0.0.1".to_string(), port: 22, username: Some("alice".to_string()) }
+        );
+        assert_eq!(written, [0x05, 0x02, 0x01, 0x00]);
+
+        let (result, written) = negotiate_with(login(b"wrong"), Some(&auth)).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(written, [0x05, 0x02, 0x01, 0x01]);
+
+        // A client that can't log in is turned away
+        let (result, written) = negotiate_with(vec![0x05, 0x01, 0x00], Some(&auth)).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(written, [0x05, 0xff]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Raw CONNECT tunnelling for when HTTPS interception is disabled
+//!
+//! Bytes are copied between client and upstream untouched, so clients don't
+//! need to trust our root CA. Only the target, byte counts and duration are
+//! visible and logged.
+
+use crate::models::{RequestData, ResponseData};
+use crate::proxy::parent::UpstreamDialer;
+use crate::utils::create_connect_transaction;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// Traffic carried by a finished tunnel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TunnelStats {
+    pub client_to_upstream: u64,
+    pub upstream_to_client: u64,
+    pub duration: Duration,
+}
+
+/// Open the TCP connection to the tunnel target, through the parent proxy
+/// if its route says so; the dialer applies `upstream.connect_timeout`
+pub async fn connect_upstream(dialer: &UpstreamDialer, host: &str, port: u16) -> Result<TcpStream> {
+    dialer
+        .connect(host, port)
+        .await
+        .map_err(|e| anyhow!("Failed to connect to {}:{}: {}", host, port, e))
+}
+
+/// Copy bytes in both directions until either side closes
+pub async fn splice<C, U>(client: &mut C, upstream: &mut U) -> std::io::Result<TunnelStats>
+where
+    C: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    U: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let started = Instant::now();
+    let (client_to_upstream, upstream_to_client) = copy_bidirectional(client, upstream).await?;
+
+    Ok(TunnelStats {
+        client_to_upstream,
+        upstream_to_client,
+        duration: started.elapsed(),
+    })
+}
+
+/// Write the `ProxyLog` entry for a closed tunnel
+///
+/// The request's `content_length` holds bytes sent by the client and the
+/// response's holds bytes returned by the upstream.
+pub fn log_tunnel_transaction(
+    mut request_data: RequestData,
+    annotations: HashMap<String, String>,
+    connect_time: Duration,
+    total_time: Duration,
+    result: std::io::Result<TunnelStats>,
+) {
+    request_data.duration_ms = Some(total_time.as_millis() as u64);
+
+    match result {
+        Ok(stats) => {
+            info!("🔌 Tunnel to {} closed: ↑ {} bytes, ↓ {} bytes in {:.2}s",
+                  request_data.url, stats.client_to_upstream, stats.upstream_to_client, stats.duration.as_secs_f64());
+
+            request_data.content_length = stats.client_to_upstream;
+            let mut response_data = ResponseData::new(
+                200,
+                "200 Connection Established".to_string(),
+                String::new(),
+                Vec::new(),
+                connect_time.as_millis() as u64,
+            );
+            response_data.content_length = stats.upstream_to_client;
+
+            create_connect_transaction(&request_data, Some(response_data), None, annotations);
+        }
+        Err(e) => {
+            info!("🔌 Tunnel to {} failed after {:.2}s: {}", request_data.url, total_time.as_secs_f64(), e);
+            create_connect_transaction(&request_data, None, Some(e.to_string()), annotations);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_splice_counts_bytes_both_ways() {
+        let (mut client, mut client_side) = tokio::io::duplex(64);
+        let (mut upstream, mut upstream_side) = tokio::io::duplex(64);
+
+        let tunnel = tokio::spawn(async move { splice(&mut client_side, &mut upstream_side).await });
+
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        upstream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        upstream.write_all(b"hi there").await.unwrap();
+        let mut buf = [0u8; 8];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi there");
+
+        drop(client);
+        drop(upstream);
+
+        let stats = tunnel.await.unwrap().unwrap();
+        assert_eq!(stats.client_to_upstream, 5);
+        assert_eq!(stats.upstream_to_client, 8);
+    }
+}
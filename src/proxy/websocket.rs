@@ -0,0 +1,484 @@
+//! WebSocket upgrades through the proxy
+//!
+//! `Upgrade` and `Connection` are hop-by-hop headers, so ordinary forwarding
+//! drops them and the handshake can't complete. Requests asking for
+//! `Upgrade: websocket` are instead forwarded with those headers intact; once
+//! the upstream answers `101 Switching Protocols` the client gets the same
+//! answer and the two upgraded connections are spliced together.
+//!
+//! With `websocket.log_frames` the bytes are still relayed untouched, but
+//! frame headers (and the start of text payloads) are decoded on the way and
+//! logged, then attached to the connection's transaction as events.
+
+use crate::config::settings::WebSocketConfig;
+use crate::models::{ProxyLog, RequestData, ResponseData, TransactionEvent, WebSocketFrame};
+use crate::proxy::http_client::HttpClient;
+use crate::proxy::tunnel::{splice, TunnelStats};
+use crate::utils::{protocol_name, should_forward_request_header};
+use crate::log_proxy_transaction;
+use anyhow::Result;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info};
+
+const CLIENT_TO_SERVER: &str = "client_to_server";
+const SERVER_TO_CLIENT: &str = "server_to_client";
+
+/// Whether a request asks to switch to the WebSocket protocol
+pub fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let has_token = |name: &str, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    has_token("upgrade", "websocket") && has_token("connection", "upgrade")
+}
+
+/// Forward a WebSocket handshake to `target_url`; when the upstream accepts
+/// it, answer the client with its 101 and relay the upgraded connections in
+/// the background
+///
+/// `request_data` supplies the headers to send (after middleware and header
+/// rules). Refused handshakes are returned to the client as they are.
+pub async fn forward_websocket(
+    mut req: Request<Body>,
+    mut request_data: RequestData,
+    target_url: &str,
+    client: &HttpClient,
+    config: &WebSocketConfig,
+    annotations: HashMap<String, String>,
+) -> Result<Response<Body>> {
+    let started = Instant::now();
+    let client_upgrade = hyper::upgrade::on(&mut req);
+
+    let mut builder = Request::builder().method(req.method()).uri(target_url);
+    for (name, value) in &request_data.headers {
+        if should_forward_request_header(name) {
+            builder = builder.header(name, value);
+        }
+    }
+    let upgrade = req.headers().get("upgrade").cloned().unwrap_or_else(|| "websocket".parse().unwrap());
+    let mut upstream_request = builder.header("connection", "Upgrade").header("upgrade", upgrade).body(Body::empty())?;
+    let uri = upstream_request.uri().clone();
+    client.authorize_with_parent(upstream_request.headers_mut(), &uri);
+
+    info!("🔌 WebSocket handshake → {}", target_url);
+    // Upgrades need HTTP/1.1 (extended CONNECT isn't used)
+    let mut upstream_response = client.get_http1_client().request(upstream_request).await?;
+    let handshake_time = started.elapsed();
+
+    if upstream_response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        info!("🔌 WebSocket handshake to {} refused: {}", target_url, upstream_response.status());
+        return Ok(upstream_response);
+    }
+
+    let upstream_version = upstream_response.version();
+    let upstream_upgrade = hyper::upgrade::on(&mut upstream_response);
+    let mut response = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
+    let mut response_headers = HashMap::new();
+    for (name, value) in upstream_response.headers() {
+        response = response.header(name, value);
+        if let Ok(value) = value.to_str() {
+            response_headers.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    let config = config.clone();
+    let url = target_url.to_string();
+    tokio::spawn(async move {
+        let frames = config.log_frames.then(|| FrameLog::new(config.max_logged_frames));
+        let result = match tokio::try_join!(client_upgrade, upstream_upgrade) {
+            Ok((mut client_io, mut upstream_io)) => match &frames {
+                Some(frames) => relay_logged(&mut client_io, &mut upstream_io, frames, config.preview_bytes).await,
+                None => splice(&mut client_io, &mut upstream_io).await,
+            },
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+
+        request_data.duration_ms = Some(started.elapsed().as_millis() as u64);
+        let mut annotations = annotations;
+        let mut log_entry = ProxyLog {
+            request: request_data,
+            response: None,
+            error: None,
+            annotations: HashMap::new(),
+            events: Vec::new(),
+        };
+        if let Some(frames) = frames {
+            let frames = frames.into_inner().unwrap();
+            annotations.insert("websocket.frames".to_string(), frames.seen.to_string());
+            log_entry.events = frames.events;
+        }
+        log_entry.annotations = annotations;
+
+        match result {
+            Ok(stats) => {
+                info!("🔌 WebSocket {} closed: ↑ {} bytes, ↓ {} bytes in {:.2}s",
+                      url, stats.client_to_upstream, stats.upstream_to_client, stats.duration.as_secs_f64());
+                log_entry.request.content_length = stats.client_to_upstream;
+                let mut response_data = ResponseData::new(
+                    101,
+                    "101 Switching Protocols".to_string(),
+                    String::new(),
+                    Vec::new(),
+                    handshake_time.as_millis() as u64,
+                );
+                response_data.headers = response_headers;
+                response_data.content_type = None;
+                response_data.protocol = Some(protocol_name(upstream_version).to_string());
+                response_data.content_length = stats.upstream_to_client;
+                log_entry.response = Some(response_data);
+            }
+            Err(e) => {
+                info!("🔌 WebSocket {} failed: {}", url, e);
+                log_entry.error = Some(e.to_string());
+            }
+        }
+        log_proxy_transaction!(&log_entry);
+    });
+
+    Ok(response.body(Body::empty())?)
+}
+
+/// Frames decoded on one connection, capped at `max` kept events
+struct FrameLog {
+    max: usize,
+    seen: usize,
+    events: Vec<TransactionEvent>,
+}
+
+impl FrameLog {
+    fn new(max: usize) -> Mutex<Self> {
+        Mutex::new(Self { max, seen: 0, events: Vec::new() })
+    }
+
+    fn record(&mut self, frame: WebSocketFrame) {
+        let arrow = if frame.direction == CLIENT_TO_SERVER { "→" } else { "←" };
+        info!("🔌 WS {} {} {} bytes{}{}", arrow, frame.opcode, frame.length,
+              if frame.fin { "" } else { " (fragment)" },
+              frame.preview.as_deref().map(|p| format!(": {:?}", p)).unwrap_or_default());
+
+        self.seen += 1;
+        if self.events.len() < self.max {
+            self.events.push(TransactionEvent::WebSocketFrame(frame));
+        }
+    }
+}
+
+/// Relay like `splice`, decoding the frames that pass in each direction
+async fn relay_logged<C, U>(client: &mut C, upstream: &mut U, frames: &Mutex<FrameLog>, preview_bytes: usize) -> std::io::Result<TunnelStats>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let started = Instant::now();
+    let (client_read, client_write) = tokio::io::split(client);
+    let (upstream_read, upstream_write) = tokio::io::split(upstream);
+
+    let (client_to_upstream, upstream_to_client) = tokio::try_join!(
+        pump(client_read, upstream_write, FrameDecoder::new(CLIENT_TO_SERVER, preview_bytes), frames),
+        pump(upstream_read, client_write, FrameDecoder::new(SERVER_TO_CLIENT, preview_bytes), frames),
+    )?;
+
+    Ok(TunnelStats {
+        client_to_upstream,
+        upstream_to_client,
+        duration: started.elapsed(),
+    })
+}
+
+/// Copy one direction until EOF, feeding the bytes to `decoder` on the way
+async fn pump<R, W>(mut from: R, mut to: W, mut decoder: FrameDecoder, frames: &Mutex<FrameLog>) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut total = 0;
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            to.shutdown().await?;
+            return Ok(total);
+        }
+
+        let decoded = decoder.feed(&buf[..n]);
+        if !decoded.is_empty() {
+            let mut frames = frames.lock().unwrap();
+            decoded.into_iter().for_each(|frame| frames.record(frame));
+        }
+
+        to.write_all(&buf[..n]).await?;
+        to.flush().await?;
+        total += n as u64;
+    }
+}
+
+/// A frame whose header has been read, with its payload still arriving
+struct PendingFrame {
+    opcode: u8,
+    fin: bool,
+    compressed: bool,
+    mask: Option<[u8; 4]>,
+    length: u64,
+    remaining: u64,
+    preview: Vec<u8>,
+}
+
+/// Incremental RFC 6455 frame parser for one direction of a connection
+///
+/// Only headers and the first `preview_bytes` of each payload are kept, so
+/// large frames cost no memory.
+pub struct FrameDecoder {
+    direction: &'static str,
+    preview_bytes: usize,
+    header: Vec<u8>,
+    frame: Option<PendingFrame>,
+    /// Whether the fragmented message in progress is text
+    text_message: bool,
+}
+
+impl FrameDecoder {
+    pub fn new(direction: &'static str, preview_bytes: usize) -> Self {
+        Self {
+            direction,
+            preview_bytes,
+            header: Vec::with_capacity(14),
+            frame: None,
+            text_message: false,
+        }
+    }
+
+    /// Consume the next bytes of the stream, returning the frames they complete
+    pub fn feed(&mut self, mut data: &[u8]) -> Vec<WebSocketFrame> {
+        let mut frames = Vec::new();
+
+        while !data.is_empty() {
+            let Some(frame) = self.frame.as_mut() else {
+                let wanted = match self.header.as_slice() {
+                    [_, second, ..] => header_len(*second),
+                    _ => 2,
+                };
+                let take = (wanted - self.header.len()).min(data.len());
+                self.header.extend_from_slice(&data[..take]);
+                data = &data[take..];
+
+                if self.header.len() >= 2 && self.header.len() == header_len(self.header[1]) {
+                    let frame = parse_header(&self.header);
+                    self.header.clear();
+                    if frame.remaining == 0 {
+                        frames.push(self.finish(frame));
+                    } else {
+                        self.frame = Some(frame);
+                    }
+                }
+                continue;
+            };
+
+            let take = frame.remaining.min(data.len() as u64) as usize;
+            let offset = (frame.length - frame.remaining) as usize;
+            let keep = self.preview_bytes.saturating_sub(frame.preview.len()).min(take);
+            frame.preview.extend(data[..keep].iter().enumerate().map(|(i, byte)| match frame.mask {
+                Some(mask) => byte ^ mask[(offset + i) % 4],
+                None => *byte,
+            }));
+            frame.remaining -= take as u64;
+            data = &data[take..];
+
+            if frame.remaining == 0 {
+                let frame = self.frame.take().unwrap();
+                frames.push(self.finish(frame));
+            }
+        }
+
+        frames
+    }
+
+    fn finish(&mut self, frame: PendingFrame) -> WebSocketFrame {
+        let is_text = match frame.opcode {
+            0x1 => {
+                self.text_message = !frame.fin;
+                true
+            }
+            0x0 => {
+                let is_text = self.text_message;
+                self.text_message &= !frame.fin;
+                is_text
+            }
+            _ => false,
+        };
+
+        let preview = if frame.compressed {
+            None
+        } else if is_text {
+            let mut text = String::from_utf8_lossy(&frame.preview).into_owned();
+            if (frame.preview.len() as u64) < frame.length {
+                text.push('…');
+            }
+            Some(text)
+        } else if frame.opcode == 0x8 && frame.preview.len() >= 2 {
+            let code = u16::from_be_bytes([frame.preview[0], frame.preview[1]]);
+            let reason = String::from_utf8_lossy(&frame.preview[2..]);
+            Some(format!("{} {}", code, reason).trim_end().to_string())
+        } else {
+            None
+        };
+
+        let frame = WebSocketFrame {
+            timestamp: chrono::Utc::now(),
+            direction: self.direction.to_string(),
+            opcode: opcode_name(frame.opcode).to_string(),
+            fin: frame.fin,
+            compressed: frame.compressed,
+            length: frame.length,
+            preview,
+        };
+        debug!("Decoded WebSocket frame: {:?}", frame);
+        frame
+    }
+}
+
+/// Header size implied by the second header byte
+fn header_len(second: u8) -> usize {
+    let extended = match second & 0x7f {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask = if second & 0x80 != 0 { 4 } else { 0 };
+    2 + extended + mask
+}
+
+fn parse_header(header: &[u8]) -> PendingFrame {
+    let (length, rest) = match header[1] & 0x7f {
+        126 => (u16::from_be_bytes([header[2], header[3]]) as u64, &header[4..]),
+        127 => (u64::from_be_bytes(header[2..10].try_into().unwrap()), &header[10..]),
+        length => (length as u64, &header[2..]),
+    };
+
+    PendingFrame {
+        opcode: header[0] & 0x0f,
+        fin: header[0] & 0x80 != 0,
+        compressed: header[0] & 0x40 != 0,
+        mask: (header[1] & 0x80 != 0).then(|| rest[..4].try_into().unwrap()),
+        length,
+        remaining: length,
+        preview: Vec::new(),
+    }
+}
+
+fn opcode_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x0 => "continuation",
+        0x1 => "text",
+        0x2 => "binary",
+        0x8 => "close",
+        0x9 => "ping",
+        0xA => "pong",
+        _ => "reserved",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a frame the way a client (masked) or server (unmasked) would
+    fn frame(first: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = vec![first];
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => out.push(mask_bit | len as u8),
+            len if len <= u16::MAX as usize => {
+                out.push(mask_bit | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(mask_bit | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+            }
+            None => out.extend_from_slice(payload),
+        }
+        out
+    }
+
+    #[test]
+    fn test_decoder_handles_split_masked_and_fragmented_frames() {
+        let mask = Some([0x37, 0xfa, 0x21, 0x3d]);
+        let mut stream = frame(0x81, b"hello", mask);
+        stream.extend(frame(0x82, &[7u8; 300], mask));
+        stream.extend(frame(0x01, b"split ", mask));
+        stream.extend(frame(0x89, b"", mask));
+        stream.extend(frame(0x80, b"message that is longer than the preview", mask));
+        let mut close = 1000u16.to_be_bytes().to_vec();
+        close.extend_from_slice(b"bye");
+        stream.extend(frame(0x88, &close, mask));
+
+        // Arrives three bytes at a time
+        let mut decoder = FrameDecoder::new(CLIENT_TO_SERVER, 16);
+        let frames: Vec<WebSocketFrame> = stream.chunks(3).flat_map(|chunk| decoder.feed(chunk)).collect();
+
+        let summary: Vec<(&str, bool, u64, Option<&str>)> = frames
+            .iter()
+            .map(|f| (f.opcode.as_str(), f.fin, f.length, f.preview.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("text", true, 5, Some("hello")),
+            ("binary", true, 300, None),
+            ("text", false, 6, Some("split ")),
+            ("ping", true, 0, None),
+            ("continuation", true, 39, Some("message that is …")),
+            ("close", true, 5, Some("1000 bye")),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_logged_relay_passes_bytes_through_and_records_frames() {
+        let (mut client, mut client_side) = tokio::io::duplex(1024);
+        let (mut upstream, mut upstream_side) = tokio::io::duplex(1024);
+        let frames = FrameLog::new(1);
+
+        let sent = frame(0x81, b"ping?", Some([1, 2, 3, 4]));
+        let reply = frame(0x81, b"pong!", None);
+        let test = async {
+            client.write_all(&sent).await.unwrap();
+            let mut buf = vec![0; sent.len()];
+            upstream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, sent);
+
+            upstream.write_all(&reply).await.unwrap();
+            let mut buf = vec![0; reply.len()];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, reply);
+
+            drop(client);
+            drop(upstream);
+        };
+        let (stats, _) = tokio::join!(relay_logged(&mut client_side, &mut upstream_side, &frames, 64), test);
+
+        let stats = stats.unwrap();
+        assert_eq!((stats.client_to_upstream, stats.upstream_to_client), (sent.len() as u64, reply.len() as u64));
+        let frames = frames.into_inner().unwrap();
+        assert_eq!(frames.seen, 2);
+        assert_eq!(frames.events.len(), 1);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("upgrade", "WebSocket".parse().unwrap());
+        headers.insert("connection", "keep-alive, Upgrade".parse().unwrap());
+        assert!(is_websocket_upgrade(&headers));
+        headers.insert("upgrade", "h2c".parse().unwrap());
+        assert!(!is_websocket_upgrade(&headers));
+    }
+}
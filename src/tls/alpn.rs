@@ -0,0 +1,144 @@
+//! ALPN mirroring for intercepted connections
+//!
+//! Offering `h2` to a client whose upstream only speaks HTTP/1.1 changes what
+//! the client sees (and how it pools and multiplexes requests), so by default
+//! intercepted clients are offered h2 only when the real upstream negotiates
+//! it. What each upstream picks is learned with a probe handshake (or from
+//! the `mimic_upstream_cert` probe) and remembered for `alpn_cache_ttl_secs`.
+
+use crate::config::settings::{ClientAlpn, TlsConfig};
+use crate::tls::UpstreamCertProbe;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+pub const ALPN_H2: &[u8] = b"h2";
+pub const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// Decides whether intercepted clients are offered h2, per upstream
+pub struct UpstreamAlpn {
+    mode: ClientAlpn,
+    ttl: Duration,
+    probe: UpstreamCertProbe,
+    /// Whether `host:port` negotiated h2, and when we found out
+    learned: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl Default for UpstreamAlpn {
+    fn default() -> Self {
+        Self::from_config(&TlsConfig::default(), UpstreamCertProbe::new())
+    }
+}
+
+impl UpstreamAlpn {
+    pub fn from_config(tls: &TlsConfig, probe: UpstreamCertProbe) -> Self {
+        Self {
+            mode: tls.client_alpn,
+            ttl: Duration::from_secs(tls.alpn_cache_ttl_secs),
+            probe,
+            learned: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember the protocol `host:port` selected in a handshake
+    pub fn record(&self, host: &str, port: u16, alpn: Option<&[u8]>) {
+        let h2 = alpn == Some(ALPN_H2);
+        let mut learned = self.learned.lock().unwrap();
+        learned.retain(|_, (_, at)| at.elapsed() < self.ttl);
+        learned.insert(format!("{}:{}", host, port), (h2, Instant::now()));
+    }
+
+    /// Whether `host:port` negotiated h2, if we found out recently
+    pub fn learned(&self, host: &str, port: u16) -> Option<bool> {
+        let learned = self.learned.lock().unwrap();
+        learned
+            .get(&format!("{}:{}", host, port))
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(h2, _)| *h2)
+    }
+
+    /// Whether a client of `host:port` (asking for `server_name`) should be
+    /// offered h2, probing the upstream if we don't know yet
+    pub async fn offer_h2(&self, host: &str, port: u16, server_name: &str) -> bool {
+        match self.mode {
+            ClientAlpn::H2 => return true,
+            ClientAlpn::Http1 => return false,
+            ClientAlpn::Mirror => {}
+        }
+
+        if let Some(h2) = self.learned(host, port) {
+            return h2;
+        }
+
+        match self.probe.handshake(host, port, server_name).await {
+            Ok(hello) => self.record(host, port, hello.alpn.as_deref()),
+            Err(e) => {
+                // HTTP/1.1 works whatever the upstream speaks
+                debug!("ALPN probe of {}:{} failed: {}", host, port, e);
+                self.record(host, port, None);
+            }
+        }
+        self.learned(host, port).unwrap_or(false)
+    }
+}
+
+/// Protocols to offer a client, most preferred first
+pub fn alpn_offer(h2: bool) -> Vec<Vec<u8>> {
+    if h2 {
+        vec![ALPN_H2.to_vec(), ALPN_HTTP1.to_vec()]
+    } else {
+        vec![ALPN_HTTP1.to_vec()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{PrivateKey, ServerConfig};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// A TLS server on localhost that negotiates from `protocols`
+    async fn upstream(protocols: &[&[u8]]) -> u16 {
+        let cert = crate::tls::generate_self_signed_cert("Test", "localhost", 1).unwrap();
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.cert()], PrivateKey(cert.key().0))
+            .unwrap();
+        config.alpn_protocols = protocols.iter().map(|p| p.to_vec()).collect();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let _ = acceptor.accept(tcp).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_mirrors_what_the_upstream_negotiates() {
+        let h2 = upstream(&[ALPN_H2, ALPN_HTTP1]).await;
+        let http1 = upstream(&[ALPN_HTTP1]).await;
+
+        let alpn = UpstreamAlpn::default();
+        assert!(alpn.offer_h2("127.0.0.1", h2, "localhost").await);
+        assert!(!alpn.offer_h2("127.0.0.1", http1, "localhost").await);
+        assert_eq!(alpn.learned("127.0.0.1", h2), Some(true));
+        assert_eq!(alpn.learned("127.0.0.1", http1), Some(false));
+
+        // Nothing listening: fall back to HTTP/1.1
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        assert!(!alpn.offer_h2("127.0.0.1", closed, "localhost").await);
+
+        let tls = TlsConfig { client_alpn: ClientAlpn::Http1, ..TlsConfig::default() };
+        assert!(!UpstreamAlpn::from_config(&tls, UpstreamCertProbe::new()).offer_h2("127.0.0.1", h2, "localhost").await);
+        let tls = TlsConfig { client_alpn: ClientAlpn::H2, ..TlsConfig::default() };
+        assert!(UpstreamAlpn::from_config(&tls, UpstreamCertProbe::new()).offer_h2("127.0.0.1", http1, "localhost").await);
+    }
+}
@@ -0,0 +1,203 @@
+//! Per-host HTTPS interception bypass rules
+//!
+//! Certificate-pinned apps, banking sites and OS update endpoints break when
+//! intercepted. `tls.interception_bypass` lists hosts that are always
+//! tunnelled; a non-empty `tls.interception_only` limits interception to the
+//! listed hosts.
+
+use crate::config::settings::TlsConfig;
+use regex::{Regex, RegexBuilder};
+use std::net::IpAddr;
+use tracing::{error, info};
+
+/// A single host pattern
+enum HostRule {
+    /// `example.com`
+    Exact(String),
+    /// `*.example.com` (subdomains only)
+    Suffix(String),
+    /// `~^.*\.bank\.example$` (case-insensitive)
+    Regex(Regex),
+    /// `10.0.0.0/8` for IP-literal targets
+    Cidr(IpAddr, u8),
+}
+
+impl HostRule {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        if let Some(expr) = pattern.strip_prefix('~') {
+            return RegexBuilder::new(expr)
+                .case_insensitive(true)
+                .build()
+                .map(Self::Regex)
+                .map_err(|e| e.to_string());
+        }
+
+        if let Some((addr, prefix)) = pattern.split_once('/') {
+            let addr: IpAddr = addr.parse().map_err(|_| format!("invalid CIDR address '{}'", addr))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix: u8 = prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid CIDR prefix '{}'", prefix))?;
+            return Ok(Self::Cidr(addr, prefix));
+        }
+
+        let pattern = pattern.to_lowercase();
+        Ok(match pattern.strip_prefix("*.") {
+            Some(suffix) => Self::Suffix(format!(".{}", suffix)),
+            None => Self::Exact(pattern),
+        })
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(exact) => host == exact,
+            Self::Suffix(suffix) => host.ends_with(suffix.as_str()),
+            Self::Regex(regex) => regex.is_match(host),
+            Self::Cidr(network, prefix) => host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| cidr_contains(network, *prefix, &ip)),
+        }
+    }
+}
+
+fn cidr_contains(network: &IpAddr, prefix: u8, ip: &IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(*net) & mask == u32::from(*ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(*net) & mask == u128::from(*ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// An ordered list of host patterns
+#[derive(Default)]
+pub struct HostMatcher {
+    rules: Vec<(String, HostRule)>,
+}
+
+impl HostMatcher {
+    /// Compile patterns, logging and skipping invalid ones
+    pub fn new(patterns: &[String]) -> Self {
+        let rules = patterns
+            .iter()
+            .filter_map(|pattern| match HostRule::parse(pattern) {
+                Ok(rule) => Some((pattern.clone(), rule)),
+                Err(e) => {
+                    error!("❌ Ignoring invalid host pattern '{}': {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return the first pattern matching `host`, if any
+    pub fn matching_rule(&self, host: &str) -> Option<&str> {
+        let host = host.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(_, rule)| rule.matches(&host))
+            .map(|(pattern, _)| pattern.as_str())
+    }
+}
+
+/// Whether a CONNECT target should be intercepted, and why not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterceptionDecision {
+    Intercept,
+    /// Tunnel without decryption; holds the reason for logs
+    Bypass(String),
+}
+
+/// `tls.interception_bypass` / `tls.interception_only` evaluated together
+#[derive(Default)]
+pub struct InterceptionPolicy {
+    bypass: HostMatcher,
+    only: HostMatcher,
+}
+
+impl InterceptionPolicy {
+    pub fn from_config(config: &TlsConfig) -> Self {
+        let policy = Self {
+            bypass: HostMatcher::new(&config.interception_bypass),
+            only: HostMatcher::new(&config.interception_only),
+        };
+
+        if !policy.bypass.is_empty() || !policy.only.is_empty() {
+            info!("🔀 Interception policy: {} bypass rule(s), {} intercept-only rule(s)",
+                  policy.bypass.rules.len(), policy.only.rules.len());
+        }
+
+        policy
+    }
+
+    /// Bypass rules win over `interception_only`
+    pub fn decide(&self, host: &str) -> InterceptionDecision {
+        if let Some(rule) = self.bypass.matching_rule(host) {
+            return InterceptionDecision::Bypass(format!("interception_bypass rule '{}'", rule));
+        }
+
+        if !self.only.is_empty() && self.only.matching_rule(host).is_none() {
+            return InterceptionDecision::Bypass("not listed in interception_only".to_string());
+        }
+
+        InterceptionDecision::Intercept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_host_matcher_pattern_kinds() {
+        let matcher = HostMatcher::new(&patterns(&[
+            "example.com",
+            "*.apple.com",
+            "~^(www\\.)?mybank\\.(com|co\\.uk)$",
+            "10.0.0.0/8",
+            "2001:db8::/32",
+            "~(unclosed",
+        ]));
+
+        assert_eq!(matcher.matching_rule("EXAMPLE.com"), Some("example.com"));
+        assert_eq!(matcher.matching_rule("sub.example.com"), None);
+        assert_eq!(matcher.matching_rule("swscan.apple.com"), Some("*.apple.com"));
+        assert_eq!(matcher.matching_rule("apple.com"), None);
+        assert!(matcher.matching_rule("www.mybank.co.uk").is_some());
+        assert_eq!(matcher.matching_rule("10.1.2.3"), Some("10.0.0.0/8"));
+        assert_eq!(matcher.matching_rule("11.1.2.3"), None);
+        assert_eq!(matcher.matching_rule("[2001:db8::1]"), Some("2001:db8::/32"));
+    }
+
+    #[test]
+    fn test_bypass_wins_over_only() {
+        let config = TlsConfig {
+            interception_bypass: patterns(&["login.example.com"]),
+            interception_only: patterns(&["*.example.com"]),
+            ..Default::default()
+        };
+        let policy = InterceptionPolicy::from_config(&config);
+
+        assert_eq!(policy.decide("api.example.com"), InterceptionDecision::Intercept);
+        assert!(matches!(policy.decide("login.example.com"), InterceptionDecision::Bypass(_)));
+        assert!(matches!(policy.decide("other.org"), InterceptionDecision::Bypass(_)));
+    }
+}
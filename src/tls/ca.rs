@@ -0,0 +1,448 @@
+//! CA lifecycle: root and intermediate creation, rotation and distribution bundles
+//!
+//! Rotation keeps the previous root next to the new one as
+//! `<stem>.retiring-<unix>.crt`, where the timestamp is the end of its overlap
+//! window. Until then it stays in `<stem>-bundle.pem`, so clients that trust
+//! the bundle keep accepting certificates either CA signed while the new root
+//! is rolled out.
+
+use crate::config::settings::LeafKeyAlgorithm;
+use crate::tls::cert_gen::{authority_key_identifier, generate_key_pair, parse_pem_certificate, signing_ca, subject_key_id};
+use crate::tls::disk_cache::{pem_block, write_atomic, write_temp};
+use crate::tls::{current_timestamp, encrypt_private_key_pem, CertificateData};
+use anyhow::{anyhow, Result};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, GeneralSubtree, IsCa,
+    KeyUsagePurpose, NameConstraints,
+};
+use rustls::{Certificate as RustlsCertificate, PrivateKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+
+/// Subject, key and constraints of a root CA
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaOptions {
+    pub organization: String,
+    pub common_name: String,
+    pub key_algorithm: LeafKeyAlgorithm,
+    pub validity_days: u32,
+    /// Intermediate CAs allowed below the root; `None` leaves it unconstrained
+    pub path_len: Option<u8>,
+    /// DNS subtrees issued names must fall under (empty permits everything)
+    pub permitted_dns: Vec<String>,
+    /// DNS subtrees issued names must never fall under
+    pub excluded_dns: Vec<String>,
+}
+
+impl Default for CaOptions {
+    fn default() -> Self {
+        Self {
+            organization: "Rust Forward Proxy CA".to_string(),
+            common_name: "Rust Proxy Root CA".to_string(),
+            key_algorithm: LeafKeyAlgorithm::EcdsaP256,
+            validity_days: 3650,
+            path_len: None,
+            permitted_dns: Vec::new(),
+            excluded_dns: Vec::new(),
+        }
+    }
+}
+
+impl CaOptions {
+    /// Read the options an existing root CA was created with
+    pub fn from_cert(cert: &RustlsCertificate) -> Result<Self> {
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)
+            .map_err(|e| anyhow!("Failed to parse CA certificate: {}", e))?;
+
+        let subject = parsed.subject();
+        let first = |attrs: Vec<&x509_parser::x509::AttributeTypeAndValue>| {
+            attrs.first().and_then(|attr| attr.as_str().ok()).map(str::to_string)
+        };
+        let defaults = Self::default();
+        let mut options = Self {
+            organization: first(subject.iter_organization().collect()).unwrap_or(defaults.organization),
+            common_name: first(subject.iter_common_name().collect()).unwrap_or(defaults.common_name),
+            key_algorithm: match parsed.public_key().algorithm.algorithm.to_id_string().as_str() {
+                "1.2.840.113549.1.1.1" => LeafKeyAlgorithm::Rsa2048,
+                "1.3.101.112" => LeafKeyAlgorithm::Ed25519,
+                _ => LeafKeyAlgorithm::EcdsaP256,
+            },
+            validity_days: ((parsed.validity().not_after.timestamp() - parsed.validity().not_before.timestamp())
+                / (24 * 60 * 60))
+                .clamp(1, u32::MAX as i64) as u32,
+            ..defaults
+        };
+
+        for ext in parsed.extensions() {
+            match ext.parsed_extension() {
+                ParsedExtension::BasicConstraints(bc) => {
+                    if !bc.ca {
+                        return Err(anyhow!("{} is not a CA certificate", options.common_name));
+                    }
+                    options.path_len = bc.path_len_constraint.map(|len| len.min(u8::MAX as u32) as u8);
+                }
+                ParsedExtension::NameConstraints(nc) => {
+                    let dns_names = |subtrees: &Option<Vec<x509_parser::extensions::GeneralSubtree>>| {
+                        subtrees.iter().flatten().filter_map(|subtree| match subtree.base {
+                            GeneralName::DNSName(name) => Some(name.to_string()),
+                            _ => None,
+                        }).collect()
+                    };
+                    options.permitted_dns = dns_names(&nc.permitted_subtrees);
+                    options.excluded_dns = dns_names(&nc.excluded_subtrees);
+                }
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Create a self-signed root CA
+pub fn generate_root_ca(options: &CaOptions) -> Result<CertificateData> {
+    debug!("Generating root CA {}", options.common_name);
+    let cert = Certificate::from_params(ca_params(options)?)
+        .map_err(|e| anyhow!("Failed to build root CA {}: {}", options.common_name, e))?;
+    let cert_der = cert.serialize_der()
+        .map_err(|e| anyhow!("Failed to sign root CA {}: {}", options.common_name, e))?;
+
+    Ok(CertificateData::new(
+        RustlsCertificate(cert_der),
+        PrivateKey(cert.serialize_private_key_der()),
+    ))
+}
+
+/// Create an intermediate CA signed by `issuer` (a root or another intermediate)
+pub fn generate_intermediate_ca(issuer: &CertificateData, options: &CaOptions) -> Result<CertificateData> {
+    debug!("Generating intermediate CA {}", options.common_name);
+    let mut params = ca_params(options)?;
+    match subject_key_id(&issuer.cert().0)? {
+        Some(key_id) => params.custom_extensions.push(authority_key_identifier(&key_id)?),
+        None => params.use_authority_key_identifier_extension = true,
+    }
+
+    let cert = Certificate::from_params(params)
+        .map_err(|e| anyhow!("Failed to build intermediate CA {}: {}", options.common_name, e))?;
+    let cert_der = cert.serialize_der_with_signer(&signing_ca(issuer)?)
+        .map_err(|e| anyhow!("Failed to sign intermediate CA {}: {}", options.common_name, e))?;
+
+    Ok(CertificateData::new(
+        RustlsCertificate(cert_der),
+        PrivateKey(cert.serialize_private_key_der()),
+    ))
+}
+
+fn ca_params(options: &CaOptions) -> Result<CertificateParams> {
+    if options.validity_days == 0 {
+        return Err(anyhow!("CA validity must be at least one day"));
+    }
+
+    let key_pair = generate_key_pair(options.key_algorithm)?;
+    let mut params = CertificateParams::default();
+    params.alg = key_pair.compatible_algs().next().ok_or_else(|| anyhow!("Unusable CA key pair"))?;
+    params.key_pair = Some(key_pair);
+    params.serial_number = Some(rand::random::<u64>() | 1);
+
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::OrganizationName, options.organization.as_str());
+    params.distinguished_name.push(DnType::CommonName, options.common_name.as_str());
+
+    params.is_ca = IsCa::Ca(match options.path_len {
+        Some(len) => BasicConstraints::Constrained(len),
+        None => BasicConstraints::Unconstrained,
+    });
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    if !options.permitted_dns.is_empty() || !options.excluded_dns.is_empty() {
+        params.name_constraints = Some(NameConstraints {
+            permitted_subtrees: options.permitted_dns.iter().cloned().map(GeneralSubtree::DnsName).collect(),
+            excluded_subtrees: options.excluded_dns.iter().cloned().map(GeneralSubtree::DnsName).collect(),
+        });
+    }
+
+    let now = SystemTime::now();
+    params.not_before = (now - Duration::from_secs(60 * 60)).into();
+    params.not_after = (now + Duration::from_secs(options.validity_days as u64 * 24 * 60 * 60)).into();
+    Ok(params)
+}
+
+/// A previous root CA still inside its overlap window
+#[derive(Debug, Clone)]
+pub struct RetiringCa {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Unix time the overlap window ends
+    pub trusted_until: u64,
+}
+
+/// The active root CA files and the retiring roots kept beside them
+#[derive(Debug, Clone)]
+pub struct CaStore {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl CaStore {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    pub fn cert_path(&self) -> &Path {
+        &self.cert_path
+    }
+
+    /// PEM bundle of the active root plus every retiring one
+    pub fn bundle_path(&self) -> PathBuf {
+        self.sibling(&self.cert_path, "-bundle", "pem")
+    }
+
+    pub fn exists(&self) -> bool {
+        self.cert_path.exists() || self.key_path.exists()
+    }
+
+    /// Write the CA files; the key is only readable by its owner and is
+    /// encrypted when a passphrase is given
+    pub fn save(&self, ca: &CertificateData, passphrase: Option<&str>) -> Result<()> {
+        for path in [&self.cert_path, &self.key_path] {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)
+                    .map_err(|e| anyhow!("Failed to create {}: {}", parent.display(), e))?;
+            }
+        }
+        write_atomic(&self.key_path, key_pem(ca, passphrase)?.as_bytes())?;
+        fs::write(&self.cert_path, pem_block("CERTIFICATE", &ca.cert().0))
+            .map_err(|e| anyhow!("Failed to write {}: {}", self.cert_path.display(), e))?;
+        info!("💾 Saved root CA to {} / {}", self.cert_path.display(), self.key_path.display());
+        Ok(())
+    }
+
+    /// Replace the active root with one built from `options`, keeping the old
+    /// root in the bundle for `overlap_days`
+    pub fn rotate(&self, options: &CaOptions, overlap_days: u32, passphrase: Option<&str>) -> Result<CertificateData> {
+        if !self.cert_path.exists() || !self.key_path.exists() {
+            return Err(anyhow!(
+                "No root CA at {} / {} to rotate - run 'cert ca init' first",
+                self.cert_path.display(), self.key_path.display()
+            ));
+        }
+
+        let new_ca = generate_root_ca(options)?;
+        // Retired roots are kept even without an overlap; the bundle skips them
+        let trusted_until = current_timestamp() + overlap_days as u64 * 24 * 60 * 60;
+        let suffix = format!(".retiring-{}", trusted_until);
+        let retiring_cert = self.sibling(&self.cert_path, &suffix, "crt");
+        let retiring_key = self.sibling(&self.cert_path, &suffix, "key");
+
+        // Stage the new root and copy the old one aside before touching the
+        // active files, so a failure part-way leaves the current root usable
+        let key_tmp = write_temp(&self.key_path, key_pem(&new_ca, passphrase)?.as_bytes(), 0o600)?;
+        let cert_tmp = match write_temp(&self.cert_path, pem_block("CERTIFICATE", &new_ca.cert().0).as_bytes(), 0o644) {
+            Ok(tmp) => tmp,
+            Err(e) => {
+                let _ = fs::remove_file(&key_tmp);
+                return Err(e);
+            }
+        };
+        let retired = fs::copy(&self.key_path, &retiring_key)
+            .map_err(|e| anyhow!("Failed to retire {}: {}", self.key_path.display(), e))
+            .and_then(|_| {
+                fs::copy(&self.cert_path, &retiring_cert)
+                    .map_err(|e| anyhow!("Failed to retire {}: {}", self.cert_path.display(), e))
+            });
+        if let Err(e) = retired {
+            for path in [&key_tmp, &cert_tmp, &retiring_key, &retiring_cert] {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        info!("🗄️  Previous root CA copied to {}, trusted for {} more days", retiring_cert.display(), overlap_days);
+
+        fs::rename(&key_tmp, &self.key_path)
+            .map_err(|e| anyhow!("Failed to write {}: {}", self.key_path.display(), e))?;
+        fs::rename(&cert_tmp, &self.cert_path)
+            .map_err(|e| anyhow!("Failed to write {}: {}", self.cert_path.display(), e))?;
+        info!("💾 Saved root CA to {} / {}", self.cert_path.display(), self.key_path.display());
+
+        self.write_bundle()?;
+        Ok(new_ca)
+    }
+
+    /// Retiring roots whose overlap window hasn't ended yet, newest first
+    pub fn retiring(&self) -> Result<Vec<RetiringCa>> {
+        let dir = match self.cert_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let prefix = format!("{}.retiring-", self.stem(&self.cert_path));
+        let now = current_timestamp();
+
+        let mut retiring = Vec::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(retiring),
+            Err(e) => return Err(anyhow!("Failed to read {}: {}", dir.display(), e)),
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(trusted_until) = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".crt"))
+                .and_then(|ts| ts.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if trusted_until <= now {
+                debug!("Overlap window for {} has ended", name);
+                continue;
+            }
+            let cert_path = entry.path();
+            retiring.push(RetiringCa {
+                key_path: cert_path.with_extension("key"),
+                cert_path,
+                trusted_until,
+            });
+        }
+        retiring.sort_by_key(|ca| std::cmp::Reverse(ca.trusted_until));
+        Ok(retiring)
+    }
+
+    /// The active root followed by every retiring one
+    pub fn bundle(&self) -> Result<Vec<RustlsCertificate>> {
+        let mut certs = vec![read_cert(&self.cert_path)?];
+        for retiring in self.retiring()? {
+            certs.push(read_cert(&retiring.cert_path)?);
+        }
+        Ok(certs)
+    }
+
+    /// Rewrite the PEM bundle, dropping roots whose overlap has ended
+    pub fn write_bundle(&self) -> Result<PathBuf> {
+        let pem: String = self.bundle()?.iter().map(|cert| pem_block("CERTIFICATE", &cert.0)).collect();
+        let path = self.bundle_path();
+        fs::write(&path, pem).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    fn stem(&self, path: &Path) -> String {
+        path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| "rootCA".to_string())
+    }
+
+    fn sibling(&self, path: &Path, suffix: &str, extension: &str) -> PathBuf {
+        path.with_file_name(format!("{}{}.{}", self.stem(path), suffix, extension))
+    }
+}
+
+/// PEM for the CA key, encrypted when a passphrase is given
+fn key_pem(ca: &CertificateData, passphrase: Option<&str>) -> Result<String> {
+    match passphrase {
+        Some(passphrase) => encrypt_private_key_pem(&ca.key(), passphrase),
+        None => Ok(pem_block("PRIVATE KEY", &ca.key().0)),
+    }
+}
+
+fn read_cert(path: &Path) -> Result<RustlsCertificate> {
+    let pem = fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    parse_pem_certificate(&pem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::settings::TlsConfig;
+    use crate::tls::{LeafIssuer, LeafProfile};
+    use rustls::client::{ServerCertVerifier, WebPkiVerifier};
+    use rustls::{RootCertStore, ServerName};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_name_constrained_root_only_vouches_for_permitted_hosts() {
+        let options = CaOptions {
+            path_len: Some(0),
+            permitted_dns: vec!["corp.example".to_string()],
+            excluded_dns: vec!["secret.corp.example".to_string()],
+            ..CaOptions::default()
+        };
+        let ca = generate_root_ca(&options).unwrap();
+        assert_eq!(CaOptions::from_cert(&ca.cert()).unwrap(), CaOptions { validity_days: 3650, ..options });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(&ca.cert()).unwrap();
+        let verifier = WebPkiVerifier::new(roots, None);
+        let issuer = LeafIssuer::new(&ca, LeafKeyAlgorithm::EcdsaP256, 30).unwrap();
+        let verify = |host: &str| {
+            let leaf = issuer.issue(&LeafProfile::for_host(host)).unwrap();
+            verifier.verify_server_cert(
+                &leaf.cert(), &[], &ServerName::try_from(host).unwrap(), &mut std::iter::empty(), &[], SystemTime::now(),
+            )
+        };
+
+        assert!(verify("www.corp.example").is_ok());
+        assert!(verify("example.com").is_err());
+        assert!(verify("db.secret.corp.example").is_err());
+    }
+
+    #[test]
+    fn test_intermediate_signed_leaves_carry_the_chain() {
+        let tls = TlsConfig { root_ca_cert_path: None, ..TlsConfig::default() };
+        let root = generate_root_ca(&CaOptions { path_len: Some(1), ..CaOptions::default() }).unwrap();
+        let intermediate = generate_intermediate_ca(
+            &root,
+            &CaOptions { common_name: "Test Intermediate".to_string(), path_len: Some(0), ..CaOptions::default() },
+        )
+        .unwrap();
+
+        let issuer = LeafIssuer::new(&intermediate, LeafKeyAlgorithm::EcdsaP256, 30)
+            .unwrap()
+            .with_chain(vec![root.cert()])
+            .unwrap();
+        assert_eq!(issuer.chain(), &[intermediate.cert()]);
+        issuer.validate_chain(&tls).unwrap();
+
+        let leaf = issuer.issue(&LeafProfile::for_host("example.com")).unwrap();
+        let chain = issuer.cert_chain(leaf.cert());
+        assert_eq!(chain, vec![leaf.cert(), intermediate.cert()]);
+        // Clients only holding the root need the intermediate from the handshake
+        assert!(crate::tls::validate_certificate_chain(&[leaf.cert(), root.cert()], "example.com", &tls).is_err());
+
+        // A chain file for some other root doesn't link the intermediate up
+        let other_root = generate_root_ca(&CaOptions::default()).unwrap();
+        let issuer = LeafIssuer::new(&intermediate, LeafKeyAlgorithm::EcdsaP256, 30)
+            .unwrap()
+            .with_chain(vec![other_root.cert()])
+            .unwrap();
+        assert!(issuer.validate_chain(&tls).is_err());
+    }
+
+    #[test]
+    fn test_rotation_keeps_previous_root_in_bundle_during_overlap() {
+        let dir = tempdir().unwrap();
+        let store = CaStore::new(dir.path().join("rootCA.crt"), dir.path().join("rootCA.key"));
+        let old = generate_root_ca(&CaOptions::default()).unwrap();
+        store.save(&old, None).unwrap();
+        store.write_bundle().unwrap();
+        assert_eq!(store.bundle().unwrap().len(), 1);
+
+        let new = store.rotate(&CaOptions::default(), 30, None).unwrap();
+        let bundle = store.bundle().unwrap();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle[0], new.cert());
+        assert_eq!(bundle[1], old.cert());
+        let retiring = store.retiring().unwrap();
+        assert!(retiring[0].key_path.exists());
+
+        // Without an overlap the old root is dropped straight away
+        store.rotate(&CaOptions::default(), 0, None).unwrap();
+        assert_eq!(store.bundle().unwrap().len(), 2);
+        let pem = fs::read_to_string(store.bundle_path()).unwrap();
+        assert_eq!(pem.matches("BEGIN CERTIFICATE").count(), 2);
+    }
+}
@@ -1,354 +1,584 @@
-//! Certificate caching system with Redis and in-memory backends
+//! Certificate caching system with Redis, disk and in-memory backends
 
-use crate::tls::CertificateData;
+use crate::config::settings::{CertCacheBackend, CertCacheConfig, RedisConfig};
+use crate::tls::{CertificateData, DiskCache};
+#[cfg(feature = "redis-support")]
+use crate::tls::{RedisCache, TieredCache};
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tracing::{debug, info, warn, error};
-
-#[cfg(feature = "redis-support")]
-use redis::{Commands, Connection};
+use tracing::{debug, info, warn};
 
 /// Certificate cache entry with expiration
 #[derive(Debug, Clone)]
 struct CachedCertificate {
     cert_data: CertificateData,
-    created_at: u64,
     expires_at: u64,
 }
 
+/// A host the proxy has learned to tunnel instead of intercept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BypassEntry {
+    pub host: String,
+    pub reason: String,
+    /// Unix timestamp (seconds) when the entry expires
+    pub expires_at: u64,
+}
+
+/// Connection state of a networked cache backend, as shown on `/health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CacheBackendHealth {
+    pub backend: &'static str,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Milliseconds until the next connection attempt while backing off
+    pub retry_in_ms: Option<u64>,
+}
+
+/// Lookup and eviction counters of an in-process LRU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Live entries pushed out to make room
+    pub evictions: u64,
+    /// Expired entries dropped on lookup or to make room
+    pub expirations: u64,
+}
+
 /// Certificate cache backend trait
+///
+/// Backends also store the learned interception bypass set so that every
+/// proxy sharing a cache (e.g. Redis) shares what it has learned.
+#[async_trait]
 pub trait CertificateCache: Send + Sync {
-    fn get(&self, domain: &str) -> Result<Option<CertificateData>>;
-    fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()>;
-    fn remove(&self, domain: &str) -> Result<()>;
-    fn clear(&self) -> Result<()>;
-    fn cache_info(&self) -> String;
+    async fn get(&self, domain: &str) -> Result<Option<CertificateData>>;
+    /// Like `get`, plus the entry's expiry (Unix seconds) when the backend knows it
+    async fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        Ok(self.get(domain).await?.map(|cert_data| (cert_data, None)))
+    }
+    async fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()>;
+    async fn remove(&self, domain: &str) -> Result<()>;
+    async fn clear(&self) -> Result<()>;
+    async fn cache_info(&self) -> String;
+
+    /// Record a learned bypass for `host` that expires after `ttl_seconds`
+    async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()>;
+    /// Return the live bypass entry for `host`, if any
+    async fn get_bypass(&self, host: &str) -> Result<Option<BypassEntry>>;
+    /// List all live bypass entries
+    async fn list_bypass(&self) -> Result<Vec<BypassEntry>>;
+    /// Remove the entry for `host`, or every entry when `None`; returns how many were removed
+    async fn clear_bypass(&self, host: Option<&str>) -> Result<usize>;
+
+    /// Connection health, for backends that talk to a server
+    fn health(&self) -> Option<CacheBackendHealth> {
+        None
+    }
+
+    /// LRU counters, for backends that keep certificates in process
+    fn stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+pub(crate) fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs()
+}
+
+/// Entries per LRU shard before another shard is added
+const ENTRIES_PER_SHARD: usize = 64;
+const MAX_SHARDS: usize = 16;
+
+#[derive(Debug, Default)]
+struct MemoryCacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
 }
 
 /// In-memory certificate cache implementation
+///
+/// A sharded LRU: each host hashes to one shard, so lookups for different
+/// hosts rarely contend and every operation is O(1). Expired entries are
+/// dropped when they're looked up or when the LRU pushes them out.
 pub struct MemoryCache {
-    cache: Arc<Mutex<HashMap<String, CachedCertificate>>>,
+    shards: Vec<Mutex<LruCache<String, CachedCertificate>>>,
+    bypass: Mutex<HashMap<String, BypassEntry>>,
     max_entries: usize,
+    counters: MemoryCacheCounters,
 }
 
 impl MemoryCache {
     pub fn new(max_entries: usize) -> Self {
-        debug!("Creating in-memory certificate cache (max_entries: {})", max_entries);
+        let max_entries = max_entries.max(1);
+        // Small caches get one shard so the LRU order is exact
+        let shard_count = (max_entries / ENTRIES_PER_SHARD).clamp(1, MAX_SHARDS);
+        let per_shard = NonZeroUsize::new(max_entries.div_ceil(shard_count)).unwrap();
+        debug!("Creating in-memory certificate cache (max_entries: {}, shards: {})", max_entries, shard_count);
+
         Self {
-            cache: Arc::new(Mutex::new(HashMap::new())),
+            shards: (0..shard_count).map(|_| Mutex::new(LruCache::new(per_shard))).collect(),
+            bypass: Mutex::new(HashMap::new()),
             max_entries,
+            counters: MemoryCacheCounters::default(),
         }
     }
 
-    fn current_timestamp() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0))
-            .as_secs()
+    fn shard(&self, domain: &str) -> &Mutex<LruCache<String, CachedCertificate>> {
+        let mut hasher = DefaultHasher::new();
+        domain.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
     }
 
-    fn cleanup_expired(&self) {
-        let now = Self::current_timestamp();
-        let mut cache = self.cache.lock().unwrap();
-        let initial_count = cache.len();
-        
-        cache.retain(|domain, entry| {
-            let is_valid = now < entry.expires_at;
-            if !is_valid {
-                debug!("Removing expired certificate for domain: {}", domain);
-            }
-            is_valid
-        });
-        
-        let removed = initial_count - cache.len();
-        if removed > 0 {
-            debug!("Cleaned up {} expired certificates", removed);
-        }
-    }
-
-    fn enforce_size_limit(&self) {
-        let mut cache = self.cache.lock().unwrap();
-        if cache.len() > self.max_entries {
-            // Remove oldest entries (simple LRU approximation)
-            let mut entries: Vec<(String, u64)> = cache
-                .iter()
-                .map(|(domain, entry)| (domain.clone(), entry.created_at))
-                .collect();
-            
-            entries.sort_by_key(|(_, created_at)| *created_at);
-            
-            let to_remove = cache.len() - self.max_entries;
-            for (domain, _) in entries.iter().take(to_remove) {
-                cache.remove(domain);
-                debug!("Removed old certificate for domain: {}", domain);
-            }
-            
-            debug!("Enforced cache size limit: removed {} entries", to_remove);
-        }
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
     }
 }
 
+#[async_trait]
 impl CertificateCache for MemoryCache {
-    fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
-        self.cleanup_expired();
-        
-        let cache = self.cache.lock().unwrap();
-        match cache.get(domain) {
-            Some(entry) => {
-                let now = Self::current_timestamp();
-                if now < entry.expires_at {
-                    debug!("Cache HIT for domain: {} (expires in {} seconds)", domain, entry.expires_at - now);
-                    Ok(Some(entry.cert_data.clone()))
-                } else {
-                    debug!("Cache entry expired for domain: {}", domain);
-                    Ok(None)
-                }
+    async fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
+        Ok(self.get_with_expiry(domain).await?.map(|(cert_data, _)| cert_data))
+    }
+
+    async fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        let now = current_timestamp();
+        let mut shard = self.shard(domain).lock().unwrap();
+
+        let lookup = shard.get(domain).map(|entry| (now < entry.expires_at).then(|| (entry.cert_data.clone(), entry.expires_at)));
+        match lookup {
+            Some(Some((cert_data, expires_at))) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                debug!("Cache HIT for domain: {} (expires in {} seconds)", domain, expires_at - now);
+                Ok(Some((cert_data, Some(expires_at))))
+            }
+            Some(None) => {
+                shard.pop(domain);
+                self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+                debug!("Cache entry expired for domain: {}", domain);
+                Ok(None)
             }
             None => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
                 debug!("Cache MISS for domain: {}", domain);
                 Ok(None)
             }
         }
     }
 
-    fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
-        let now = Self::current_timestamp();
-        let expires_at = now + ttl_seconds;
-        
+    async fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
+        let now = current_timestamp();
         let entry = CachedCertificate {
             cert_data,
-            created_at: now,
-            expires_at,
+            expires_at: now + ttl_seconds,
         };
-        
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(domain.to_string(), entry);
+
+        let evicted = self.shard(domain).lock().unwrap().push(domain.to_string(), entry);
+        match evicted.filter(|(key, _)| key != domain) {
+            Some((evicted, entry)) if now < entry.expires_at => {
+                self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+                debug!("Evicted least recently used certificate for domain: {}", evicted);
+            }
+            Some(_) => {
+                self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {}
         }
-        
-        self.enforce_size_limit();
-        
+
         debug!("Cached certificate for domain: {} (TTL: {} seconds)", domain, ttl_seconds);
         Ok(())
     }
 
-    fn remove(&self, domain: &str) -> Result<()> {
-        let mut cache = self.cache.lock().unwrap();
-        if cache.remove(domain).is_some() {
+    async fn remove(&self, domain: &str) -> Result<()> {
+        if self.shard(domain).lock().unwrap().pop(domain).is_some() {
             debug!("Removed certificate from cache for domain: {}", domain);
         }
         Ok(())
     }
 
-    fn clear(&self) -> Result<()> {
-        let mut cache = self.cache.lock().unwrap();
-        let count = cache.len();
-        cache.clear();
+    async fn clear(&self) -> Result<()> {
+        let mut count = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            count += shard.len();
+            shard.clear();
+        }
         info!("Cleared certificate cache ({} entries removed)", count);
         Ok(())
     }
 
-    fn cache_info(&self) -> String {
-        let cache = self.cache.lock().unwrap();
-        format!("Memory cache: {}/{} entries", cache.len(), self.max_entries)
+    async fn cache_info(&self) -> String {
+        format!("Memory cache: {}/{} entries", self.len(), self.max_entries)
     }
-}
 
-/// Redis certificate cache implementation
-#[cfg(feature = "redis-support")]
-pub struct RedisCache {
-    connection: Arc<Mutex<Connection>>,
-    key_prefix: String,
-}
-
-#[cfg(feature = "redis-support")]
-impl RedisCache {
-    pub fn new(redis_url: &str, key_prefix: Option<String>) -> Result<Self> {
-        let client = redis::Client::open(redis_url)
-            .map_err(|e| anyhow!("Failed to create Redis client: {}", e))?;
-            
-        let connection = client.get_connection()
-            .map_err(|e| anyhow!("Failed to connect to Redis: {}", e))?;
-            
-        let key_prefix = key_prefix.unwrap_or_else(|| "proxy:cert:".to_string());
-        
-        info!("Connected to Redis certificate cache (prefix: {})", key_prefix);
-        
-        Ok(Self {
-            connection: Arc::new(Mutex::new(connection)),
-            key_prefix,
-        })
+    async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()> {
+        let entry = BypassEntry {
+            host: host.to_string(),
+            reason: reason.to_string(),
+            expires_at: current_timestamp() + ttl_seconds,
+        };
+        self.bypass.lock().unwrap().insert(host.to_string(), entry);
+        Ok(())
     }
 
-    fn make_key(&self, domain: &str) -> String {
-        format!("{}{}", self.key_prefix, domain)
+    async fn get_bypass(&self, host: &str) -> Result<Option<BypassEntry>> {
+        let now = current_timestamp();
+        let mut bypass = self.bypass.lock().unwrap();
+        bypass.retain(|_, entry| now < entry.expires_at);
+        Ok(bypass.get(host).cloned())
     }
-}
 
-#[cfg(feature = "redis-support")]
-impl CertificateCache for RedisCache {
-    fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
-        let key = self.make_key(domain);
-        let mut conn = self.connection.lock().unwrap();
-        
-        match conn.get::<_, Option<Vec<u8>>>(&key) {
-            Ok(Some(data)) => {
-                match bincode::deserialize::<CertificateData>(&data) {
-                    Ok(cert_data) => {
-                        debug!("Redis cache HIT for domain: {}", domain);
-                        Ok(Some(cert_data))
-                    }
-                    Err(e) => {
-                        warn!("Failed to deserialize cached certificate for {}: {}", domain, e);
-                        // Remove corrupted data
-                        let _ = conn.del::<_, ()>(&key);
-                        Ok(None)
-                    }
-                }
-            }
-            Ok(None) => {
-                debug!("Redis cache MISS for domain: {}", domain);
-                Ok(None)
-            }
-            Err(e) => {
-                error!("Redis get error for domain {}: {}", domain, e);
-                Ok(None) // Graceful degradation - don't fail the request
-            }
-        }
+    async fn list_bypass(&self) -> Result<Vec<BypassEntry>> {
+        let now = current_timestamp();
+        let mut bypass = self.bypass.lock().unwrap();
+        bypass.retain(|_, entry| now < entry.expires_at);
+        Ok(bypass.values().cloned().collect())
     }
 
-    fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
-        let key = self.make_key(domain);
-        let data = bincode::serialize(&cert_data)
-            .map_err(|e| anyhow!("Failed to serialize certificate data: {}", e))?;
-            
-        let mut conn = self.connection.lock().unwrap();
-        
-        match conn.set_ex::<_, _, ()>(&key, data, ttl_seconds.try_into().unwrap()) {
-            Ok(_) => {
-                debug!("Cached certificate in Redis for domain: {} (TTL: {} seconds)", domain, ttl_seconds);
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to cache certificate in Redis for domain {}: {}", domain, e);
-                Err(anyhow!("Redis set error: {}", e))
+    async fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        let mut bypass = self.bypass.lock().unwrap();
+        match host {
+            Some(host) => Ok(bypass.remove(host).map_or(0, |_| 1)),
+            None => {
+                let count = bypass.len();
+                bypass.clear();
+                Ok(count)
             }
         }
     }
 
-    fn remove(&self, domain: &str) -> Result<()> {
-        let key = self.make_key(domain);
-        let mut conn = self.connection.lock().unwrap();
-        
-        match conn.del::<_, i32>(&key) {
-            Ok(count) => {
-                if count > 0 {
-                    debug!("Removed certificate from Redis cache for domain: {}", domain);
-                }
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to remove certificate from Redis for domain {}: {}", domain, e);
-                Err(anyhow!("Redis delete error: {}", e))
-            }
-        }
+    fn stats(&self) -> Option<CacheStats> {
+        let c = &self.counters;
+        Some(CacheStats {
+            entries: self.len(),
+            capacity: self.max_entries,
+            hits: c.hits.load(Ordering::Relaxed),
+            misses: c.misses.load(Ordering::Relaxed),
+            evictions: c.evictions.load(Ordering::Relaxed),
+            expirations: c.expirations.load(Ordering::Relaxed),
+        })
     }
+}
 
-    fn clear(&self) -> Result<()> {
-        let pattern = format!("{}*", self.key_prefix);
-        let mut conn = self.connection.lock().unwrap();
-        
-        // Get all keys with the prefix
-        let keys: Vec<String> = conn.keys(&pattern)
-            .map_err(|e| anyhow!("Failed to get Redis keys: {}", e))?;
-            
-        if !keys.is_empty() {
-            let count: i32 = conn.del(&keys)
-                .map_err(|e| anyhow!("Failed to delete Redis keys: {}", e))?;
-            info!("Cleared Redis certificate cache ({} entries removed)", count);
-        }
-        
-        Ok(())
+fn memory_backend(max_entries: usize) -> Box<dyn CertificateCache> {
+    info!("🧠 Using in-memory certificate cache");
+    Box::new(MemoryCache::new(max_entries))
+}
+
+/// Redis, fronted by an in-process L1 unless `l1_entries` is 0
+#[cfg(feature = "redis-support")]
+fn redis_backend(config: &RedisConfig, l1_entries: usize) -> Result<Box<dyn CertificateCache>> {
+    let redis = RedisCache::new(config, Some("proxy:cert:".to_string()))?;
+    if l1_entries == 0 {
+        info!("🚀 Using Redis certificate cache");
+        return Ok(Box::new(redis));
     }
 
-    fn cache_info(&self) -> String {
-        let mut conn = self.connection.lock().unwrap();
-        let pattern = format!("{}*", self.key_prefix);
-        
-        match conn.keys::<_, Vec<String>>(&pattern) {
-            Ok(keys) => format!("Redis cache: {} entries (prefix: {})", keys.len(), self.key_prefix),
-            Err(_) => format!("Redis cache: unknown entries (prefix: {})", self.key_prefix),
-        }
+    let pool = redis.pool();
+    let tiered = TieredCache::new(Box::new(redis), l1_entries).with_redis_invalidation(pool)?;
+    info!("🚀 Using Redis certificate cache with {}-entry memory L1", l1_entries);
+    Ok(Box::new(tiered))
+}
+
+/// Renew once less than an hour, or a quarter of the lifetime, remains
+const RENEW_BEFORE_SECS: u64 = 60 * 60;
+
+fn renewal_due(expires_at: u64, ttl_seconds: u64) -> bool {
+    expires_at.saturating_sub(current_timestamp()) < RENEW_BEFORE_SECS.min(ttl_seconds / 4)
+}
+
+/// A certificate generation other requests for the same key can await
+type InFlight = Shared<BoxFuture<'static, std::result::Result<CertificateData, Arc<anyhow::Error>>>>;
+
+/// Certificate lookup and generation counters
+#[derive(Debug, Default)]
+struct CertificateMetrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    generated: AtomicU64,
+    generation_failures: AtomicU64,
+    coalesced_waits: AtomicU64,
+    renewals: AtomicU64,
+}
+
+/// Point-in-time copy of the certificate counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CertificateMetricsSnapshot {
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub generated: u64,
+    pub generation_failures: u64,
+    /// Requests that waited for another request's generation instead of generating
+    pub coalesced_waits: u64,
+    /// Certificates regenerated in the background ahead of their expiry
+    pub renewals: u64,
+    /// LRU counters of the in-process cache (the L1 tier in front of Redis)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_cache: Option<CacheStats>,
+}
+
+/// Removes an in-flight entry even if its leader is cancelled
+struct InFlightGuard<'a> {
+    in_flight: &'a Mutex<HashMap<String, InFlight>>,
+    key: &'a str,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(self.key);
     }
 }
 
 /// Certificate cache factory
 pub struct CertificateManager {
-    cache: Box<dyn CertificateCache>,
+    cache: Arc<dyn CertificateCache>,
     default_ttl: u64,
+    in_flight: Mutex<HashMap<String, InFlight>>,
+    /// Keys with a background renewal running
+    renewing: Arc<Mutex<HashSet<String>>>,
+    metrics: Arc<CertificateMetrics>,
 }
 
 impl CertificateManager {
     /// Create a new certificate manager with automatic backend selection
     pub fn new() -> Self {
+        Self::from_config(&CertCacheConfig::default(), &RedisConfig::default())
+    }
+
+    /// Create a certificate manager with the backend chosen by `tls.cert_cache`
+    #[cfg_attr(not(feature = "redis-support"), allow(unused_variables))]
+    pub fn from_config(config: &CertCacheConfig, redis: &RedisConfig) -> Self {
         let default_ttl = 24 * 60 * 60; // 24 hours
-        
-        // Try to use Redis if available and enabled
-        #[cfg(feature = "redis-support")]
-        if let Ok(redis_url) = std::env::var("REDIS_URL") {
-            match RedisCache::new(&redis_url, Some("proxy:cert:".to_string())) {
-                Ok(redis_cache) => {
-                    info!("🚀 Using Redis certificate cache");
-                    return Self {
-                        cache: Box::new(redis_cache),
-                        default_ttl,
-                    };
-                }
-                Err(e) => {
-                    warn!("Failed to connect to Redis cache: {}", e);
-                    info!("Falling back to in-memory certificate cache");
-                }
+
+        let cache: Result<Box<dyn CertificateCache>> = match config.backend {
+            // Redis only when REDIS_URL says where it is
+            #[cfg(feature = "redis-support")]
+            CertCacheBackend::Auto => match std::env::var("REDIS_URL") {
+                Ok(url) => redis_backend(&RedisConfig { url, ..redis.clone() }, config.l1_entries),
+                Err(_) => Ok(memory_backend(config.max_entries)),
+            },
+            #[cfg(not(feature = "redis-support"))]
+            CertCacheBackend::Auto => Ok(memory_backend(config.max_entries)),
+            CertCacheBackend::Memory => Ok(memory_backend(config.max_entries)),
+            CertCacheBackend::Disk => DiskCache::new(&config.path, config.format, config.max_entries)
+                .map(|cache| Box::new(cache) as Box<dyn CertificateCache>),
+            #[cfg(feature = "redis-support")]
+            CertCacheBackend::Redis => {
+                let url = config.redis_url.clone().unwrap_or_else(|| redis.url.clone());
+                redis_backend(&RedisConfig { url, ..redis.clone() }, config.l1_entries)
+            }
+            #[cfg(not(feature = "redis-support"))]
+            CertCacheBackend::Redis => Err(anyhow!("built without redis-support")),
+        };
+
+        match cache {
+            Ok(cache) => Self::with_cache_and_ttl(cache, default_ttl),
+            Err(e) => {
+                warn!("Failed to open {:?} certificate cache: {}", config.backend, e);
+                Self::with_cache_and_ttl(memory_backend(config.max_entries), default_ttl)
             }
         }
-        
-        // Fallback to in-memory cache
-        info!("🧠 Using in-memory certificate cache");
+    }
+
+    /// Create a certificate manager around a specific cache backend
+    pub fn with_cache(cache: Box<dyn CertificateCache>) -> Self {
+        Self::with_cache_and_ttl(cache, 24 * 60 * 60) // 24 hours
+    }
+
+    fn with_cache_and_ttl(cache: Box<dyn CertificateCache>, default_ttl: u64) -> Self {
         Self {
-            cache: Box::new(MemoryCache::new(1000)), // Max 1000 certificates
+            cache: Arc::from(cache),
             default_ttl,
+            in_flight: Mutex::new(HashMap::new()),
+            renewing: Arc::new(Mutex::new(HashSet::new())),
+            metrics: Arc::new(CertificateMetrics::default()),
         }
     }
 
     /// Get certificate from cache
-    pub fn get_certificate(&self, domain: &str) -> Result<Option<CertificateData>> {
-        self.cache.get(domain)
+    pub async fn get_certificate(&self, domain: &str) -> Result<Option<CertificateData>> {
+        self.cache.get(domain).await
     }
 
     /// Cache a certificate
-    pub fn cache_certificate(&self, domain: &str, cert_data: CertificateData) -> Result<()> {
-        self.cache.set(domain, cert_data, self.default_ttl)
+    pub async fn cache_certificate(&self, domain: &str, cert_data: CertificateData) -> Result<()> {
+        self.cache.set(domain, cert_data, self.default_ttl).await
     }
 
     /// Cache a certificate with custom TTL
-    pub fn cache_certificate_with_ttl(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
-        self.cache.set(domain, cert_data, ttl_seconds)
+    pub async fn cache_certificate_with_ttl(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
+        self.cache.set(domain, cert_data, ttl_seconds).await
+    }
+
+    /// Get the certificate for `key`, generating and caching it on a miss
+    ///
+    /// Concurrent misses for the same key share one generation: the first
+    /// caller runs `generate`, the rest await its result. A `ttl_seconds` of 0
+    /// skips caching. Hits close to expiry are served as-is while `generate`
+    /// renews the entry in the background.
+    pub async fn get_or_generate<F, Fut>(&self, key: &str, ttl_seconds: u64, generate: F) -> Result<CertificateData>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CertificateData>> + Send + 'static,
+    {
+        match self.cache.get_with_expiry(key).await {
+            Ok(Some((cert_data, expires_at))) => {
+                self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+                info!("🎯 Using cached certificate for {}", key);
+                if expires_at.is_some_and(|expires_at| renewal_due(expires_at, ttl_seconds)) {
+                    self.renew(key, ttl_seconds, generate());
+                }
+                return Ok(cert_data);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Certificate cache error for {}: {}", key, e),
+        }
+        self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+        
+        let (generation, leader) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(key) {
+                Some(generation) => {
+                    self.metrics.coalesced_waits.fetch_add(1, Ordering::Relaxed);
+                    info!("⏳ Waiting for in-flight certificate generation for {}", key);
+                    (generation.clone(), None)
+                }
+                None => {
+                    let generation = generate().map(|result| result.map_err(Arc::new)).boxed().shared();
+                    in_flight.insert(key.to_string(), generation.clone());
+                    (generation, Some(InFlightGuard { in_flight: &self.in_flight, key }))
+                }
+            }
+        };
+        
+        let result = generation.await;
+        
+        // Only the leader caches, so concurrent generations can't overwrite each other
+        if let Some(_guard) = leader {
+            match &result {
+                Ok(cert_data) => {
+                    self.metrics.generated.fetch_add(1, Ordering::Relaxed);
+                    info!("💾 Generated new certificate for {}", key);
+                    if ttl_seconds > 0 {
+                        if let Err(e) = self.cache.set(key, cert_data.clone(), ttl_seconds).await {
+                            warn!("Failed to cache certificate for {}: {}", key, e);
+                        }
+                    }
+                }
+                Err(_) => {
+                    self.metrics.generation_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        
+        result.map_err(|e| anyhow!("{:#}", e))
+    }
+
+    /// Regenerate `key` in the background unless a renewal is already running
+    fn renew<Fut>(&self, key: &str, ttl_seconds: u64, generation: Fut)
+    where
+        Fut: Future<Output = Result<CertificateData>> + Send + 'static,
+    {
+        if !self.renewing.lock().unwrap().insert(key.to_string()) {
+            return;
+        }
+        info!("🔄 Renewing certificate for {} ahead of expiry", key);
+
+        let cache = Arc::clone(&self.cache);
+        let metrics = Arc::clone(&self.metrics);
+        let renewing = Arc::clone(&self.renewing);
+        let key = key.to_string();
+        tokio::spawn(async move {
+            match generation.await {
+                Ok(cert_data) => match cache.set(&key, cert_data, ttl_seconds).await {
+                    Ok(()) => {
+                        metrics.renewals.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => warn!("Failed to cache renewed certificate for {}: {}", key, e),
+                },
+                Err(e) => {
+                    metrics.generation_failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Failed to renew certificate for {}: {}", key, e);
+                }
+            }
+            renewing.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Health of the cache backend's server connection, if it has one
+    pub fn backend_health(&self) -> Option<CacheBackendHealth> {
+        self.cache.health()
+    }
+
+    /// Snapshot of the lookup and generation counters
+    pub fn metrics(&self) -> CertificateMetricsSnapshot {
+        let m = &self.metrics;
+        CertificateMetricsSnapshot {
+            cache_hits: m.cache_hits.load(Ordering::Relaxed),
+            cache_misses: m.cache_misses.load(Ordering::Relaxed),
+            generated: m.generated.load(Ordering::Relaxed),
+            generation_failures: m.generation_failures.load(Ordering::Relaxed),
+            coalesced_waits: m.coalesced_waits.load(Ordering::Relaxed),
+            renewals: m.renewals.load(Ordering::Relaxed),
+            memory_cache: self.cache.stats(),
+        }
     }
 
     /// Remove certificate from cache
-    pub fn remove_certificate(&self, domain: &str) -> Result<()> {
-        self.cache.remove(domain)
+    pub async fn remove_certificate(&self, domain: &str) -> Result<()> {
+        self.cache.remove(domain).await
     }
 
     /// Clear all cached certificates
-    pub fn clear_cache(&self) -> Result<()> {
-        self.cache.clear()
+    pub async fn clear_cache(&self) -> Result<()> {
+        self.cache.clear().await
     }
 
     /// Get cache information
-    pub fn cache_info(&self) -> String {
-        self.cache.cache_info()
+    pub async fn cache_info(&self) -> String {
+        self.cache.cache_info().await
+    }
+
+    /// Tunnel `host` instead of intercepting it for `ttl_seconds`
+    pub async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()> {
+        self.cache.mark_bypass(host, reason, ttl_seconds).await
+    }
+
+    /// Learned bypass entry for `host`, treating cache errors as "not bypassed"
+    pub async fn get_bypass(&self, host: &str) -> Option<BypassEntry> {
+        self.cache.get_bypass(host).await.unwrap_or_else(|e| {
+            warn!("Bypass lookup failed for {}: {}", host, e);
+            None
+        })
+    }
+
+    /// List learned bypass entries
+    pub async fn list_bypass(&self) -> Result<Vec<BypassEntry>> {
+        self.cache.list_bypass().await
+    }
+
+    /// Forget learned bypass entries (one host, or all)
+    pub async fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        self.cache.clear_bypass(host).await
     }
 }
 
@@ -358,3 +588,100 @@ impl Default for CertificateManager {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::generate_self_signed_cert;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_generation() {
+        let manager = Arc::new(CertificateManager::with_cache(Box::new(MemoryCache::new(10))));
+        let generations = Arc::new(AtomicUsize::new(0));
+
+        let requests = (0..6).map(|_| {
+            let manager = Arc::clone(&manager);
+            let generations = Arc::clone(&generations);
+            tokio::spawn(async move {
+                manager
+                    .get_or_generate("example.com", 60, move || async move {
+                        generations.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        generate_self_signed_cert("Test", "example.com", 1)
+                    })
+                    .await
+                    .map(|cert| cert.cert().0)
+            })
+        });
+        let certs: Vec<_> = futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap().unwrap())
+            .collect();
+
+        assert_eq!(generations.load(Ordering::SeqCst), 1);
+        assert!(certs.iter().all(|cert| *cert == certs[0]));
+        let metrics = manager.metrics();
+        assert_eq!(metrics.generated, 1);
+        assert_eq!(metrics.coalesced_waits, 5);
+
+        // The result was cached and the in-flight entry cleared
+        manager.get_or_generate("example.com", 60, || async { Err(anyhow!("unused")) }).await.unwrap();
+        assert_eq!(manager.metrics().cache_hits, 1);
+        assert!(manager.in_flight.lock().unwrap().is_empty());
+
+        // Failures are shared too, and not cached
+        let err = manager.get_or_generate("other.com", 60, || async { Err(anyhow!("CA unavailable")) }).await;
+        assert!(err.unwrap_err().to_string().contains("CA unavailable"));
+        assert_eq!(manager.metrics().generation_failures, 1);
+        assert!(manager.get_certificate("other.com").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_cache_evicts_least_recently_used() {
+        let cache = MemoryCache::new(2);
+        let cert = generate_self_signed_cert("Test", "example.com", 1).unwrap();
+
+        cache.set("a.com", cert.clone(), 60).await.unwrap();
+        cache.set("b.com", cert.clone(), 60).await.unwrap();
+        assert!(cache.get("a.com").await.unwrap().is_some());
+        cache.set("c.com", cert.clone(), 60).await.unwrap();
+
+        // b.com was the least recently used
+        assert!(cache.get("b.com").await.unwrap().is_none());
+        assert!(cache.get("a.com").await.unwrap().is_some());
+        cache.set("expired.com", cert, 0).await.unwrap();
+        assert!(cache.get("expired.com").await.unwrap().is_none());
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.entries, 1);
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+        assert_eq!((stats.evictions, stats.expirations), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_entries_near_expiry_renew_in_background() {
+        let manager = CertificateManager::with_cache(Box::new(MemoryCache::new(10)));
+        let old = generate_self_signed_cert("Test", "old.example.com", 1).unwrap();
+        manager.cache_certificate_with_ttl("example.com", old.clone(), 60).await.unwrap();
+
+        // The hit is served immediately; the replacement lands later
+        let served = manager
+            .get_or_generate("example.com", 3600, || async { generate_self_signed_cert("Test", "example.com", 1) })
+            .await
+            .unwrap();
+        assert_eq!(served.cert().0, old.cert().0);
+
+        for _ in 0..100 {
+            if manager.metrics().renewals == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let renewed = manager.get_certificate("example.com").await.unwrap().unwrap();
+        assert_ne!(renewed.cert().0, old.cert().0);
+        assert!(manager.renewing.lock().unwrap().is_empty());
+        assert_eq!(manager.metrics().memory_cache.unwrap().entries, 1);
+    }
+}
@@ -1,15 +1,28 @@
 //! TLS configuration utilities
 
 use crate::config::settings::TlsConfig;
+use crate::tls::{CertExceptions, UpstreamCertVerifier};
 use anyhow::{anyhow, Result};
 use rustls::{ServerConfig, ClientConfig, RootCertStore, Certificate, PrivateKey};
 use rustls::client::{ServerCertVerifier, ServerCertVerified};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 use std::sync::Arc;
 use tracing::{info, debug};
+use x509_parser::der_parser::oid::Oid;
+use x509_parser::objects::{oid2sn, oid_registry};
+use x509_parser::oid_registry::OID_SIG_ED25519;
+use x509_parser::prelude::{ASN1Time, GeneralName};
+use x509_parser::public_key::PublicKey;
 
 /// Create rustls ServerConfig for TLS termination
+///
+/// `cert_chain` is the leaf followed by any intermediates to send with it.
 pub fn create_server_config(
-    cert: Certificate,
+    cert_chain: Vec<Certificate>,
     key: PrivateKey,
     tls_config: &TlsConfig,
 ) -> Result<Arc<ServerConfig>> {
@@ -18,7 +31,7 @@ pub fn create_server_config(
     let mut config = ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth()
-        .with_single_cert(vec![cert], key)
+        .with_single_cert(cert_chain, key)
         .map_err(|e| anyhow!("Failed to create TLS server config: {}", e))?;
     
     // Configure ALPN protocols for HTTP/1.1 and HTTP/2
@@ -32,37 +45,28 @@ pub fn create_server_config(
 }
 
 /// Create rustls ClientConfig for upstream connections
-pub fn create_client_config(tls_config: &TlsConfig) -> Result<Arc<ClientConfig>> {
+///
+/// Certificates are checked by `UpstreamCertVerifier`, which applies the
+/// `tls.upstream_trust` rule matching each host and the global settings
+/// otherwise, and lets `exceptions` through. ALPN is left empty for the HTTP
+/// connector to fill in.
+pub fn create_client_config(tls_config: &TlsConfig, exceptions: Arc<CertExceptions>) -> Result<Arc<ClientConfig>> {
     debug!("Creating TLS client configuration");
     
-    let mut root_store = RootCertStore::empty();
-    
-    // Add system root certificates for proper certificate validation
-    add_system_root_certificates(&mut root_store)?;
-    
-    // Add custom root CA certificate if specified
-    if let Some(root_ca_path) = &tls_config.root_ca_cert_path {
-        if let Err(e) = add_custom_root_ca(&mut root_store, root_ca_path) {
-            tracing::warn!("Failed to load custom root CA: {}", e);
-            info!("   Continuing with system root certificates only");
-        }
+    if tls_config.skip_upstream_cert_verify {
+        info!("⚠️  WARNING: Skipping upstream certificate verification (insecure)");
     }
     
-    let config = if tls_config.skip_upstream_cert_verify {
-        info!("⚠️  WARNING: Skipping upstream certificate verification (insecure)");
-        ClientConfig::builder()
-            .with_safe_defaults()
-            .with_custom_certificate_verifier(Arc::new(AcceptAllCertVerifier))
-            .with_no_client_auth()
-    } else {
-        ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(root_store)
-            .with_no_client_auth()
-    };
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(UpstreamCertVerifier::from_config(tls_config, exceptions)?))
+        .with_no_client_auth();
     
     info!("✅ TLS client configuration created");
     info!("   Certificate verification: {}", !tls_config.skip_upstream_cert_verify);
+    if !tls_config.upstream_trust.is_empty() {
+        info!("   Per-host trust rules: {}", tls_config.upstream_trust.len());
+    }
     
     Ok(Arc::new(config))
 }
@@ -102,7 +106,7 @@ pub fn validate_tls_config(tls_config: &TlsConfig) -> Result<()> {
 }
 
 /// Add system root certificates to the root store
-fn add_system_root_certificates(root_store: &mut RootCertStore) -> Result<()> {
+pub fn add_system_root_certificates(root_store: &mut RootCertStore) -> Result<()> {
     debug!("Loading system root certificates");
     
     match rustls_native_certs::load_native_certs() {
@@ -160,6 +164,10 @@ pub fn add_custom_root_ca(root_store: &mut RootCertStore, root_ca_path: &str) ->
 }
 
 /// Validate a certificate chain against the root store
+///
+/// `cert_chain` is the leaf followed by intermediates. Self-signed
+/// certificates in it are trusted alongside the system roots and
+/// `root_ca_cert_path`, so a chain file ending in its root validates on its own.
 pub fn validate_certificate_chain(
     cert_chain: &[Certificate],
     server_name: &str,
@@ -167,66 +175,194 @@ pub fn validate_certificate_chain(
 ) -> Result<()> {
     debug!("Validating certificate chain for {}", server_name);
     
-    if tls_config.skip_upstream_cert_verify {
-        debug!("⚠️  Skipping certificate validation (disabled in config)");
-        return Ok(());
+    let (leaf, rest) = cert_chain.split_first().ok_or_else(|| anyhow!("Empty certificate chain"))?;
+    
+    let mut root_store = RootCertStore::empty();
+    add_system_root_certificates(&mut root_store)?;
+    if let Some(root_ca_path) = &tls_config.root_ca_cert_path {
+        if let Err(e) = add_custom_root_ca(&mut root_store, root_ca_path) {
+            debug!("Custom root CA not used for chain validation: {}", e);
+        }
     }
     
-    if cert_chain.is_empty() {
-        return Err(anyhow!("Empty certificate chain"));
+    let mut intermediates = Vec::new();
+    for cert in rest {
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)
+            .map_err(|e| anyhow!("Failed to parse chain certificate: {}", e))?;
+        if parsed.subject().as_raw() == parsed.issuer().as_raw() {
+            root_store.add(cert).map_err(|e| anyhow!("Unusable root certificate in chain: {:?}", e))?;
+        } else {
+            intermediates.push(cert.clone());
+        }
     }
     
-    // TODO: Implement full certificate chain validation
-    // This would include:
-    // 1. Verify certificate signatures
-    // 2. Check certificate validity dates
-    // 3. Validate certificate chain to root CA
-    // 4. Check server name against certificate subject/SAN
-    // 5. Verify certificate key usage and extended key usage
+    let name = rustls::ServerName::try_from(server_name)
+        .map_err(|_| anyhow!("Invalid server name: {}", server_name))?;
+    rustls::client::WebPkiVerifier::new(root_store, None)
+        .verify_server_cert(leaf, &intermediates, &name, &mut std::iter::empty(), &[], std::time::SystemTime::now())
+        .map_err(|e| anyhow!("Certificate chain for {} doesn't validate: {}", server_name, e))?;
     
-    info!("📜 Certificate chain validation:");
-    info!("   Server: {}", server_name);
-    info!("   Chain length: {} certificates", cert_chain.len());
-    info!("   Validation: Not yet fully implemented");
+    info!("✅ Certificate chain validated for {} ({} intermediates)", server_name, intermediates.len());
     
     Ok(())
 }
 
 /// Extract certificate information for inspection
-pub fn extract_certificate_info(_cert: &Certificate) -> CertificateInfo {
-    // TODO: Parse certificate and extract detailed information
-    // For now, return basic information
-    CertificateInfo {
-        subject: "Unknown".to_string(),
-        issuer: "Unknown".to_string(),
-        serial_number: "Unknown".to_string(),
-        not_before: "Unknown".to_string(),
-        not_after: "Unknown".to_string(),
-        signature_algorithm: "Unknown".to_string(),
-        public_key_algorithm: "Unknown".to_string(),
-        key_size: 0,
-        fingerprint_sha1: "Unknown".to_string(),
-        fingerprint_sha256: "Unknown".to_string(),
-        extensions: Vec::new(),
-        is_ca: false,
+pub fn extract_certificate_info(cert: &Certificate) -> Result<CertificateInfo> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(&cert.0)
+        .map_err(|e| anyhow!("Failed to parse certificate: {}", e))?;
+    let registry = oid_registry();
+    let name_of = |oid: &Oid| oid2sn(oid, registry).map(str::to_string).unwrap_or_else(|_| oid.to_id_string());
+    let time = |t: ASN1Time| DateTime::<Utc>::from_timestamp(t.timestamp(), 0).unwrap_or_default();
+
+    let mut subject_alt_names = Vec::new();
+    if let Ok(Some(san)) = parsed.subject_alternative_name() {
+        for name in &san.value.general_names {
+            subject_alt_names.push(match name {
+                GeneralName::DNSName(dns) => format!("DNS:{}", dns),
+                GeneralName::IPAddress(ip) => match <[u8; 4]>::try_from(*ip) {
+                    Ok(v4) => format!("IP:{}", IpAddr::from(v4)),
+                    Err(_) => match <[u8; 16]>::try_from(*ip) {
+                        Ok(v6) => format!("IP:{}", IpAddr::from(v6)),
+                        Err(_) => format!("IP:{:02x?}", ip),
+                    },
+                },
+                GeneralName::RFC822Name(email) => format!("email:{}", email),
+                GeneralName::URI(uri) => format!("URI:{}", uri),
+                other => format!("{:?}", other),
+            });
+        }
     }
+
+    let spki = parsed.public_key();
+    let (public_key_algorithm, key_size) = match spki.parsed() {
+        Ok(PublicKey::RSA(rsa)) => ("RSA".to_string(), rsa.key_size()),
+        Ok(PublicKey::EC(point)) => {
+            let curve = spki.algorithm.parameters.as_ref().and_then(|p| p.as_oid().ok());
+            match curve {
+                Some(curve) => (format!("EC ({})", name_of(&curve)), point.key_size()),
+                None => ("EC".to_string(), point.key_size()),
+            }
+        }
+        _ if spki.algorithm.algorithm == OID_SIG_ED25519 => ("Ed25519".to_string(), 256),
+        Ok(key) => (name_of(&spki.algorithm.algorithm), key.key_size()),
+        Err(_) => (name_of(&spki.algorithm.algorithm), 0),
+    };
+
+    let basic_constraints = parsed.basic_constraints().ok().flatten().map(|ext| ext.value);
+    let hex = |digest: &[u8]| digest.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":");
+
+    Ok(CertificateInfo {
+        subject: parsed.subject().to_string(),
+        issuer: parsed.issuer().to_string(),
+        serial_number: parsed.raw_serial_as_string(),
+        not_before: time(parsed.validity().not_before),
+        not_after: time(parsed.validity().not_after),
+        subject_alt_names,
+        signature_algorithm: name_of(&parsed.signature_algorithm.algorithm),
+        public_key_algorithm,
+        key_size,
+        fingerprint_sha1: hex(&Sha1::digest(&cert.0)),
+        fingerprint_sha256: hex(&Sha256::digest(&cert.0)),
+        key_usage: parsed
+            .key_usage()
+            .ok()
+            .flatten()
+            .map(|ext| ext.value.to_string().split(", ").map(str::to_string).collect())
+            .unwrap_or_default(),
+        extended_key_usage: parsed
+            .extended_key_usage()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                let eku = ext.value;
+                let mut usages: Vec<String> = [
+                    (eku.any, "anyExtendedKeyUsage"),
+                    (eku.server_auth, "serverAuth"),
+                    (eku.client_auth, "clientAuth"),
+                    (eku.code_signing, "codeSigning"),
+                    (eku.email_protection, "emailProtection"),
+                    (eku.time_stamping, "timeStamping"),
+                    (eku.ocsp_signing, "OCSPSigning"),
+                ]
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, name)| name.to_string())
+                .collect();
+                usages.extend(eku.other.iter().map(&name_of));
+                usages
+            })
+            .unwrap_or_default(),
+        extensions: parsed
+            .extensions()
+            .iter()
+            .map(|ext| CertificateExtension {
+                oid: ext.oid.to_id_string(),
+                name: name_of(&ext.oid),
+                critical: ext.critical,
+            })
+            .collect(),
+        is_ca: basic_constraints.is_some_and(|bc| bc.ca),
+        path_len_constraint: basic_constraints.and_then(|bc| bc.path_len_constraint),
+        is_self_signed: parsed.subject().as_raw() == parsed.issuer().as_raw(),
+    })
+}
+
+/// Parse every certificate in `data`, which is either PEM (possibly a
+/// bundle of several certificates) or a single DER certificate
+pub fn parse_certificates(data: &[u8]) -> Result<Vec<Certificate>> {
+    if !data.windows(11).any(|w| w == b"-----BEGIN ") {
+        x509_parser::parse_x509_certificate(data)
+            .map_err(|e| anyhow!("Neither PEM nor a DER certificate: {}", e))?;
+        return Ok(vec![Certificate(data.to_vec())]);
+    }
+
+    let certs = rustls_pemfile::certs(&mut &data[..])
+        .map_err(|e| anyhow!("Failed to parse PEM certificates: {}", e))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in PEM data"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
 }
 
 /// Certificate information structure
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CertificateInfo {
     pub subject: String,
     pub issuer: String,
     pub serial_number: String,
-    pub not_before: String,
-    pub not_after: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// `DNS:`, `IP:`, `email:` or `URI:` prefixed names
+    pub subject_alt_names: Vec<String>,
     pub signature_algorithm: String,
     pub public_key_algorithm: String,
+    /// Key size in bits
     pub key_size: usize,
     pub fingerprint_sha1: String,
     pub fingerprint_sha256: String,
-    pub extensions: Vec<String>,
+    pub key_usage: Vec<String>,
+    pub extended_key_usage: Vec<String>,
+    pub extensions: Vec<CertificateExtension>,
     pub is_ca: bool,
+    pub path_len_constraint: Option<u32>,
+    pub is_self_signed: bool,
+}
+
+impl CertificateInfo {
+    /// Whether the certificate is currently within its validity period
+    pub fn is_valid_now(&self) -> bool {
+        let now = Utc::now();
+        self.not_before <= now && now <= self.not_after
+    }
+}
+
+/// An X.509 extension present in a certificate
+#[derive(Debug, Clone, Serialize)]
+pub struct CertificateExtension {
+    pub oid: String,
+    pub name: String,
+    pub critical: bool,
 }
 
 /// Create a custom certificate verifier that accepts all certificates (for testing)
@@ -245,3 +381,44 @@ impl ServerCertVerifier for AcceptAllCertVerifier {
         Ok(ServerCertVerified::assertion())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::disk_cache::pem_block;
+    use crate::config::settings::LeafKeyAlgorithm;
+    use crate::tls::{generate_root_ca, CaOptions, LeafIssuer, LeafProfile};
+
+    #[test]
+    fn test_certificate_info_from_pem_bundles_and_der() {
+        let ca = generate_root_ca(&CaOptions { path_len: Some(1), ..CaOptions::default() }).unwrap();
+        let issuer = LeafIssuer::new(&ca, LeafKeyAlgorithm::Rsa2048, 30).unwrap();
+        let mut profile = LeafProfile::for_host("example.com");
+        profile.ip_addresses.push("10.0.0.1".parse().unwrap());
+        let leaf = issuer.issue(&profile).unwrap();
+
+        let bundle = pem_block("CERTIFICATE", &leaf.cert().0) + &pem_block("CERTIFICATE", &ca.cert().0);
+        let certs = parse_certificates(bundle.as_bytes()).unwrap();
+        assert_eq!(certs.len(), 2);
+
+        let leaf_info = extract_certificate_info(&certs[0]).unwrap();
+        assert_eq!(leaf_info.subject, "O=Rust Forward Proxy, CN=example.com");
+        assert_eq!(leaf_info.issuer, "O=Rust Forward Proxy CA, CN=Rust Proxy Root CA");
+        assert_eq!(leaf_info.subject_alt_names, vec!["DNS:example.com", "IP:10.0.0.1"]);
+        assert_eq!((leaf_info.public_key_algorithm.as_str(), leaf_info.key_size), ("RSA", 2048));
+        assert!(leaf_info.extended_key_usage.contains(&"serverAuth".to_string()));
+        assert!(!leaf_info.is_ca && !leaf_info.is_self_signed && leaf_info.is_valid_now());
+        assert_eq!(leaf_info.fingerprint_sha256.len(), 32 * 3 - 1);
+
+        let ca_info = extract_certificate_info(&certs[1]).unwrap();
+        assert_eq!((ca_info.public_key_algorithm.as_str(), ca_info.key_size), ("EC (prime256v1)", 256));
+        assert!(ca_info.is_ca && ca_info.is_self_signed);
+        assert_eq!(ca_info.path_len_constraint, Some(1));
+        assert!(ca_info.extensions.iter().any(|ext| ext.name == "basicConstraints" && ext.critical));
+
+        // Raw DER is a single certificate
+        let der = parse_certificates(&ca.cert().0).unwrap();
+        assert_eq!(der, vec![ca.cert()]);
+        assert!(parse_certificates(b"not a certificate").is_err());
+    }
+}
@@ -0,0 +1,451 @@
+//! On-disk certificate cache backend
+//!
+//! Lets a standalone proxy keep its forged certificates across restarts.
+//! Each entry is one file named after the SHA-256 of its cache key, written
+//! atomically (temp file + rename) with mode 0600 since it holds a private
+//! key. Learned bypass entries live in `bypass.json` in the same directory.
+
+use crate::config::settings::DiskCacheFormat;
+use crate::tls::{current_timestamp, fingerprint, BypassEntry, CertificateCache, CertificateData};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use rustls::{Certificate, PrivateKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const BYPASS_FILE: &str = "bypass.json";
+
+/// A cached certificate as stored on disk
+#[derive(Debug, Serialize, Deserialize)]
+struct DiskEntry {
+    key: String,
+    created_at: u64,
+    expires_at: u64,
+    cert: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+/// Directory-backed certificate cache
+///
+/// File I/O runs on tokio's blocking pool; the store itself is synchronous.
+pub struct DiskCache {
+    store: Arc<DiskStore>,
+}
+
+struct DiskStore {
+    dir: PathBuf,
+    format: DiskCacheFormat,
+    max_entries: usize,
+    /// Entry files on disk, approximately (another process may share the
+    /// directory); recounted whenever eviction runs
+    entries: AtomicUsize,
+    /// Serialises writers within this process (readers never see partial files)
+    write_lock: Mutex<()>,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>, format: DiskCacheFormat, max_entries: usize) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("Failed to restrict permissions on {}", dir.display()))?;
+        }
+
+        info!("💽 Disk certificate cache at {} ({:?}, max {} entries)", dir.display(), format, max_entries);
+        let store = DiskStore {
+            dir,
+            format,
+            max_entries,
+            entries: AtomicUsize::new(0),
+            write_lock: Mutex::new(()),
+        };
+        store.entries.store(store.entry_files()?.len(), Ordering::Relaxed);
+        Ok(Self { store: Arc::new(store) })
+    }
+
+    /// Run filesystem work on the blocking pool
+    async fn blocking<T, F>(&self, work: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DiskStore) -> Result<T> + Send + 'static,
+    {
+        let store = Arc::clone(&self.store);
+        tokio::task::spawn_blocking(move || work(&store))
+            .await
+            .map_err(|e| anyhow!("Disk cache task failed: {}", e))?
+    }
+}
+
+impl DiskStore {
+    fn extension(&self) -> &'static str {
+        match self.format {
+            DiskCacheFormat::Pem => "pem",
+            DiskCacheFormat::Bincode => "bin",
+        }
+    }
+
+    /// Cache keys may contain `:`, `*` or `/`, so files are named by hash
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", fingerprint(key.as_bytes()), self.extension()))
+    }
+
+    fn entry_files(&self) -> Result<Vec<(PathBuf, SystemTime)>> {
+        let extension = self.extension();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == extension) {
+                let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+                files.push((path, modified));
+            }
+        }
+        Ok(files)
+    }
+
+    fn read_entry(&self, path: &Path) -> Result<DiskEntry> {
+        let bytes = fs::read(path)?;
+        match self.format {
+            DiskCacheFormat::Pem => decode_pem_entry(&bytes),
+            DiskCacheFormat::Bincode => bincode::deserialize(&bytes).map_err(|e| anyhow!("Corrupt cache entry: {}", e)),
+        }
+    }
+
+    fn remove_entry(&self, path: &Path) -> std::io::Result<()> {
+        fs::remove_file(path)?;
+        let _ = self.entries.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_sub(1));
+        Ok(())
+    }
+
+    /// Drop the least recently written entries beyond `max_entries`
+    ///
+    /// Goes by file modification time alone; expired entries are dropped
+    /// when they're next read.
+    fn evict(&self) -> Result<()> {
+        let mut files = self.entry_files()?;
+        self.entries.store(files.len(), Ordering::Relaxed);
+        if files.len() <= self.max_entries {
+            return Ok(());
+        }
+
+        files.sort_by_key(|(_, modified)| *modified);
+        let to_remove = files.len() - self.max_entries;
+        for (path, _) in files.iter().take(to_remove) {
+            let _ = self.remove_entry(path);
+        }
+        debug!("Enforced disk cache size limit: removed {} entries", to_remove);
+        Ok(())
+    }
+
+    fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        let path = self.entry_path(domain);
+        let entry = match self.read_entry(&path) {
+            Ok(entry) => entry,
+            Err(e) => {
+                if path.exists() {
+                    warn!("Discarding unreadable cache file {}: {}", path.display(), e);
+                    let _ = self.remove_entry(&path);
+                } else {
+                    debug!("Cache MISS for domain: {}", domain);
+                }
+                return Ok(None);
+            }
+        };
+
+        let now = current_timestamp();
+        // A hash collision is practically impossible, but don't serve the wrong host
+        if entry.key != domain || now >= entry.expires_at {
+            debug!("Cache entry expired for domain: {}", domain);
+            let _ = self.remove_entry(&path);
+            return Ok(None);
+        }
+
+        debug!("Cache HIT for domain: {} (expires in {} seconds)", domain, entry.expires_at - now);
+        let cert_data = CertificateData::new(Certificate(entry.cert), PrivateKey(entry.private_key));
+        Ok(Some((cert_data, Some(entry.expires_at))))
+    }
+
+    fn set(&self, domain: &str, bytes: &[u8]) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        let path = self.entry_path(domain);
+        let is_new = !path.exists();
+        write_atomic(&path, bytes)?;
+        if is_new && self.entries.fetch_add(1, Ordering::Relaxed) + 1 > self.max_entries {
+            if let Err(e) = self.evict() {
+                warn!("Disk cache eviction failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, domain: &str) -> Result<()> {
+        match self.remove_entry(&self.entry_path(domain)) {
+            Ok(()) => debug!("Removed certificate from cache for domain: {}", domain),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        let files = self.entry_files()?;
+        for (path, _) in &files {
+            fs::remove_file(path)?;
+        }
+        self.entries.store(0, Ordering::Relaxed);
+        info!("Cleared certificate cache ({} entries removed)", files.len());
+        Ok(())
+    }
+
+    fn read_bypass(&self) -> Result<Vec<BypassEntry>> {
+        match fs::read(self.dir.join(BYPASS_FILE)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn live_bypass(&self) -> Result<Vec<BypassEntry>> {
+        let now = current_timestamp();
+        let mut entries = self.read_bypass()?;
+        entries.retain(|entry| now < entry.expires_at);
+        Ok(entries)
+    }
+
+    fn write_bypass(&self, entries: &[BypassEntry]) -> Result<()> {
+        write_atomic(&self.dir.join(BYPASS_FILE), &serde_json::to_vec_pretty(entries)?)
+    }
+
+    fn mark_bypass(&self, entry: BypassEntry) -> Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        let now = current_timestamp();
+        let mut entries = self.read_bypass()?;
+        entries.retain(|existing| existing.host != entry.host && now < existing.expires_at);
+        entries.push(entry);
+        self.write_bypass(&entries)
+    }
+
+    fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut entries = self.live_bypass()?;
+        let before = entries.len();
+        match host {
+            Some(host) => entries.retain(|entry| entry.host != host),
+            None => entries.clear(),
+        }
+        self.write_bypass(&entries)?;
+        Ok(before - entries.len())
+    }
+}
+
+#[async_trait]
+impl CertificateCache for DiskCache {
+    async fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
+        Ok(self.get_with_expiry(domain).await?.map(|(cert_data, _)| cert_data))
+    }
+
+    async fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        let domain = domain.to_string();
+        self.blocking(move |store| store.get_with_expiry(&domain)).await
+    }
+
+    async fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
+        let now = current_timestamp();
+        let entry = DiskEntry {
+            key: domain.to_string(),
+            created_at: now,
+            expires_at: now + ttl_seconds,
+            cert: cert_data.cert().0,
+            private_key: cert_data.key().0,
+        };
+        let bytes = match self.store.format {
+            DiskCacheFormat::Pem => encode_pem_entry(&entry).into_bytes(),
+            DiskCacheFormat::Bincode => bincode::serialize(&entry)?,
+        };
+
+        self.blocking(move |store| store.set(&entry.key, &bytes)).await?;
+        debug!("Cached certificate for domain: {} (TTL: {} seconds)", domain, ttl_seconds);
+        Ok(())
+    }
+
+    async fn remove(&self, domain: &str) -> Result<()> {
+        let domain = domain.to_string();
+        self.blocking(move |store| store.remove(&domain)).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.blocking(|store| store.clear()).await
+    }
+
+    async fn cache_info(&self) -> String {
+        format!(
+            "Disk cache: {}/{} entries in {}",
+            self.store.entries.load(Ordering::Relaxed),
+            self.store.max_entries,
+            self.store.dir.display()
+        )
+    }
+
+    async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()> {
+        let entry = BypassEntry {
+            host: host.to_string(),
+            reason: reason.to_string(),
+            expires_at: current_timestamp() + ttl_seconds,
+        };
+        self.blocking(move |store| store.mark_bypass(entry)).await
+    }
+
+    async fn get_bypass(&self, host: &str) -> Result<Option<BypassEntry>> {
+        let host = host.to_string();
+        self.blocking(move |store| Ok(store.live_bypass()?.into_iter().find(|entry| entry.host == host))).await
+    }
+
+    async fn list_bypass(&self) -> Result<Vec<BypassEntry>> {
+        self.blocking(|store| store.live_bypass()).await
+    }
+
+    async fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        let host = host.map(str::to_string);
+        self.blocking(move |store| store.clear_bypass(host.as_deref())).await
+    }
+}
+
+/// Write `bytes` to `path` via a 0600 temp file in the same directory
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = write_temp(path, bytes, 0o600)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+/// Write `bytes` to a fresh file next to `path` and return its name; the
+/// caller renames it into place
+pub(crate) fn write_temp(path: &Path, bytes: &[u8], mode: u32) -> Result<PathBuf> {
+    let tmp = path.with_extension(format!("tmp{}", rand::random::<u32>()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let result = options.open(&tmp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(anyhow!("Failed to write {}: {}", path.display(), e));
+    }
+    Ok(tmp)
+}
+
+fn encode_pem_entry(entry: &DiskEntry) -> String {
+    format!(
+        "# key: {}\n# created_at: {}\n# expires_at: {}\n{}{}",
+        entry.key,
+        entry.created_at,
+        entry.expires_at,
+        pem_block("CERTIFICATE", &entry.cert),
+        pem_block("PRIVATE KEY", &entry.private_key),
+    )
+}
+
+pub(crate) fn pem_block(label: &str, der: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+fn decode_pem_entry(bytes: &[u8]) -> Result<DiskEntry> {
+    let text = std::str::from_utf8(bytes)?;
+    let header = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("# {}: ", name)))
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Missing '{}' header", name))
+    };
+    let key = header("key")?;
+    let created_at = header("created_at")?.parse()?;
+    let expires_at = header("expires_at")?.parse()?;
+
+    let mut cert = None;
+    let mut private_key = None;
+    for item in rustls_pemfile::read_all(&mut text.as_bytes())? {
+        match item {
+            rustls_pemfile::Item::X509Certificate(der) => cert = cert.or(Some(der)),
+            rustls_pemfile::Item::PKCS8Key(der) => private_key = Some(der),
+            _ => {}
+        }
+    }
+
+    Ok(DiskEntry {
+        key,
+        created_at,
+        expires_at,
+        cert: cert.ok_or_else(|| anyhow!("No certificate in cache entry"))?,
+        private_key: private_key.ok_or_else(|| anyhow!("No private key in cache entry"))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::generate_self_signed_cert;
+
+    #[tokio::test]
+    async fn test_entries_survive_reopen_and_evict_oldest() {
+        for format in [DiskCacheFormat::Pem, DiskCacheFormat::Bincode] {
+            let dir = tempfile::tempdir().unwrap();
+            let cert = generate_self_signed_cert("Test", "example.com", 1).unwrap();
+
+            let cache = DiskCache::new(dir.path(), format, 2).unwrap();
+            cache.set("mimic:abc:10.0.0.1", cert.clone(), 60).await.unwrap();
+            cache.set("expired.com", cert.clone(), 0).await.unwrap();
+            cache.mark_bypass("pinned.example", "test", 60).await.unwrap();
+
+            // A new instance (a restarted proxy) sees the same entries
+            let cache = DiskCache::new(dir.path(), format, 2).unwrap();
+            let loaded = cache.get("mimic:abc:10.0.0.1").await.unwrap().unwrap();
+            assert_eq!(loaded.cert().0, cert.cert().0);
+            assert_eq!(loaded.key().0, cert.key().0);
+            assert!(cache.get("expired.com").await.unwrap().is_none());
+            assert!(cache.get_bypass("pinned.example").await.unwrap().is_some());
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(cache.store.entry_path("mimic:abc:10.0.0.1")).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cache.set("b.com", cert.clone(), 60).await.unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cache.set("c.com", cert.clone(), 60).await.unwrap();
+            assert!(cache.get("mimic:abc:10.0.0.1").await.unwrap().is_none());
+            assert!(cache.get("c.com").await.unwrap().is_some());
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Upstream certificate mimicry
+//!
+//! With `tls.mimic_upstream_cert` the proxy handshakes with the real upstream
+//! before forging a leaf and copies its subject, SANs, key usage and expiry,
+//! so clients that connect by IP or check the SAN set see what they would see
+//! without the proxy. Forged certificates are cached under the upstream
+//! certificate's SHA-256 fingerprint, and each upstream's plan is reused for
+//! its cache TTL, so only a miss or an expired plan costs a probe handshake.
+
+use crate::proxy::parent::UpstreamDialer;
+use crate::tls::{AcceptAllCertVerifier, LeafProfile, ALPN_H2, ALPN_HTTP1};
+use anyhow::{anyhow, Result};
+use rustls::{ClientConfig, ServerName};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+use x509_parser::objects::{oid2abbrev, oid_registry};
+use x509_parser::prelude::{GeneralName, X509Certificate};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Subject attributes copied into forged certificates
+const SUBJECT_ATTRIBUTES: &[&str] = &["C", "ST", "L", "O", "OU", "CN"];
+
+/// What an upstream revealed in its handshake
+#[derive(Debug, Clone)]
+pub struct UpstreamHello {
+    /// DER of the leaf certificate
+    pub leaf: Vec<u8>,
+    /// Protocol selected from our `h2, http/1.1` offer
+    pub alpn: Option<Vec<u8>>,
+}
+
+/// Reads the leaf certificate (and ALPN choice) an upstream presents
+pub struct UpstreamCertProbe {
+    connector: TlsConnector,
+    dialer: Arc<UpstreamDialer>,
+    /// Plans by `host:port/server_name`, with when they lapse
+    plans: Mutex<HashMap<String, (MimicPlan, Instant)>>,
+}
+
+impl Default for UpstreamCertProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpstreamCertProbe {
+    pub fn new() -> Self {
+        Self::with_dialer(Arc::new(UpstreamDialer::direct()))
+    }
+
+    /// Probe that reaches upstreams through `dialer` (and so any parent proxy)
+    pub fn with_dialer(dialer: Arc<UpstreamDialer>) -> Self {
+        // We only read the certificate; the real request is verified by HttpClient
+        let mut config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAllCertVerifier))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ALPN_H2.to_vec(), ALPN_HTTP1.to_vec()];
+
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            dialer,
+            plans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The plan remembered for `host:port` and `server_name`, if still fresh
+    pub fn cached_plan(&self, host: &str, port: u16, server_name: &str) -> Option<MimicPlan> {
+        let plans = self.plans.lock().unwrap();
+        plans
+            .get(&plan_key(host, port, server_name))
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(plan, _)| plan.clone())
+    }
+
+    /// Remember `plan` for its cache TTL
+    pub fn remember_plan(&self, host: &str, port: u16, server_name: &str, plan: &MimicPlan) {
+        let now = Instant::now();
+        let mut plans = self.plans.lock().unwrap();
+        plans.retain(|_, (_, expires)| *expires > now);
+        if plan.ttl_seconds > 0 {
+            let expires = now + Duration::from_secs(plan.ttl_seconds);
+            plans.insert(plan_key(host, port, server_name), (plan.clone(), expires));
+        }
+    }
+
+    /// Handshake with `host:port`, sending `server_name` as SNI, and return
+    /// the DER of the leaf certificate it presents
+    pub async fn fetch_leaf(&self, host: &str, port: u16, server_name: &str) -> Result<Vec<u8>> {
+        self.handshake(host, port, server_name).await.map(|hello| hello.leaf)
+    }
+
+    /// Handshake with `host:port`, sending `server_name` as SNI, and report
+    /// its leaf certificate and ALPN choice
+    pub async fn handshake(&self, host: &str, port: u16, server_name: &str) -> Result<UpstreamHello> {
+        let server_name = ServerName::try_from(server_name.trim_start_matches('[').trim_end_matches(']'))
+            .map_err(|_| anyhow!("Invalid server name '{}'", server_name))?;
+
+        let handshake = async {
+            let tcp = self.dialer.connect(host, port).await?;
+            let tls = self.connector.connect(server_name, tcp).await?;
+            let (_, connection) = tls.get_ref();
+            let leaf = connection
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| cert.0.clone())
+                .ok_or_else(|| anyhow!("{} presented no certificate", host))?;
+            Ok(UpstreamHello {
+                leaf,
+                alpn: connection.alpn_protocol().map(|alpn| alpn.to_vec()),
+            })
+        };
+
+        match tokio::time::timeout(PROBE_TIMEOUT, handshake).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("Timed out reading certificate from {}:{}", host, port)),
+        }
+    }
+}
+
+/// A forged certificate to issue for an upstream leaf
+#[derive(Debug, Clone)]
+pub struct MimicPlan {
+    pub profile: LeafProfile,
+    /// Certificate cache key
+    pub cache_key: String,
+    /// Cache TTL, capped at the upstream certificate's remaining validity
+    pub ttl_seconds: u64,
+}
+
+impl MimicPlan {
+    /// Plan a forged leaf for `host` copying the upstream certificate `der`
+    pub fn from_upstream(der: &[u8], host: &str, max_ttl_seconds: u64) -> Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| anyhow!("Failed to parse upstream certificate: {}", e))?;
+
+        let mut profile = profile_from_certificate(&cert)?;
+        let fingerprint = fingerprint(der);
+
+        // Clients connecting by a name the upstream doesn't cover still need
+        // a certificate for it, which makes the forged cert host-specific
+        let cache_key = if profile.covers_host(host) {
+            format!("mimic:{}", fingerprint)
+        } else {
+            debug!("Upstream certificate doesn't cover {}, adding it to the SANs", host);
+            profile.ensure_covers(host);
+            format!("mimic:{}:{}", fingerprint, host.to_lowercase())
+        };
+
+        let remaining = profile
+            .not_after
+            .and_then(|not_after| not_after.duration_since(SystemTime::now()).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(Self {
+            profile,
+            cache_key,
+            ttl_seconds: remaining.min(max_ttl_seconds),
+        })
+    }
+}
+
+fn plan_key(host: &str, port: u16, server_name: &str) -> String {
+    format!("{}:{}/{}", host.to_lowercase(), port, server_name.to_lowercase())
+}
+
+/// Hex SHA-256 of a DER certificate (also names disk cache entries)
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn profile_from_certificate(cert: &X509Certificate) -> Result<LeafProfile> {
+    let registry = oid_registry();
+    let subject = cert
+        .subject()
+        .iter_attributes()
+        .filter_map(|attr| {
+            let key = oid2abbrev(attr.attr_type(), registry).ok()?;
+            if !SUBJECT_ATTRIBUTES.contains(&key) {
+                return None;
+            }
+            Some((key.to_string(), attr.as_str().ok()?.to_string()))
+        })
+        .collect();
+
+    let mut dns_names = Vec::new();
+    let mut ip_addresses = Vec::new();
+    if let Some(san) = cert.subject_alternative_name().map_err(|e| anyhow!("Invalid SAN extension: {}", e))? {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => dns_names.push(dns.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    if let Some(ip) = ip_from_bytes(bytes) {
+                        ip_addresses.push(ip);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Leaf usages only; digitalSignature is required for ECDHE key exchange
+    let mut key_usage = vec!["digitalSignature".to_string()];
+    if let Some(ku) = cert.key_usage().map_err(|e| anyhow!("Invalid keyUsage extension: {}", e))? {
+        let ku = ku.value;
+        for (set, name) in [
+            (ku.non_repudiation(), "nonRepudiation"),
+            (ku.key_encipherment(), "keyEncipherment"),
+            (ku.data_encipherment(), "dataEncipherment"),
+            (ku.key_agreement(), "keyAgreement"),
+        ] {
+            if set {
+                key_usage.push(name.to_string());
+            }
+        }
+    } else {
+        key_usage.push("keyEncipherment".to_string());
+    }
+
+    let mut extended_key_usage = vec!["serverAuth".to_string()];
+    if let Some(eku) = cert.extended_key_usage().map_err(|e| anyhow!("Invalid extendedKeyUsage extension: {}", e))? {
+        if eku.value.client_auth {
+            extended_key_usage.push("clientAuth".to_string());
+        }
+    }
+
+    let not_after = u64::try_from(cert.validity().not_after.timestamp())
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    Ok(LeafProfile {
+        subject,
+        dns_names,
+        ip_addresses,
+        key_usage,
+        extended_key_usage,
+        not_after,
+    })
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(|b| IpAddr::V6(Ipv6Addr::from(b))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+
+    fn upstream_cert() -> Vec<u8> {
+        let mut params = CertificateParams::new(vec!["example.com".to_string(), "*.example.com".to_string()]);
+        params.subject_alt_names.push(SanType::IpAddress("93.184.216.34".parse().unwrap()));
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::CountryName, "US");
+        dn.push(DnType::OrganizationName, "Example Inc");
+        dn.push(DnType::CommonName, "example.com");
+        params.distinguished_name = dn;
+        params.not_after = rcgen::date_time_ymd(2099, 1, 1);
+        Certificate::from_params(params).unwrap().serialize_der().unwrap()
+    }
+
+    #[test]
+    fn test_plan_copies_upstream_profile() {
+        let der = upstream_cert();
+        let fp = fingerprint(&der);
+        assert_eq!(fp.len(), 64);
+
+        let plan = MimicPlan::from_upstream(&der, "www.example.com", 86400).unwrap();
+        assert_eq!(plan.cache_key, format!("mimic:{}", fp));
+        assert_eq!(plan.ttl_seconds, 86400);
+        assert_eq!(plan.profile.dns_names, vec!["example.com", "*.example.com"]);
+        assert_eq!(plan.profile.ip_addresses, vec!["93.184.216.34".parse::<IpAddr>().unwrap()]);
+        assert_eq!(
+            plan.profile.subject,
+            vec![
+                ("C".to_string(), "US".to_string()),
+                ("O".to_string(), "Example Inc".to_string()),
+                ("CN".to_string(), "example.com".to_string()),
+            ]
+        );
+
+        let probe = UpstreamCertProbe::new();
+        probe.remember_plan("Example.com", 443, "www.example.com", &plan);
+        assert_eq!(probe.cached_plan("example.com", 443, "www.example.com").unwrap().cache_key, plan.cache_key);
+        assert!(probe.cached_plan("example.com", 8443, "www.example.com").is_none());
+        let expired = MimicPlan { ttl_seconds: 0, ..plan.clone() };
+        probe.remember_plan("expired.example.com", 443, "expired.example.com", &expired);
+        assert!(probe.cached_plan("expired.example.com", 443, "expired.example.com").is_none());
+
+        // Connecting by an address the upstream doesn't list adds it as an IP SAN
+        let plan = MimicPlan::from_upstream(&der, "10.0.0.5", 86400).unwrap();
+        assert_eq!(plan.cache_key, format!("mimic:{}:10.0.0.5", fp));
+        assert!(plan.profile.ip_addresses.contains(&"10.0.0.5".parse().unwrap()));
+        assert!(plan.profile.covers_host("10.0.0.5"));
+        assert!(!plan.profile.covers_host("a.b.example.com"));
+    }
+}
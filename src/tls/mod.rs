@@ -1,11 +1,33 @@
 //! TLS certificate management and generation
 
+pub mod alpn;
+pub mod bypass;
+pub mod ca;
 pub mod cache;
 pub mod cert_gen;
 pub mod config;
+pub mod disk_cache;
+pub mod mimic;
+pub mod pinning;
+#[cfg(feature = "redis-support")]
+pub mod redis_cache;
+pub mod resolver;
 pub mod server;
+pub mod tiered_cache;
+pub mod upstream;
 
+pub use alpn::*;
+pub use bypass::*;
+pub use ca::*;
 pub use cache::*;
 pub use cert_gen::*;
 pub use config::*;
+pub use disk_cache::*;
+pub use mimic::*;
+pub use pinning::*;
+#[cfg(feature = "redis-support")]
+pub use redis_cache::*;
+pub use resolver::*;
 pub use server::*;
+pub use tiered_cache::*;
+pub use upstream::*;
@@ -0,0 +1,159 @@
+//! Certificate pinning detection
+//!
+//! Clients that pin certificates reject our forged leaf with a TLS alert
+//! during the handshake. Counting those failures per host, from several
+//! distinct clients, lets the proxy learn to tunnel such hosts instead of
+//! breaking them forever. Learned
+//! entries live in the `CertificateManager` cache backend so a Redis-backed
+//! cluster shares them.
+
+use crate::config::settings::PinningDetectionConfig;
+use crate::tls::CertificateManager;
+use rustls::AlertDescription;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Certificate rejections seen for one host in the current window
+struct HostFailures {
+    count: u32,
+    clients: HashSet<IpAddr>,
+    first: Instant,
+}
+
+/// Counts certificate-rejecting handshake failures per host
+pub struct PinningDetector {
+    config: PinningDetectionConfig,
+    failures: Mutex<HashMap<String, HostFailures>>,
+}
+
+impl PinningDetector {
+    pub fn new(config: &PinningDetectionConfig) -> Self {
+        if config.enabled {
+            debug!("📌 Pinning detection: bypass after {} failures from {} clients in {}s (for {}s)",
+                   config.failure_threshold, config.min_clients, config.window_secs, config.bypass_ttl_secs);
+        }
+        Self {
+            config: config.clone(),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn over_threshold(&self, (count, clients): (u32, u32)) -> bool {
+        count >= self.config.failure_threshold && clients >= self.config.min_clients
+    }
+
+    /// Record a failed handshake for `host` from the client at `client`
+    ///
+    /// Returns true when this failure pushed the host over the threshold and
+    /// it was added to the learned bypass set.
+    pub async fn record_handshake_failure(
+        &self,
+        host: &str,
+        client: IpAddr,
+        error: &std::io::Error,
+        cert_manager: &CertificateManager,
+    ) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+
+        let Some(cause) = certificate_rejection(error) else {
+            return false;
+        };
+
+        let (count, clients) = {
+            let window = Duration::from_secs(self.config.window_secs);
+            let now = Instant::now();
+            let mut failures = self.failures.lock().unwrap();
+            failures.retain(|_, entry| now.duration_since(entry.first) < window);
+
+            let entry = failures.entry(host.to_string()).or_insert_with(|| HostFailures {
+                count: 0,
+                clients: HashSet::new(),
+                first: now,
+            });
+            entry.count += 1;
+            entry.clients.insert(client);
+            let seen = (entry.count, entry.clients.len() as u32);
+            if self.over_threshold(seen) {
+                failures.remove(host);
+            }
+            seen
+        };
+
+        debug!("📌 {} rejected our certificate ({}) for {}, failure {}/{} from {}/{} clients", host, cause, client,
+               count, self.config.failure_threshold, clients, self.config.min_clients);
+        if !self.over_threshold((count, clients)) {
+            return false;
+        }
+
+        let reason = format!("pinning suspected: {} handshake failures from {} clients ({})", count, clients, cause);
+        match cert_manager.mark_bypass(host, &reason, self.config.bypass_ttl_secs).await {
+            Ok(()) => {
+                info!("📌 Learned interception bypass for {} ({}), expires in {}s", host, reason, self.config.bypass_ttl_secs);
+                true
+            }
+            Err(e) => {
+                warn!("Failed to store learned bypass for {}: {}", host, e);
+                false
+            }
+        }
+    }
+}
+
+/// Why the client rejected our certificate, if that's why the handshake failed
+fn certificate_rejection(error: &std::io::Error) -> Option<String> {
+    match error.get_ref()?.downcast_ref::<rustls::Error>()? {
+        rustls::Error::AlertReceived(alert) => match alert {
+            AlertDescription::UnknownCA
+            | AlertDescription::BadCertificate
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::UnsupportedCertificate => Some(format!("{:?}", alert)),
+            _ => None,
+        },
+        // TLS 1.3 clients (e.g. OpenSSL) send their rejection alert encrypted
+        // under handshake keys, which rustls reports as a decrypt error
+        rustls::Error::DecryptError => Some("DecryptError".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::MemoryCache;
+
+    fn alert(description: AlertDescription) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, rustls::Error::AlertReceived(description))
+    }
+
+    #[tokio::test]
+    async fn test_bypass_learned_after_threshold() {
+        let manager = CertificateManager::with_cache(Box::new(MemoryCache::new(10)));
+        let detector = PinningDetector::new(&PinningDetectionConfig {
+            enabled: true,
+            failure_threshold: 2,
+            ..Default::default()
+        });
+        let (alice, bob): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+
+        // Unrelated failures don't count
+        assert!(!detector.record_handshake_failure("app.example", alice, &alert(AlertDescription::HandshakeFailure), &manager).await);
+        assert!(!detector.record_handshake_failure("app.example", alice, &std::io::Error::other("eof"), &manager).await);
+
+        // One client alone (say, without the root installed) never triggers a bypass
+        for _ in 0..3 {
+            assert!(!detector.record_handshake_failure("app.example", alice, &alert(AlertDescription::UnknownCA), &manager).await);
+        }
+        assert!(manager.get_bypass("app.example").await.is_none());
+        assert!(detector.record_handshake_failure("app.example", bob, &alert(AlertDescription::BadCertificate), &manager).await);
+
+        assert!(manager.get_bypass("app.example").await.is_some());
+        assert_eq!(manager.list_bypass().await.unwrap().len(), 1);
+        assert_eq!(manager.clear_bypass(None).await.unwrap(), 1);
+        assert!(manager.get_bypass("app.example").await.is_none());
+    }
+}
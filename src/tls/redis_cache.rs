@@ -0,0 +1,374 @@
+//! Redis certificate cache backend
+//!
+//! Commands go through a pool of `redis::aio::ConnectionManager`s (one
+//! multiplexed connection each, re-established after I/O errors) sized and
+//! timed by the `redis:` section of config.yml. Connections are opened on
+//! first use. After a connection failure the pool backs off exponentially and
+//! fails fast in the meantime, so an unreachable Redis costs a request a cache
+//! miss rather than a timeout.
+
+use crate::config::settings::RedisConfig;
+use crate::tls::cache::current_timestamp;
+use crate::tls::{BypassEntry, CacheBackendHealth, CertificateCache, CertificateData};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::FromRedisValue;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio::time::error::Elapsed;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+const BACKOFF_BASE: Duration = Duration::from_millis(250);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+const SCAN_COUNT: usize = 500;
+
+#[derive(Debug, Default)]
+struct PoolState {
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+/// Lazily connected Redis connections with timeouts and failure backoff
+pub struct RedisPool {
+    client: redis::Client,
+    connections: Vec<OnceCell<ConnectionManager>>,
+    next: AtomicUsize,
+    connection_timeout: Duration,
+    command_timeout: Duration,
+    state: Mutex<PoolState>,
+}
+
+impl RedisPool {
+    /// Create a pool for `config`; nothing is connected until first use
+    pub fn new(config: &RedisConfig) -> Result<Self> {
+        let client = redis::Client::open(config.url.as_str())
+            .map_err(|e| anyhow!("Failed to create Redis client: {}", e))?;
+
+        Ok(Self {
+            client,
+            connections: (0..config.pool_size.max(1)).map(|_| OnceCell::new()).collect(),
+            next: AtomicUsize::new(0),
+            connection_timeout: Duration::from_secs(config.connection_timeout.max(1)),
+            command_timeout: Duration::from_secs(config.command_timeout.max(1)),
+            state: Mutex::new(PoolState::default()),
+        })
+    }
+
+    pub fn client(&self) -> &redis::Client {
+        &self.client
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        if let Some(retry_at) = self.state.lock().unwrap().retry_at {
+            let now = Instant::now();
+            if now < retry_at {
+                return Err(anyhow!("Redis unavailable (retrying in {}ms)", (retry_at - now).as_millis()));
+            }
+        }
+
+        let slot = &self.connections[self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len()];
+        let connect = slot.get_or_try_init(|| ConnectionManager::new(self.client.clone()));
+        match timeout(self.connection_timeout, connect).await {
+            Ok(Ok(connection)) => Ok(connection.clone()),
+            Ok(Err(e)) => Err(self.record_failure(e)),
+            Err(_) => Err(self.record_failure("connection timed out")),
+        }
+    }
+
+    /// Open a pooled connection now, so an unreachable Redis shows at startup
+    pub async fn connect(&self) -> Result<()> {
+        self.connection().await.map(|_| ())
+    }
+
+    /// Run `cmd` on the next pooled connection
+    pub async fn query<T: FromRedisValue>(&self, cmd: &redis::Cmd) -> Result<T> {
+        let mut connection = self.connection().await?;
+        let result = timeout(self.command_timeout, cmd.query_async(&mut connection)).await;
+        self.outcome(result)
+    }
+
+    /// Run `pipe` in one round-trip on the next pooled connection
+    pub async fn query_pipeline<T: FromRedisValue>(&self, pipe: &redis::Pipeline) -> Result<T> {
+        let mut connection = self.connection().await?;
+        let result = timeout(self.command_timeout, pipe.query_async(&mut connection)).await;
+        self.outcome(result)
+    }
+
+    /// Track connection health from a command's (timed) result
+    fn outcome<T>(&self, result: std::result::Result<redis::RedisResult<T>, Elapsed>) -> Result<T> {
+        match result {
+            Ok(Ok(value)) => {
+                self.record_success();
+                Ok(value)
+            }
+            Ok(Err(e)) if e.is_io_error() || e.is_connection_dropped() || e.is_timeout() => {
+                Err(self.record_failure(e))
+            }
+            Ok(Err(e)) => Err(anyhow!("Redis error: {}", e)),
+            Err(_) => Err(self.record_failure("command timed out")),
+        }
+    }
+
+    /// All keys matching `pattern`, walked with SCAN rather than KEYS
+    pub async fn scan(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<String>) = self
+                .query(redis::cmd("SCAN").arg(cursor).arg("MATCH").arg(pattern).arg("COUNT").arg(SCAN_COUNT))
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<()> {
+        self.query(redis::cmd("PUBLISH").arg(channel).arg(message)).await
+    }
+
+    pub fn health(&self) -> CacheBackendHealth {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        CacheBackendHealth {
+            backend: "redis",
+            healthy: state.consecutive_failures == 0,
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.clone(),
+            retry_in_ms: state
+                .retry_at
+                .filter(|retry_at| *retry_at > now)
+                .map(|retry_at| (retry_at - now).as_millis() as u64),
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.consecutive_failures > 0 {
+            info!("🚀 Redis connection restored after {} failures", state.consecutive_failures);
+            *state = PoolState::default();
+        }
+    }
+
+    fn record_failure(&self, error: impl Display) -> anyhow::Error {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        let backoff = (BACKOFF_BASE * 2u32.pow(state.consecutive_failures.min(8) - 1)).min(BACKOFF_MAX);
+        state.retry_at = Some(Instant::now() + backoff);
+        state.last_error = Some(error.to_string());
+
+        if state.consecutive_failures == 1 {
+            warn!("Redis certificate cache unavailable: {} (backing off)", error);
+        } else {
+            debug!("Redis failure #{}: {} (retry in {:?})", state.consecutive_failures, error, backoff);
+        }
+        anyhow!("Redis unavailable: {}", error)
+    }
+}
+
+/// Redis certificate cache implementation
+pub struct RedisCache {
+    pool: Arc<RedisPool>,
+    key_prefix: String,
+    bypass_prefix: String,
+}
+
+impl RedisCache {
+    pub fn new(config: &RedisConfig, key_prefix: Option<String>) -> Result<Self> {
+        let pool = Arc::new(RedisPool::new(config)?);
+        let key_prefix = key_prefix.unwrap_or_else(|| "proxy:cert:".to_string());
+
+        info!("Using Redis certificate cache (prefix: {}, {} connections)", key_prefix, pool.connections.len());
+        // Connect in the background; failures are logged and backed off by the pool
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let pool = Arc::clone(&pool);
+            runtime.spawn(async move {
+                if pool.connect().await.is_ok() {
+                    info!("🚀 Connected to Redis certificate cache");
+                }
+            });
+        }
+
+        Ok(Self {
+            pool,
+            key_prefix,
+            bypass_prefix: "proxy:bypass:".to_string(),
+        })
+    }
+
+    /// The connection pool, for sharing with pub/sub publishers
+    pub fn pool(&self) -> Arc<RedisPool> {
+        Arc::clone(&self.pool)
+    }
+
+    fn make_key(&self, domain: &str) -> String {
+        format!("{}{}", self.key_prefix, domain)
+    }
+
+    fn make_bypass_key(&self, host: &str) -> String {
+        format!("{}{}", self.bypass_prefix, host)
+    }
+
+    /// Deserialize a stored certificate, dropping the entry if it's corrupt
+    async fn decode(&self, domain: &str, key: &str, data: Option<Vec<u8>>) -> Option<CertificateData> {
+        let Some(data) = data else {
+            debug!("Redis cache MISS for domain: {}", domain);
+            return None;
+        };
+        match bincode::deserialize::<CertificateData>(&data) {
+            Ok(cert_data) => {
+                debug!("Redis cache HIT for domain: {}", domain);
+                Some(cert_data)
+            }
+            Err(e) => {
+                warn!("Failed to deserialize cached certificate for {}: {}", domain, e);
+                // Remove corrupted data
+                let _ = self.pool.query::<()>(redis::cmd("DEL").arg(key)).await;
+                None
+            }
+        }
+    }
+
+    async fn delete(&self, keys: &[String]) -> Result<usize> {
+        let mut removed = 0;
+        for chunk in keys.chunks(SCAN_COUNT) {
+            removed += self.pool.query::<usize>(redis::cmd("DEL").arg(chunk)).await?;
+        }
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl CertificateCache for RedisCache {
+    async fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
+        let key = self.make_key(domain);
+        let data = self.pool.query(redis::cmd("GET").arg(&key)).await?;
+        Ok(self.decode(domain, &key, data).await)
+    }
+
+    async fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        let key = self.make_key(domain);
+        let (data, ttl): (Option<Vec<u8>>, i64) = self
+            .pool
+            .query_pipeline(redis::pipe().cmd("GET").arg(&key).cmd("TTL").arg(&key))
+            .await?;
+        // TTL is -1/-2 for keys without expiry or already gone
+        let expires_at = (ttl >= 0).then(|| current_timestamp() + ttl as u64);
+        Ok(self.decode(domain, &key, data).await.map(|cert_data| (cert_data, expires_at)))
+    }
+
+    async fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
+        if ttl_seconds == 0 {
+            return Ok(());
+        }
+        let data = bincode::serialize(&cert_data)
+            .map_err(|e| anyhow!("Failed to serialize certificate data: {}", e))?;
+
+        self.pool
+            .query::<()>(redis::cmd("SET").arg(self.make_key(domain)).arg(data).arg("EX").arg(ttl_seconds))
+            .await?;
+        debug!("Cached certificate in Redis for domain: {} (TTL: {} seconds)", domain, ttl_seconds);
+        Ok(())
+    }
+
+    async fn remove(&self, domain: &str) -> Result<()> {
+        if self.delete(&[self.make_key(domain)]).await? > 0 {
+            debug!("Removed certificate from Redis cache for domain: {}", domain);
+        }
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let keys = self.pool.scan(&format!("{}*", self.key_prefix)).await?;
+        let count = self.delete(&keys).await?;
+        info!("Cleared Redis certificate cache ({} entries removed)", count);
+        Ok(())
+    }
+
+    async fn cache_info(&self) -> String {
+        match self.pool.scan(&format!("{}*", self.key_prefix)).await {
+            Ok(keys) => format!("Redis cache: {} entries (prefix: {})", keys.len(), self.key_prefix),
+            Err(_) => format!("Redis cache: unknown entries (prefix: {})", self.key_prefix),
+        }
+    }
+
+    async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()> {
+        let entry = BypassEntry {
+            host: host.to_string(),
+            reason: reason.to_string(),
+            expires_at: current_timestamp() + ttl_seconds,
+        };
+        let data = serde_json::to_string(&entry)?;
+
+        self.pool
+            .query(redis::cmd("SET").arg(self.make_bypass_key(host)).arg(data).arg("EX").arg(ttl_seconds.max(1)))
+            .await
+    }
+
+    async fn get_bypass(&self, host: &str) -> Result<Option<BypassEntry>> {
+        let data: Option<String> = self.pool.query(redis::cmd("GET").arg(self.make_bypass_key(host))).await?;
+        Ok(data.and_then(|d| serde_json::from_str(&d).ok()))
+    }
+
+    async fn list_bypass(&self) -> Result<Vec<BypassEntry>> {
+        let keys = self.pool.scan(&format!("{}*", self.bypass_prefix)).await?;
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let values: Vec<Option<String>> = self.pool.query(redis::cmd("MGET").arg(&keys)).await?;
+        Ok(values
+            .into_iter()
+            .flatten()
+            .filter_map(|data| serde_json::from_str(&data).ok())
+            .collect())
+    }
+
+    async fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        let keys = match host {
+            Some(host) => vec![self.make_bypass_key(host)],
+            None => self.pool.scan(&format!("{}*", self.bypass_prefix)).await?,
+        };
+        self.delete(&keys).await
+    }
+
+    fn health(&self) -> Option<CacheBackendHealth> {
+        Some(self.pool.health())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unreachable_redis_backs_off_and_fails_fast() {
+        let config = RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            pool_size: 2,
+            connection_timeout: 1,
+            command_timeout: 1,
+        };
+        let pool = RedisPool::new(&config).unwrap();
+        assert!(pool.health().healthy);
+
+        assert!(pool.connect().await.is_err());
+        let health = pool.health();
+        assert!(!health.healthy);
+        assert_eq!(health.consecutive_failures, 1);
+        assert!(health.retry_in_ms.is_some());
+
+        // Within the backoff window nothing is attempted
+        let err = pool.query::<String>(&redis::cmd("PING")).await.unwrap_err();
+        assert!(err.to_string().contains("retrying in"));
+        assert_eq!(pool.health().consecutive_failures, 1);
+    }
+}
@@ -0,0 +1,218 @@
+//! SNI-driven certificate selection for intercepted connections
+//!
+//! One `ServerConfig` is shared by every intercepted connection (two, really:
+//! with and without h2 in its ALPN list); its `InterceptResolver` picks the
+//! forged certificate from the ClientHello SNI.
+//! Clients that send no SNI (typically when connecting to an IP literal) get
+//! a per-host `ServerConfig` keyed on the CONNECT host, built once and cached.
+
+use crate::tls::{alpn_offer, CertificateData, CertificateManager, LeafIssuer, LeafProfile};
+use anyhow::{anyhow, Result};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long a parsed key stays in memory before it's re-read from the cache
+const KEY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Picks forged certificates by host, backed by `CertificateManager`
+pub struct InterceptResolver {
+    cert_manager: Arc<CertificateManager>,
+    issuer: Option<Arc<LeafIssuer>>,
+    keys: Mutex<HashMap<String, (Arc<CertifiedKey>, Instant)>>,
+}
+
+impl InterceptResolver {
+    pub fn new(cert_manager: Arc<CertificateManager>, issuer: Option<Arc<LeafIssuer>>) -> Self {
+        Self {
+            cert_manager,
+            issuer,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Parsed certificate for `host`, if one is loaded and fresh
+    pub fn get(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+        let keys = self.keys.lock().unwrap();
+        keys.get(host)
+            .filter(|(_, loaded)| loaded.elapsed() < KEY_TTL)
+            .map(|(key, _)| Arc::clone(key))
+    }
+
+    /// Serve `cert_data` for `host` from now on
+    pub fn insert(&self, host: &str, cert_data: &CertificateData) -> Result<Arc<CertifiedKey>> {
+        let signing_key = rustls::sign::any_supported_type(&cert_data.key())
+            .map_err(|e| anyhow!("Unusable private key for {}: {}", host, e))?;
+        let chain = match &self.issuer {
+            Some(issuer) => issuer.cert_chain(cert_data.cert()),
+            None => vec![cert_data.cert()],
+        };
+        let key = Arc::new(CertifiedKey::new(chain, signing_key));
+
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, (_, loaded)| loaded.elapsed() < KEY_TTL);
+        keys.insert(host.to_string(), (Arc::clone(&key), Instant::now()));
+        Ok(key)
+    }
+
+    /// Loaded key, else the cached certificate, else a freshly issued one
+    async fn load(&self, host: &str) -> Result<Arc<CertifiedKey>> {
+        if let Some(key) = self.get(host) {
+            return Ok(key);
+        }
+
+        let cached = self.cert_manager.get_certificate(host).await.unwrap_or_else(|e| {
+            warn!("Certificate cache error for {}: {}", host, e);
+            None
+        });
+        let cert_data = match cached {
+            Some(cert_data) => cert_data,
+            None => {
+                let cert_data = self.issue(host)?;
+                if let Err(e) = self.cert_manager.cache_certificate(host, cert_data.clone()).await {
+                    warn!("Failed to cache certificate for {}: {}", host, e);
+                }
+                cert_data
+            }
+        };
+
+        self.insert(host, &cert_data)
+    }
+
+    fn issue(&self, host: &str) -> Result<CertificateData> {
+        let issuer = self.issuer.as_ref().ok_or_else(|| anyhow!("No signing CA loaded"))?;
+        issuer.issue(&LeafProfile::for_host(host))
+    }
+}
+
+impl ResolvesServerCert for InterceptResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello.server_name()?.to_lowercase();
+        if let Some(key) = self.get(&host) {
+            return Some(key);
+        }
+
+        // Certificates are loaded before the handshake starts, so this only
+        // runs when the SNI names an unprepared host. Generating a key here
+        // would block the runtime, so the handshake fails instead
+        warn!("No certificate prepared for SNI {} - failing the handshake", host);
+        None
+    }
+}
+
+/// Always serves one key; used for clients that send no SNI
+struct FixedResolver(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for FixedResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.0))
+    }
+}
+
+/// A per-host config and the key it was built for
+type FixedConfig = (Arc<ServerConfig>, Arc<CertifiedKey>);
+
+/// TLS server configurations for intercepted connections
+pub struct InterceptTls {
+    resolver: Arc<InterceptResolver>,
+    shared: Arc<ServerConfig>,
+    /// `shared` without h2
+    shared_http1: Arc<ServerConfig>,
+    /// Per-host configs for SNI-less clients
+    fixed: Mutex<HashMap<String, FixedConfig>>,
+}
+
+impl InterceptTls {
+    pub fn new(cert_manager: Arc<CertificateManager>, issuer: Option<Arc<LeafIssuer>>) -> Self {
+        let resolver = Arc::new(InterceptResolver::new(cert_manager, issuer));
+        Self {
+            shared: server_config(resolver.clone(), true),
+            shared_http1: server_config(resolver.clone(), false),
+            resolver,
+            fixed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn resolver(&self) -> &InterceptResolver {
+        &self.resolver
+    }
+
+    /// Config for a ClientHello: the shared SNI-resolving one, or a per-host
+    /// one serving `host`'s certificate when the client sent no SNI
+    ///
+    /// `h2` decides whether h2 is offered alongside http/1.1.
+    pub async fn config_for(&self, sni: Option<&str>, host: &str, h2: bool) -> Result<Arc<ServerConfig>> {
+        if sni.is_some() {
+            return Ok(Arc::clone(if h2 { &self.shared } else { &self.shared_http1 }));
+        }
+
+        let config = self.fixed_config(host).await?;
+        if h2 {
+            return Ok(config);
+        }
+        let mut config = (*config).clone();
+        config.alpn_protocols = alpn_offer(false);
+        Ok(Arc::new(config))
+    }
+
+    /// Per-host config (offering h2) for SNI-less clients of `host`
+    async fn fixed_config(&self, host: &str) -> Result<Arc<ServerConfig>> {
+        let key = self.resolver.load(host).await?;
+        let mut fixed = self.fixed.lock().unwrap();
+        if let Some((config, built_for)) = fixed.get(host) {
+            if Arc::ptr_eq(built_for, &key) {
+                return Ok(Arc::clone(config));
+            }
+        }
+
+        debug!("Building TLS config for SNI-less clients of {}", host);
+        let config = server_config(Arc::new(FixedResolver(Arc::clone(&key))), true);
+        // Drop configs whose key the resolver has since replaced or expired
+        fixed.retain(|h, (_, built_for)| self.resolver.get(h).is_some_and(|current| Arc::ptr_eq(&current, built_for)));
+        fixed.insert(host.to_string(), (Arc::clone(&config), key));
+        Ok(config)
+    }
+}
+
+fn server_config(resolver: Arc<dyn ResolvesServerCert>, h2: bool) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = alpn_offer(h2);
+    Arc::new(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::MemoryCache;
+
+    #[tokio::test]
+    async fn test_sni_less_configs_follow_the_loaded_key() {
+        let cert_manager = Arc::new(CertificateManager::with_cache(Box::new(MemoryCache::new(10))));
+        let cert = crate::tls::generate_self_signed_cert("Test", "10.0.0.1", 1).unwrap();
+        cert_manager.cache_certificate("10.0.0.1", cert.clone()).await.unwrap();
+
+        let tls = InterceptTls::new(cert_manager, None);
+        assert!(Arc::ptr_eq(&tls.config_for(Some("example.com"), "10.0.0.1", true).await.unwrap(), &tls.shared));
+        let http1 = tls.config_for(Some("example.com"), "10.0.0.1", false).await.unwrap();
+        assert_eq!(http1.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        // Loaded from the certificate cache, then reused
+        let first = tls.config_for(None, "10.0.0.1", true).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &tls.config_for(None, "10.0.0.1", true).await.unwrap()));
+        assert_eq!(tls.config_for(None, "10.0.0.1", false).await.unwrap().alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        // A replaced certificate gets a new config
+        tls.resolver().insert("10.0.0.1", &cert).unwrap();
+        assert!(!Arc::ptr_eq(&first, &tls.config_for(None, "10.0.0.1", true).await.unwrap()));
+
+        // Nothing cached and no CA to issue with
+        assert!(tls.config_for(None, "10.0.0.2", true).await.is_err());
+    }
+}
@@ -1,8 +1,8 @@
 //! TLS server implementation for HTTPS interception
 
 use crate::config::settings::ProxyConfig;
-use crate::tls::{get_or_generate_certificate, create_server_config, validate_tls_config};
-use crate::proxy::server::handle_request;
+use crate::tls::{get_or_generate_certificate, create_server_config, load_pem_chain, validate_tls_config};
+use crate::proxy::server::{handle_request, ProxyContext};
 use anyhow::{anyhow, Result};
 use hyper::service::service_fn;
 use hyper::{Body, Request, Response};
@@ -16,12 +16,21 @@ use tracing::{info, debug, error, warn};
 /// TLS-enabled proxy server for HTTPS interception
 pub struct TlsProxyServer {
     config: ProxyConfig,
+    context: Arc<ProxyContext>,
 }
 
 impl TlsProxyServer {
     /// Create a new TLS proxy server
-    pub fn new(config: ProxyConfig) -> Self {
-        Self { config }
+    ///
+    /// CONNECTs sent over the TLS listener follow the same interception setting.
+    pub fn new(config: ProxyConfig) -> Result<Self> {
+        let context = Arc::new(ProxyContext::from_config(&config, config.tls.interception_enabled)?);
+        Ok(Self { config, context })
+    }
+
+    /// Create a TLS proxy server sharing another listener's request context
+    pub fn with_context(config: ProxyConfig, context: Arc<ProxyContext>) -> Self {
+        Self { config, context }
     }
 
     /// Start the TLS proxy server with actual TLS termination
@@ -46,8 +55,11 @@ impl TlsProxyServer {
         )?;
 
         // Create TLS server configuration
+        // cert_path may hold a full chain; serve the intermediates after the leaf
+        let mut cert_chain = vec![cert_data.cert()];
+        cert_chain.extend(load_pem_chain(&self.config.tls.cert_path).unwrap_or_default().into_iter().skip(1));
         let server_config = create_server_config(
-            cert_data.cert(),
+            cert_chain,
             cert_data.key(),
             &self.config.tls,
         )?;
@@ -55,7 +67,7 @@ impl TlsProxyServer {
         info!("✅ TLS proxy server configuration completed");
         info!("📜 Certificate: {}", self.config.tls.cert_path);
         info!("🔐 Private key: {}", self.config.tls.key_path);
-        info!("🔒 Interception mode: enabled");
+        info!("🔒 Interception mode: {}", if self.config.tls.interception_enabled { "enabled" } else { "disabled (tunnel)" });
 
         // Create TLS acceptor
         let tls_acceptor = TlsAcceptor::from(server_config);
@@ -67,16 +79,19 @@ impl TlsProxyServer {
         info!("🔒 TLS proxy server listening on https://{}", self.config.tls.https_listen_addr);
         info!("🌐 Ready to intercept HTTPS traffic!");
 
+        // Shared request context for every connection on this listener
+        let context = self.context;
+
         // Accept connections loop
         loop {
             match listener.accept().await {
                 Ok((stream, remote_addr)) => {
                     let acceptor = tls_acceptor.clone();
-                    let config = self.config.clone();
+                    let context = Arc::clone(&context);
                     
                     // Spawn a task to handle each connection
                     tokio::spawn(async move {
-                        if let Err(e) = handle_tls_connection(stream, remote_addr, acceptor, config).await {
+                        if let Err(e) = handle_tls_connection(stream, remote_addr, acceptor, context).await {
                             error!("TLS connection error from {}: {}", remote_addr, e);
                         }
                     });
@@ -94,7 +109,7 @@ async fn handle_tls_connection(
     stream: TcpStream,
     remote_addr: SocketAddr,
     acceptor: TlsAcceptor,
-    config: ProxyConfig,
+    context: Arc<ProxyContext>,
 ) -> Result<()> {
     debug!("🔒 New TLS connection from {}", remote_addr);
 
@@ -112,12 +127,14 @@ async fn handle_tls_connection(
 
     // Create HTTP service for this TLS connection
     let http_service = service_fn(move |req| {
-        handle_tls_request(req, remote_addr, config.clone())
+        handle_tls_request(req, remote_addr, Arc::clone(&context))
     });
     
-    // Serve HTTP over the TLS connection
+    // Serve HTTP over the TLS connection; upgrades keep CONNECT tunnels and
+    // WebSocket relays working on this listener
     if let Err(e) = hyper::server::conn::Http::new()
         .serve_connection(tls_stream, http_service)
+        .with_upgrades()
         .await
     {
         debug!("HTTP over TLS connection ended for {}: {}", remote_addr, e);
@@ -130,7 +147,7 @@ async fn handle_tls_connection(
 async fn handle_tls_request(
     req: Request<Body>,
     remote_addr: SocketAddr,
-    config: ProxyConfig,
+    context: Arc<ProxyContext>,
 ) -> Result<Response<Body>, Infallible> {
     debug!("🔒 Processing decrypted HTTPS request from {}", remote_addr);
 
@@ -138,15 +155,9 @@ async fn handle_tls_request(
     // This gives us full visibility into the request/response
     debug!("🔍 HTTPS interception mode: full request/response logging enabled");
     
-    // Use the same handler as regular HTTP requests
+    // Use the same handler (and middleware chain) as regular HTTP requests
     // This provides complete transparency into HTTPS traffic
-    // Create a dummy certificate manager for TLS-terminated requests (no interception needed)
-    let cert_manager = Arc::new(crate::tls::CertificateManager::new());
-    // Create optimized HTTP client for performance with configuration
-    let client_manager = Arc::new(crate::proxy::http_client::HttpClient::from_config(&config.http_client));
-    // Create smart body handler for streaming optimization with configuration
-    let body_handler = Arc::new(crate::proxy::streaming::SmartBodyHandler::from_config(&config.streaming));
-    handle_request(req, remote_addr, false, cert_manager, client_manager, body_handler, &config.tls).await // Don't enable HTTPS interception here since we're already handling TLS
+    handle_request(req, remote_addr, context).await
 }
 
 /// Start both HTTP and HTTPS servers concurrently
@@ -154,19 +165,19 @@ pub async fn start_dual_servers(config: ProxyConfig) -> Result<()> {
     info!("🚀 Starting dual HTTP/HTTPS proxy servers");
 
     if config.tls.enabled {
-        // Start both HTTP and HTTPS servers
-        let http_config = config.clone();
-        let https_config = config.clone();
+        // Start both HTTP and HTTPS servers on one context, so they share the
+        // certificate cache, intercept TLS config and upstream clients
+        let context = Arc::new(ProxyContext::from_config(&config, config.tls.interception_enabled)?);
+        let server = crate::proxy::server::ProxyServer::with_context(config.listen_addr, &config, Arc::clone(&context));
+        let tls_server = TlsProxyServer::with_context(config.clone(), context);
 
         let http_server = tokio::spawn(async move {
-            let server = crate::proxy::server::ProxyServer::with_https_interception_and_config(http_config.listen_addr, true, &http_config);
             if let Err(e) = server.start().await {
                 error!("HTTP server failed: {}", e);
             }
         });
 
         let https_server = tokio::spawn(async move {
-            let tls_server = TlsProxyServer::new(https_config);
             if let Err(e) = tls_server.start().await {
                 error!("HTTPS server failed: {}", e);
             }
@@ -174,14 +185,14 @@ pub async fn start_dual_servers(config: ProxyConfig) -> Result<()> {
 
         info!("🌐 HTTP proxy: http://{}", config.listen_addr);
         info!("🔒 HTTPS proxy: https://{}", config.tls.https_listen_addr);
-        info!("🔍 HTTPS interception: ENABLED");
+        info!("🔍 HTTPS interception: {}", if config.tls.interception_enabled { "ENABLED" } else { "DISABLED" });
 
         // Wait for both servers
         tokio::try_join!(http_server, https_server)?;
     } else {
         // Start only HTTP server
         info!("🌐 Starting HTTP-only proxy server (TLS disabled)");
-        let server = crate::proxy::server::ProxyServer::new(config.listen_addr);
+        let server = crate::proxy::server::ProxyServer::with_https_interception_and_config(config.listen_addr, config.tls.interception_enabled, &config)?;
         server.start().await?;
     }
 
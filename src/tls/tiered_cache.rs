@@ -0,0 +1,258 @@
+//! Two-tier certificate cache
+//!
+//! Hot certificates are served from an in-process `MemoryCache` (L1) in front
+//! of a shared backend such as Redis (L2), so repeat CONNECTs skip the network
+//! round-trip. With Redis, writes and removals are announced on a pub/sub
+//! channel and every other instance drops its L1 copy of the key.
+
+use crate::tls::{BypassEntry, CacheBackendHealth, CacheStats, CertificateCache, CertificateData, MemoryCache};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::debug;
+
+#[cfg(feature = "redis-support")]
+use {
+    crate::tls::RedisPool,
+    std::sync::Weak,
+    std::time::Duration,
+    tracing::{info, warn},
+};
+
+/// Upper bound on how long an L1 copy lives without hearing from L2
+const L1_TTL_SECS: u64 = 300;
+
+/// Pub/sub channel carrying `<instance> <set|del|clear> [key]` messages
+pub const INVALIDATION_CHANNEL: &str = "proxy:cert:invalidate";
+
+/// Memory L1 in front of a shared L2 certificate cache
+pub struct TieredCache {
+    l1: Arc<MemoryCache>,
+    l2: Box<dyn CertificateCache>,
+    #[cfg(feature = "redis-support")]
+    bus: Option<InvalidationBus>,
+}
+
+#[cfg(feature = "redis-support")]
+struct InvalidationBus {
+    pool: Arc<RedisPool>,
+    instance_id: String,
+}
+
+impl TieredCache {
+    pub fn new(l2: Box<dyn CertificateCache>, l1_entries: usize) -> Self {
+        Self {
+            l1: Arc::new(MemoryCache::new(l1_entries)),
+            l2,
+            #[cfg(feature = "redis-support")]
+            bus: None,
+        }
+    }
+
+    /// Publish invalidations through `pool` and apply other instances' ones
+    #[cfg(feature = "redis-support")]
+    pub fn with_redis_invalidation(mut self, pool: Arc<RedisPool>) -> Result<Self> {
+        let instance_id = format!("{:016x}", rand::random::<u64>());
+
+        // The subscriber blocks on its own connection, so it gets a thread
+        let client = pool.client().clone();
+        let l1 = Arc::downgrade(&self.l1);
+        let id = instance_id.clone();
+        std::thread::Builder::new()
+            .name("cert-cache-invalidation".to_string())
+            .spawn(move || subscribe_invalidations(client, l1, id))?;
+
+        info!("📡 L1 certificate cache invalidation via Redis channel {}", INVALIDATION_CHANNEL);
+        self.bus = Some(InvalidationBus { pool, instance_id });
+        Ok(self)
+    }
+
+    #[cfg(feature = "redis-support")]
+    async fn publish(&self, op: &str, key: &str) {
+        if let Some(bus) = &self.bus {
+            let message = format!("{} {} {}", bus.instance_id, op, key);
+            if let Err(e) = bus.pool.publish(INVALIDATION_CHANNEL, &message).await {
+                warn!("Failed to publish certificate cache invalidation: {}", e);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "redis-support"))]
+    async fn publish(&self, _op: &str, _key: &str) {}
+}
+
+#[async_trait]
+impl CertificateCache for TieredCache {
+    async fn get(&self, domain: &str) -> Result<Option<CertificateData>> {
+        Ok(self.get_with_expiry(domain).await?.map(|(cert_data, _)| cert_data))
+    }
+
+    /// L1 hits report no expiry: their own is just the L1 lifetime. Hot hosts
+    /// come back to L2 every `L1_TTL_SECS`, which is when renewal sees them.
+    async fn get_with_expiry(&self, domain: &str) -> Result<Option<(CertificateData, Option<u64>)>> {
+        if let Some(cert_data) = self.l1.get(domain).await? {
+            return Ok(Some((cert_data, None)));
+        }
+
+        let entry = self.l2.get_with_expiry(domain).await?;
+        if let Some((cert_data, _)) = &entry {
+            debug!("Promoting {} to the L1 certificate cache", domain);
+            self.l1.set(domain, cert_data.clone(), L1_TTL_SECS).await?;
+        }
+        Ok(entry)
+    }
+
+    async fn set(&self, domain: &str, cert_data: CertificateData, ttl_seconds: u64) -> Result<()> {
+        self.l1.set(domain, cert_data.clone(), ttl_seconds.min(L1_TTL_SECS)).await?;
+        let result = self.l2.set(domain, cert_data, ttl_seconds).await;
+        self.publish("set", domain).await;
+        result
+    }
+
+    async fn remove(&self, domain: &str) -> Result<()> {
+        self.l1.remove(domain).await?;
+        let result = self.l2.remove(domain).await;
+        self.publish("del", domain).await;
+        result
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.l1.clear().await?;
+        let result = self.l2.clear().await;
+        self.publish("clear", "").await;
+        result
+    }
+
+    async fn cache_info(&self) -> String {
+        format!("Tiered cache: L1 {}; L2 {}", self.l1.cache_info().await, self.l2.cache_info().await)
+    }
+
+    // Bypass entries are read rarely and must be shared, so they skip L1
+    async fn mark_bypass(&self, host: &str, reason: &str, ttl_seconds: u64) -> Result<()> {
+        self.l2.mark_bypass(host, reason, ttl_seconds).await
+    }
+
+    async fn get_bypass(&self, host: &str) -> Result<Option<BypassEntry>> {
+        self.l2.get_bypass(host).await
+    }
+
+    async fn list_bypass(&self) -> Result<Vec<BypassEntry>> {
+        self.l2.list_bypass().await
+    }
+
+    async fn clear_bypass(&self, host: Option<&str>) -> Result<usize> {
+        self.l2.clear_bypass(host).await
+    }
+
+    fn health(&self) -> Option<CacheBackendHealth> {
+        self.l2.health()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.l1.stats()
+    }
+}
+
+/// Apply one invalidation message to `l1`, ignoring our own
+#[cfg_attr(not(feature = "redis-support"), allow(dead_code))]
+async fn apply_invalidation(l1: &MemoryCache, instance_id: &str, payload: &str) -> Result<()> {
+    let mut parts = payload.splitn(3, ' ');
+    let (Some(sender), Some(op)) = (parts.next(), parts.next()) else {
+        debug!("Ignoring malformed invalidation message: {}", payload);
+        return Ok(());
+    };
+    if sender == instance_id {
+        return Ok(());
+    }
+
+    match (op, parts.next()) {
+        ("set" | "del", Some(key)) => l1.remove(key).await,
+        ("clear", _) => l1.clear().await,
+        _ => {
+            debug!("Ignoring malformed invalidation message: {}", payload);
+            Ok(())
+        }
+    }
+}
+
+/// Listen for invalidations until the cache owning `l1` is dropped
+#[cfg(feature = "redis-support")]
+fn subscribe_invalidations(client: redis::Client, l1: Weak<MemoryCache>, instance_id: String) {
+    let mut backoff = Duration::from_millis(500);
+    while l1.strong_count() > 0 {
+        match listen(&client, &l1, &instance_id) {
+            Ok(()) => return,
+            Err(e) => {
+                warn!("Certificate cache invalidation subscriber lost Redis: {}", e);
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis-support")]
+fn listen(client: &redis::Client, l1: &Weak<MemoryCache>, instance_id: &str) -> redis::RedisResult<()> {
+    let mut conn = client.get_connection()?;
+    // Wake up periodically to notice the cache being dropped
+    conn.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut pubsub = conn.as_pubsub();
+    pubsub.subscribe(INVALIDATION_CHANNEL)?;
+
+    // Anything published while we were disconnected is lost
+    if let Some(l1) = l1.upgrade() {
+        let _ = futures::executor::block_on(l1.clear());
+    }
+
+    loop {
+        let message = match pubsub.get_message() {
+            Ok(message) => message,
+            Err(e) if e.is_timeout() => {
+                if l1.strong_count() == 0 {
+                    return Ok(());
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(l1) = l1.upgrade() else {
+            return Ok(());
+        };
+        let payload: String = message.get_payload()?;
+        // MemoryCache never actually suspends, so this doesn't need a runtime
+        if let Err(e) = futures::executor::block_on(apply_invalidation(&l1, instance_id, &payload)) {
+            warn!("Failed to apply certificate cache invalidation: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::generate_self_signed_cert;
+
+    #[tokio::test]
+    async fn test_l2_hits_populate_l1_and_invalidations_evict() {
+        let cert = generate_self_signed_cert("Test", "example.com", 1).unwrap();
+        let l2 = MemoryCache::new(10);
+        l2.set("warm.com", cert.clone(), 3600).await.unwrap();
+
+        let cache = TieredCache::new(Box::new(l2), 10);
+        assert!(cache.l1.get("warm.com").await.unwrap().is_none());
+        assert!(cache.get("warm.com").await.unwrap().is_some());
+        assert!(cache.l1.get("warm.com").await.unwrap().is_some());
+
+        cache.set("new.com", cert, 3600).await.unwrap();
+        assert!(cache.l1.get("new.com").await.unwrap().is_some());
+        assert!(cache.l2.get("new.com").await.unwrap().is_some());
+
+        // Our own messages are ignored; other instances' evict the key
+        apply_invalidation(&cache.l1, "me", "me del new.com").await.unwrap();
+        assert!(cache.l1.get("new.com").await.unwrap().is_some());
+        apply_invalidation(&cache.l1, "me", "peer set new.com").await.unwrap();
+        assert!(cache.l1.get("new.com").await.unwrap().is_none());
+        apply_invalidation(&cache.l1, "me", "peer clear ").await.unwrap();
+        assert!(cache.l1.get("warm.com").await.unwrap().is_none());
+        assert!(cache.get("warm.com").await.unwrap().is_some());
+    }
+}
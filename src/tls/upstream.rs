@@ -0,0 +1,329 @@
+//! Upstream certificate verification
+//!
+//! Requests forwarded to real servers verify their certificates against the
+//! system roots plus `tls.root_ca_cert_path`, or not at all with
+//! `tls.skip_upstream_cert_verify`. `tls.upstream_trust` overrides that per
+//! host: internal services can be trusted through their own CA, and test
+//! hosts with broken certificates can skip verification without turning it
+//! off everywhere.
+//!
+//! Certificates that fail verification are still accepted for hosts with a
+//! "proceed anyway" exception, either configured in
+//! `tls.upstream_cert_errors.proceed_anyway` or granted at runtime through
+//! `/admin/cert-exceptions`. Every such connection is logged.
+
+use crate::config::settings::{TlsConfig, UpstreamCertErrorConfig, UpstreamTrustRule};
+use crate::tls::{add_custom_root_ca, add_system_root_certificates, load_pem_chain, AcceptAllCertVerifier, HostMatcher};
+use anyhow::{anyhow, Result};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, RootCertStore, ServerName};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, warn};
+
+/// How one group of upstream hosts is verified
+struct Trust {
+    /// Shown in logs when a certificate is rejected
+    description: String,
+    verifier: Arc<dyn ServerCertVerifier>,
+}
+
+/// Verifies upstream certificates with the first `tls.upstream_trust` rule
+/// matching the server name, else with the global settings
+pub struct UpstreamCertVerifier {
+    overrides: Vec<(HostMatcher, Trust)>,
+    default: Trust,
+    exceptions: Arc<CertExceptions>,
+}
+
+impl UpstreamCertVerifier {
+    /// Build from configuration; rules whose CA files can't be loaded are
+    /// logged and skipped, leaving those hosts on the global settings
+    pub fn from_config(tls: &TlsConfig, exceptions: Arc<CertExceptions>) -> Result<Self> {
+        let default = if tls.skip_upstream_cert_verify {
+            Trust {
+                description: "verification disabled".to_string(),
+                verifier: Arc::new(AcceptAllCertVerifier),
+            }
+        } else {
+            Trust {
+                description: "system roots".to_string(),
+                verifier: webpki_verifier(global_roots(tls)?),
+            }
+        };
+
+        let mut overrides = Vec::new();
+        for rule in &tls.upstream_trust {
+            match rule_trust(rule, tls) {
+                Ok(trust) => overrides.push((HostMatcher::new(&rule.hosts), trust)),
+                Err(e) => error!("❌ Ignoring upstream_trust rule for {:?}: {}", rule.hosts, e),
+            }
+        }
+
+        Ok(Self { overrides, default, exceptions })
+    }
+
+    fn trust_for(&self, host: &str) -> &Trust {
+        self.overrides
+            .iter()
+            .find(|(hosts, _)| hosts.matching_rule(host).is_some())
+            .map(|(_, trust)| trust)
+            .unwrap_or(&self.default)
+    }
+}
+
+impl ServerCertVerifier for UpstreamCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+
+        let trust = self.trust_for(&host);
+        debug!("Verifying upstream certificate for {} ({})", host, trust.description);
+        let result = trust.verifier.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now);
+        if let Err(e) = &result {
+            if let (rustls::Error::InvalidCertificate(_), Some(why)) = (e, self.exceptions.allows(&host)) {
+                warn!("⚠️  Proceeding past invalid upstream certificate for {} ({}): {}", host, why, e);
+                return Ok(ServerCertVerified::assertion());
+            }
+            warn!("🔒 Rejected upstream certificate for {} ({}): {}", host, trust.description, e);
+        }
+        result
+    }
+}
+
+/// A host whose invalid upstream certificate is accepted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CertException {
+    /// Host, or host pattern for configured exceptions
+    pub host: String,
+    /// `config` or `admin`
+    pub source: &'static str,
+    /// Unix time the exception lapses; configured ones don't
+    pub expires_at: Option<u64>,
+}
+
+/// Longest exception that can be granted or configured
+pub const MAX_EXCEPTION_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// "Proceed anyway" exceptions for upstream certificates that fail verification
+pub struct CertExceptions {
+    configured: HostMatcher,
+    patterns: Vec<String>,
+    ttl: Duration,
+    granted: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl Default for CertExceptions {
+    fn default() -> Self {
+        Self::from_config(&UpstreamCertErrorConfig::default())
+    }
+}
+
+impl CertExceptions {
+    pub fn from_config(config: &UpstreamCertErrorConfig) -> Self {
+        Self {
+            configured: HostMatcher::new(&config.proceed_anyway),
+            patterns: config.proceed_anyway.clone(),
+            ttl: Duration::from_secs(config.exception_ttl_secs).min(MAX_EXCEPTION_TTL),
+            granted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Why invalid certificates from `host` are accepted, if they are
+    pub fn allows(&self, host: &str) -> Option<String> {
+        if let Some(pattern) = self.configured.matching_rule(host) {
+            return Some(format!("proceed_anyway rule '{}'", pattern));
+        }
+        let granted = self.granted.lock().unwrap();
+        granted
+            .get(&host.to_lowercase())
+            .filter(|expires| **expires > SystemTime::now())
+            .map(|_| "exception granted by an administrator".to_string())
+    }
+
+    /// Accept invalid certificates from `host` for `ttl` (default
+    /// `exception_ttl_secs`), returning when the exception lapses
+    ///
+    /// Returns `None`, granting nothing, when `ttl` exceeds `MAX_EXCEPTION_TTL`.
+    pub fn grant(&self, host: &str, ttl: Option<Duration>) -> Option<SystemTime> {
+        let ttl = ttl.unwrap_or(self.ttl);
+        if ttl > MAX_EXCEPTION_TTL {
+            return None;
+        }
+        let expires = SystemTime::now().checked_add(ttl)?;
+        let mut granted = self.granted.lock().unwrap();
+        granted.retain(|_, expires| *expires > SystemTime::now());
+        granted.insert(host.to_lowercase(), expires);
+        Some(expires)
+    }
+
+    /// Withdraw the exception for `host`, or every granted one; returns how many were removed
+    pub fn revoke(&self, host: Option<&str>) -> usize {
+        let mut granted = self.granted.lock().unwrap();
+        match host {
+            Some(host) => granted.remove(&host.to_lowercase()).map_or(0, |_| 1),
+            None => {
+                let removed = granted.len();
+                granted.clear();
+                removed
+            }
+        }
+    }
+
+    /// Configured patterns followed by live granted exceptions
+    pub fn list(&self) -> Vec<CertException> {
+        let now = SystemTime::now();
+        let granted = self.granted.lock().unwrap();
+        let mut granted: Vec<CertException> = granted
+            .iter()
+            .filter(|(_, expires)| **expires > now)
+            .map(|(host, expires)| CertException {
+                host: host.clone(),
+                source: "admin",
+                expires_at: expires.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+            })
+            .collect();
+        granted.sort_by(|a, b| a.host.cmp(&b.host));
+
+        self.patterns
+            .iter()
+            .map(|pattern| CertException { host: pattern.clone(), source: "config", expires_at: None })
+            .chain(granted)
+            .collect()
+    }
+}
+
+fn rule_trust(rule: &UpstreamTrustRule, tls: &TlsConfig) -> Result<Trust> {
+    if rule.skip_verify {
+        return Ok(Trust {
+            description: "verification disabled for host".to_string(),
+            verifier: Arc::new(AcceptAllCertVerifier),
+        });
+    }
+
+    let mut roots = if rule.system_roots { global_roots(tls)? } else { RootCertStore::empty() };
+    for path in &rule.ca_cert_paths {
+        for cert in load_pem_chain(path)? {
+            roots.add(&cert).map_err(|e| anyhow!("Unusable CA certificate in {}: {:?}", path, e))?;
+        }
+    }
+    if roots.is_empty() {
+        return Err(anyhow!("no trust anchors (set ca_cert_paths or system_roots)"));
+    }
+
+    let mut sources = rule.ca_cert_paths.clone();
+    if rule.system_roots {
+        sources.insert(0, "system roots".to_string());
+    }
+    Ok(Trust {
+        description: sources.join(" + "),
+        verifier: webpki_verifier(roots),
+    })
+}
+
+/// System roots plus `root_ca_cert_path`
+fn global_roots(tls: &TlsConfig) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    add_system_root_certificates(&mut roots)?;
+    if let Some(root_ca_path) = &tls.root_ca_cert_path {
+        if let Err(e) = add_custom_root_ca(&mut roots, root_ca_path) {
+            warn!("Failed to load custom root CA: {}", e);
+        }
+    }
+    Ok(roots)
+}
+
+fn webpki_verifier(roots: RootCertStore) -> Arc<dyn ServerCertVerifier> {
+    Arc::new(WebPkiVerifier::new(roots, None))
+}
+
+/// The TLS error behind a failed upstream request, if it failed in the handshake
+pub fn upstream_tls_error<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a rustls::Error> {
+    // `io::Error::source` skips the error it wraps, so unwrap those by hand
+    let next = |e: &&'a (dyn std::error::Error + 'static)| match e.downcast_ref::<std::io::Error>().and_then(|io| io.get_ref()) {
+        Some(inner) => Some(inner as &(dyn std::error::Error + 'static)),
+        None => e.source(),
+    };
+    std::iter::successors(Some(err), next).find_map(|e| e.downcast_ref::<rustls::Error>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::disk_cache::pem_block;
+    use crate::tls::{generate_root_ca, CaOptions, LeafIssuer, LeafProfile};
+
+    #[test]
+    fn test_per_host_trust_overrides() {
+        let ca = generate_root_ca(&CaOptions::default()).unwrap();
+        let issuer = LeafIssuer::new(&ca, Default::default(), 30).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let ca_path = dir.path().join("internal-ca.crt");
+        std::fs::write(&ca_path, pem_block("CERTIFICATE", &ca.cert().0)).unwrap();
+
+        let tls = TlsConfig {
+            root_ca_cert_path: None,
+            upstream_trust: vec![
+                UpstreamTrustRule {
+                    hosts: vec!["*.internal.test".to_string()],
+                    ca_cert_paths: vec![ca_path.to_string_lossy().to_string()],
+                    system_roots: false,
+                    ..Default::default()
+                },
+                UpstreamTrustRule {
+                    hosts: vec!["broken.test".to_string()],
+                    skip_verify: true,
+                    ..Default::default()
+                },
+                UpstreamTrustRule {
+                    hosts: vec!["missing.test".to_string()],
+                    ca_cert_paths: vec!["/nonexistent/ca.crt".to_string()],
+                    ..Default::default()
+                },
+            ],
+            ..TlsConfig::default()
+        };
+        let exceptions = Arc::new(CertExceptions::default());
+        let verifier = UpstreamCertVerifier::from_config(&tls, exceptions.clone()).unwrap();
+        assert_eq!(verifier.overrides.len(), 2);
+
+        let verify = |host: &str| {
+            let leaf = issuer.issue(&LeafProfile::for_host(host)).unwrap().cert();
+            let name = ServerName::try_from(host).unwrap();
+            verifier.verify_server_cert(&leaf, &[], &name, &mut std::iter::empty(), &[], SystemTime::now())
+        };
+
+        assert!(verify("api.internal.test").is_ok());
+        assert!(verify("broken.test").is_ok());
+        // Everything else only trusts the system roots
+        let err = verify("other.test").unwrap_err();
+        assert!(matches!(err, rustls::Error::InvalidCertificate(_)));
+
+        // As hyper-rustls reports it: rustls inside tokio-rustls' io::Error inside its own
+        let io = std::io::Error::new(std::io::ErrorKind::InvalidData, err.clone());
+        let io = std::io::Error::other(io);
+        assert_eq!(upstream_tls_error(&io), Some(&err));
+
+        // Proceeding anyway until the exception is withdrawn
+        assert!(exceptions.grant("other.test", Some(Duration::from_secs(u64::MAX))).is_none());
+        assert!(verify("other.test").is_err());
+        assert!(exceptions.grant("Other.test", None).is_some());
+        assert!(verify("other.test").is_ok());
+        assert_eq!(exceptions.list()[0].host, "other.test");
+        assert_eq!(exceptions.revoke(Some("other.test")), 1);
+        assert!(verify("other.test").is_err());
+    }
+}
@@ -1,9 +1,10 @@
 //! HTTP utility functions
 
-use hyper::{HeaderMap, header::{HeaderName, HeaderValue}, Body, Request, Response, StatusCode};
+use hyper::{HeaderMap, header::{HeaderName, HeaderValue}, Body, Request, Response, StatusCode, Version};
 use std::collections::HashMap;
 use tracing::debug;
 use anyhow::Result;
+use bytes::Bytes;
 use crate::models::RequestData;
 use form_urlencoded;
 
@@ -14,6 +15,7 @@ pub fn is_hop_by_hop_header(name: &str) -> bool {
         "keep-alive",
         "proxy-authenticate",
         "proxy-authorization",
+        "proxy-connection",
         "te",
         "trailers",
         "transfer-encoding",
@@ -23,32 +25,31 @@ pub fn is_hop_by_hop_header(name: &str) -> bool {
     hop_by_hop_headers.contains(&name.to_lowercase().as_str())
 }
 
+/// Name of an HTTP version as recorded in transactions
+pub fn protocol_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
 /// Check if a request header should be forwarded to upstream server
-/// This function consolidates all header filtering logic for requests
+///
+/// Only covers headers the proxy manages itself; everything else (such as
+/// browser hint stripping) is handled by the configurable `header_rules`.
 pub fn should_forward_request_header(name: &str) -> bool {
     let name_lower = name.to_lowercase();
     
-    // Skip hop-by-hop headers and problematic headers that might cause 400 errors
+    // Skip hop-by-hop headers and headers the proxy sets explicitly
     !is_hop_by_hop_header(&name_lower) 
         && name_lower != "host"                   // Will be set explicitly by proxy
         && name_lower != "content-length"         // Will be set explicitly by proxy to avoid duplicates
         && name_lower != "x-forwarded-for"        // Proxy will handle forwarding headers
         && name_lower != "x-forwarded-proto"      // Proxy will handle forwarding headers
         && name_lower != "x-real-ip"              // Proxy will handle real IP
-        && name_lower != "rtt"                    // Network timing hint
-        && name_lower != "downlink"               // Network speed hint  
-        && name_lower != "priority"               // Browser priority hint
-        && name_lower != "ect"                    // Effective connection type hint
-        && !name_lower.starts_with("x-browser-") // Chrome browser headers
-        && !name_lower.starts_with("sec-ch-ua")  // Chrome Client Hints
-        && !name_lower.starts_with("sec-ch-prefers") // Chrome preference hints
-        && name_lower != "x-client-data"          // Chrome telemetry data
-        && name_lower != "sec-fetch-dest"         // Browser security hint
-        && name_lower != "sec-fetch-mode"         // Browser security hint
-        && name_lower != "sec-fetch-site"         // Browser security hint
-        && name_lower != "sec-fetch-user"         // Browser security hint
-        && name_lower != "upgrade-insecure-requests" // Browser security hint
-        && name_lower != "sec-fetch-storage-access" // Browser security hint
 }
 
 /// Check if a response header should be forwarded to client
@@ -172,26 +173,29 @@ pub fn build_response_with_headers(
 }
 
 /// Extract headers from request and populate RequestData
+///
+/// Repeated headers are combined into one value; HTTP/2 clients send each
+/// cookie as its own `cookie` field, which are joined with `; ` again.
 pub fn extract_headers(req_headers: &HeaderMap, request_data: &mut RequestData) {
     let mut header_count = 0;
-    for (name, value) in req_headers {
-        if let Ok(value_str) = value.to_str() {
-            request_data
-                .headers
-                .insert(name.to_string().to_lowercase(), value_str.to_string());
-            header_count += 1;
+    for name in req_headers.keys() {
+        let values: Vec<&str> = req_headers.get_all(name).iter().filter_map(|value| value.to_str().ok()).collect();
+        if values.is_empty() {
+            continue;
         }
+        let separator = if name == "cookie" { "; " } else { ", " };
+        request_data.headers.insert(name.to_string(), values.join(separator));
+        header_count += 1;
     }
     debug!("Extracted {} headers from request", header_count);
 }
 
 /// Extract cookies from request and populate RequestData
 pub fn extract_cookies_to_request_data(req_headers: &HeaderMap, request_data: &mut RequestData) {
-    if let Some(cookie_header) = req_headers.get("cookie") {
-        if let Ok(cookie_str) = cookie_header.to_str() {
-            request_data.cookies = parse_cookies(cookie_str);
-            debug!("Extracted {} cookies from request", request_data.cookies.len());
-        }
+    let cookie_headers: Vec<&str> = req_headers.get_all("cookie").iter().filter_map(|value| value.to_str().ok()).collect();
+    if !cookie_headers.is_empty() {
+        request_data.cookies = parse_cookies(&cookie_headers.join("; "));
+        debug!("Extracted {} cookies from request", request_data.cookies.len());
     } else {
         debug!("No cookies found in request");
     }
@@ -238,7 +242,7 @@ pub async fn extract_body(body: Body, request_data: &mut RequestData) {
 }
 
 /// Build forwarding request with proper headers
-pub fn build_forwarding_request(request_data: &RequestData) -> Result<Request<Body>> {
+pub fn build_forwarding_request(request_data: &RequestData) -> Result<Request<Bytes>> {
     let mut request_builder = Request::builder()
         .method(request_data.method.as_str())
         .uri(&request_data.url);
@@ -258,7 +262,7 @@ pub fn build_forwarding_request(request_data: &RequestData) -> Result<Request<Bo
                forwarded_headers, skipped_headers);
 
     // Build the request
-    let request = request_builder.body(Body::from(request_data.body.clone()))?;
+    let request = request_builder.body(Bytes::from(request_data.body.clone()))?;
     debug!("Forward request built, body size: {} bytes", request_data.body.len());
     
     Ok(request)
@@ -3,6 +3,7 @@
 use crate::models::{ProxyLog, RequestData, ResponseData};
 use crate::{log_debug, log_proxy_transaction};
 use hyper::StatusCode;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use tracing::info;
 
@@ -23,12 +24,14 @@ pub fn log_incoming_request(method: &str, uri: &str, remote_addr: &SocketAddr) {
 }
 
 /// Log CONNECT request details
-pub fn log_connect_request(uri: &str) {
+pub fn log_connect_request(uri: &str, intercept: bool) {
+    let mode = if intercept { "intercept" } else { "tunnel" };
+    
     // CONNECT requests at DEBUG level
-    log_debug!("🔐 CONNECT to {} (will intercept)", uri);
+    log_debug!("🔐 CONNECT to {} (will {})", uri, mode);
     
     // Verbose DEBUG log for CONNECT details
-    log_debug!("🔐 CONNECT REQUEST:\n  Target: {}\n  Will intercept HTTPS traffic for full visibility", uri);
+    log_debug!("🔐 CONNECT REQUEST:\n  Target: {}\n  Mode: {}", uri, mode);
 }
 
 /// Log successful CONNECT tunnel establishment
@@ -55,12 +58,15 @@ pub fn log_connect_failure(host: &str, port: u16, connect_time: u128, error: &st
 pub fn create_connect_transaction(
     request_data: &RequestData, 
     response_data: Option<ResponseData>, 
-    error: Option<String>
+    error: Option<String>,
+    annotations: HashMap<String, String>,
 ) -> ProxyLog {
     let log_entry = ProxyLog {
         request: request_data.clone(),
         response: response_data,
         error,
+        annotations,
+        events: Vec::new(),
     };
     
     // DEBUG: Log full transaction details